@@ -0,0 +1,170 @@
+use super::{Error, Session};
+
+use std::fmt;
+
+use tempfile::TempDir;
+use tokio::sync::OnceCell;
+
+/// A backend running the commands of a [`Session`] through the ssh
+/// multiplex master, see the crate-level documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Backend {
+    /// Spawns an `ssh` process for each command, with the `process-mux`
+    /// feature.
+    ProcessMux,
+
+    /// Speaks the multiplex protocol with the master directly, with the
+    /// `native-mux` feature.
+    NativeMux,
+}
+
+impl Backend {
+    /// Return `true` if the backend is compiled in.
+    pub fn is_compiled(self) -> bool {
+        match self {
+            Backend::ProcessMux => cfg!(feature = "process-mux"),
+            Backend::NativeMux => cfg!(feature = "native-mux"),
+        }
+    }
+
+    /// Return `true` if the backend is compiled in and can connect from
+    /// this host, i.e. a local `ssh` can be executed.
+    ///
+    /// The local `ssh` is only looked for once, blocking the current thread
+    /// while it runs if no session was connected yet.
+    pub fn is_available(self) -> bool {
+        self.is_compiled() && ssh_version_blocking().is_some()
+    }
+
+    /// Fail with [`Error::BackendUnavailable`] unless the backend is
+    /// [available](Self::is_available), without blocking.
+    pub(crate) async fn ensure_available(self) -> Result<(), Error> {
+        if self.is_compiled() && ssh_version().await.is_some() {
+            Ok(())
+        } else {
+            Err(Error::BackendUnavailable(self))
+        }
+    }
+
+    /// Return the constructor of the sessions of this backend, once it is
+    /// known to be available.
+    pub(crate) async fn constructor(self) -> Result<fn(TempDir) -> Session, Error> {
+        self.ensure_available().await?;
+
+        match self {
+            #[cfg(feature = "process-mux")]
+            Backend::ProcessMux => Ok(Session::new_process_mux),
+
+            #[cfg(feature = "native-mux")]
+            Backend::NativeMux => Ok(Session::new_native_mux),
+
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("unavailable backends are rejected"),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::ProcessMux => "process-mux",
+            Backend::NativeMux => "native-mux",
+        })
+    }
+}
+
+/// Return the backends which are compiled in and can connect from this
+/// host, see [`Backend::is_available`], so that prebuilt binaries can pick
+/// one at runtime, or report why none can be used.
+pub fn available_backends() -> Vec<Backend> {
+    [Backend::ProcessMux, Backend::NativeMux]
+        .into_iter()
+        .filter(|backend| backend.is_available())
+        .collect()
+}
+
+/// Parse the version of the local ssh, e.g. `(9, 6)` from the banner
+/// `OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024` printed by
+/// `ssh -V`.
+fn parse_version(banner: &str) -> Option<(u32, u32)> {
+    let version = banner.trim_start().strip_prefix("OpenSSH_")?;
+    let (major, rest) = version.split_once('.')?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    Some((major.parse().ok()?, rest[..end].parse().ok()?))
+}
+
+/// The version of the local ssh, once looked for.
+static SSH_VERSION: OnceCell<Option<(u32, u32)>> = OnceCell::const_new();
+
+/// The version of the local ssh, or `None` if it cannot be executed, or is
+/// not OpenSSH.
+pub(crate) async fn ssh_version() -> Option<(u32, u32)> {
+    *SSH_VERSION
+        .get_or_init(|| async {
+            let output = tokio::process::Command::new("ssh")
+                .arg("-V")
+                .output()
+                .await
+                .ok()?;
+            parse_version(&String::from_utf8_lossy(&output.stderr))
+        })
+        .await
+}
+
+/// Same as [`ssh_version`], for the synchronous functions of the public
+/// API.
+fn ssh_version_blocking() -> Option<(u32, u32)> {
+    if let Some(version) = SSH_VERSION.get() {
+        return *version;
+    }
+
+    let version = std::process::Command::new("ssh")
+        .arg("-V")
+        .output()
+        .ok()
+        .and_then(|output| parse_version(&String::from_utf8_lossy(&output.stderr)));
+    // Another thread may have looked for it in the meantime.
+    let _ = SSH_VERSION.set(version);
+    version
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{available_backends, parse_version, ssh_version_blocking, Backend};
+
+    #[test]
+    fn available() {
+        assert_eq!(
+            Backend::ProcessMux.is_compiled(),
+            cfg!(feature = "process-mux")
+        );
+        assert_eq!(
+            Backend::NativeMux.is_compiled(),
+            cfg!(feature = "native-mux")
+        );
+
+        let available = available_backends();
+        assert!(available.iter().all(|backend| backend.is_compiled()));
+        if ssh_version_blocking().is_none() {
+            assert_eq!(available, []);
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(
+            parse_version("OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024\n"),
+            Some((9, 6))
+        );
+        assert_eq!(
+            parse_version("OpenSSH_10.0p2, LibreSSL 3.3.6"),
+            Some((10, 0))
+        );
+        assert_eq!(parse_version("OpenSSH_8.9"), Some((8, 9)));
+        assert_eq!(parse_version("ssh: command not found"), None);
+    }
+}
@@ -0,0 +1,110 @@
+use super::{Error, Session};
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Output;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+type OutputFuture<'s> = Pin<Box<dyn Future<Output = Result<Output, Error>> + Send + 's>>;
+
+/// Run `program` with `args` on each of `sessions` concurrently, with at
+/// most `limit` of them running at once, and collect their output as
+/// with [`OwningCommand::output`](crate::OwningCommand::output).
+///
+/// The command is built once with the same escaping as
+/// [`Session::command`], so every host runs the exact same argv.
+///
+/// The returned [`Stream`] yields the index of each session in `sessions`
+/// along with its result, in the order they complete.
+pub fn broadcast<'s, I, A>(
+    sessions: &'s [Session],
+    program: &str,
+    args: I,
+    limit: usize,
+) -> Broadcast<'s>
+where
+    I: IntoIterator<Item = A>,
+    A: AsRef<str>,
+{
+    let mut argv = vec![program.to_owned()];
+    argv.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+
+    Broadcast {
+        sessions,
+        argv: argv.into(),
+        limit: limit.max(1),
+        next: 0,
+        in_flight: Vec::new(),
+    }
+}
+
+/// [`Stream`] of the results of [`broadcast`].
+pub struct Broadcast<'s> {
+    sessions: &'s [Session],
+    argv: Arc<[String]>,
+    limit: usize,
+    /// Index of the next session to run the command on.
+    next: usize,
+    in_flight: Vec<(usize, OutputFuture<'s>)>,
+}
+
+impl fmt::Debug for Broadcast<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Broadcast")
+            .field("argv", &self.argv)
+            .field("limit", &self.limit)
+            .field("next", &self.next)
+            .field("in_flight", &self.in_flight.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s> Broadcast<'s> {
+    fn start(&mut self, index: usize) {
+        let session = &self.sessions[index];
+        let argv = self.argv.clone();
+
+        let fut = async move {
+            let mut cmd = session.command(&argv[0]);
+            cmd.args(&argv[1..]);
+            cmd.output().await
+        };
+        self.in_flight.push((index, Box::pin(fut)));
+    }
+}
+
+impl Stream for Broadcast<'_> {
+    type Item = (usize, Result<Output, Error>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while this.in_flight.len() < this.limit && this.next < this.sessions.len() {
+            this.start(this.next);
+            this.next += 1;
+        }
+
+        if this.in_flight.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        // Every future registers the waker, so all of them are polled again
+        // once any of them makes progress.
+        for i in 0..this.in_flight.len() {
+            if let Poll::Ready(res) = this.in_flight[i].1.as_mut().poll(cx) {
+                let (index, _) = this.in_flight.swap_remove(i);
+                return Poll::Ready(Some((index, res)));
+            }
+        }
+        Poll::Pending
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.in_flight.len() + self.sessions.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::sync::oneshot;
+
+/// Priority of a command waiting for a slot of a [`ConcurrencyBudget`],
+/// set with [`OwningCommand::priority`](crate::OwningCommand::priority).
+///
+/// Once a slot is released, it is given to the command of the highest
+/// priority which has been waiting the longest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Priority {
+    /// Bulk work, only spawned once no other command is waiting.
+    Low,
+
+    /// The default.
+    #[default]
+    Normal,
+
+    /// Health checks and other commands that must not be starved by bulk
+    /// work, spawned before any other waiting command.
+    High,
+}
+
+impl Priority {
+    fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    /// Commands waiting for a slot, by [`Priority::index`].
+    waiters: [VecDeque<oneshot::Sender<()>>; 3],
+}
+
+/// A limit on the number of remote commands running simultaneously, shared
+/// by all the [`Session`](crate::Session)s it is attached to.
+///
+/// Attach it with [`SessionBuilder::concurrency_budget`] or
+/// [`Session::set_concurrency_budget`]. Spawning a command then waits
+/// until a slot is free, which is released once the [`Child`] exits or is
+/// dropped. This protects both the local fd budget and the remote
+/// infrastructure when orchestrating many hosts.
+///
+/// Waiting commands get the slots by [`Priority`], then in order.
+///
+/// Cloning it returns a handle to the same budget.
+///
+/// [`SessionBuilder::concurrency_budget`]: crate::SessionBuilder::concurrency_budget
+/// [`Session::set_concurrency_budget`]: crate::Session::set_concurrency_budget
+/// [`Child`]: crate::Child
+#[derive(Debug, Clone)]
+pub struct ConcurrencyBudget {
+    state: Arc<Mutex<State>>,
+    limit: usize,
+}
+
+/// A slot of a [`ConcurrencyBudget`], released on drop.
+#[derive(Debug)]
+pub(crate) struct BudgetPermit(ConcurrencyBudget);
+
+impl Drop for BudgetPermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Gives the slot back if the command stops waiting right after being
+/// handed one.
+struct Waiter<'a> {
+    budget: &'a ConcurrencyBudget,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.budget.release();
+        }
+    }
+}
+
+impl ConcurrencyBudget {
+    /// Create a budget allowing up to `limit` commands to run simultaneously.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero, since no command could ever run.
+    pub fn new(limit: usize) -> Self {
+        assert!(limit > 0, "concurrency budget limit must be non-zero");
+        Self {
+            state: Arc::new(Mutex::new(State {
+                available: limit,
+                waiters: Default::default(),
+            })),
+            limit,
+        }
+    }
+
+    /// Return the maximum number of commands that can run simultaneously.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Return the number of commands that can currently be spawned without
+    /// waiting.
+    pub fn available(&self) -> usize {
+        self.state().available
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) async fn acquire(&self, priority: Priority) -> BudgetPermit {
+        let receiver = {
+            let mut state = self.state();
+            // Slots are handed over to waiters directly, so none is
+            // available while some are waiting.
+            if state.available > 0 {
+                state.available -= 1;
+                return BudgetPermit(self.clone());
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.waiters[priority.index()].push_back(sender);
+            receiver
+        };
+
+        let mut waiter = Waiter {
+            budget: self,
+            receiver,
+        };
+        (&mut waiter.receiver)
+            .await
+            .expect("slots are sent before the senders are dropped");
+        BudgetPermit(self.clone())
+    }
+
+    fn release(&self) {
+        let mut state = self.state();
+        for waiters in state.waiters.iter_mut() {
+            while let Some(waiter) = waiters.pop_front() {
+                // Skip the commands which stopped waiting.
+                if waiter.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConcurrencyBudget, Priority};
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::time::{sleep, timeout};
+
+    #[tokio::test]
+    async fn priority() {
+        let budget = ConcurrencyBudget::new(1);
+        let permit = budget.acquire(Priority::Normal).await;
+        assert_eq!(budget.available(), 0);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let budget = budget.clone();
+            let order = order.clone();
+            waiters.push(tokio::spawn(async move {
+                let _permit = budget.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+            }));
+            // Queue them in order.
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(permit);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+        assert_eq!(budget.available(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter() {
+        let budget = ConcurrencyBudget::new(1);
+        let permit = budget.acquire(Priority::Normal).await;
+
+        timeout(Duration::from_millis(10), budget.acquire(Priority::High))
+            .await
+            .unwrap_err();
+        drop(permit);
+        assert_eq!(budget.available(), 1);
+
+        let _permit = budget.acquire(Priority::Low).await;
+        assert_eq!(budget.available(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "concurrency budget limit must be non-zero")]
+    fn zero_limit() {
+        ConcurrencyBudget::new(0);
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Mutex;
+
+/// A pool of buffers that output of remote commands can be read into,
+/// so that high-throughput applications can recycle large buffers instead
+/// of allocating fresh ones for every command.
+///
+/// Used by [`Child::wait_with_output_in`](crate::Child::wait_with_output_in)
+/// and [`OwningCommand::output_in`](crate::OwningCommand::output_in).
+///
+/// A basic implementation is provided for `Mutex<Vec<Vec<u8>>>`.
+pub trait BufferPool {
+    /// Take a buffer out of the pool, or allocate a new one if the pool
+    /// is empty.
+    ///
+    /// The returned buffer may contain data, which is cleared before use.
+    fn get(&self) -> Vec<u8>;
+
+    /// Return a buffer to the pool once the caller is done with it.
+    fn put(&self, buf: Vec<u8>);
+}
+
+impl<P: BufferPool + ?Sized> BufferPool for &P {
+    fn get(&self) -> Vec<u8> {
+        (**self).get()
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        (**self).put(buf)
+    }
+}
+
+impl BufferPool for Mutex<Vec<Vec<u8>>> {
+    fn get(&self) -> Vec<u8> {
+        self.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop()
+            .unwrap_or_default()
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        // Buffers without allocation are not worth keeping.
+        if buf.capacity() != 0 {
+            self.lock().unwrap_or_else(|err| err.into_inner()).push(buf);
+        }
+    }
+}
@@ -1,17 +1,28 @@
-use super::{Error, Session};
+use super::escape;
+use super::jump_host::{proxy_command, proxy_jump};
+use super::master_log::strip_debug_lines;
+use super::metrics_sink::Metrics;
+use super::trace::{Op, Target};
+use super::{Backend, ConcurrencyBudget, ConfigWriter, Error, JumpHost, MetricsSink, Session};
 
 use std::borrow::Cow;
-use std::ffi::OsString;
+use std::collections::hash_map::RandomState;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::iter::IntoIterator;
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{fs, io};
 
 use once_cell::sync::OnceCell;
 use tempfile::{Builder, TempDir};
-use tokio::process;
+use tokio::{process, time};
 
 #[cfg(not(windows))]
 fn state_dir() -> Option<PathBuf> {
@@ -56,6 +67,43 @@ fn get_default_control_dir<'a>() -> Result<&'a Path, Error> {
         })
 }
 
+/// Random delay before retrying a connection throttled by the server,
+/// in `[0, 100ms * 2^attempt)`.
+fn throttled_backoff(attempt: u32) -> Duration {
+    let max = 100u64 << attempt.min(10);
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % max)
+}
+
+/// Set the priority of the current process, meant to be called after fork.
+fn set_priority(nice: i32, ionice: Option<IoPriority>) -> io::Result<()> {
+    // The type of `PRIO_PROCESS` does not always match the parameter.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(ionice) = ionice {
+        const IOPRIO_WHO_PROCESS: i32 = 1;
+        const IOPRIO_CLASS_SHIFT: i32 = 13;
+
+        let (class, data) = match ionice {
+            IoPriority::BestEffort(level) => (2, level.min(7)),
+            IoPriority::Idle => (3, 0),
+        };
+        let ioprio: i32 = (class << IOPRIO_CLASS_SHIFT) | i32::from(data);
+
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0i32, ioprio) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = ionice;
+
+    Ok(())
+}
+
 fn clean_history_control_dir(socketdir: &Path, prefix: &str) -> io::Result<()> {
     // Read the entries in the parent directory
     fs::read_dir(socketdir)?
@@ -76,47 +124,155 @@ fn clean_history_control_dir(socketdir: &Path, prefix: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// Maximum length of [`SessionBuilder::control_socket_label`].
+const MAX_LABEL_LEN: usize = 32;
+
+fn sanitize_label(label: &str) -> String {
+    label
+        .chars()
+        .take(MAX_LABEL_LEN)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 /// Build a [`Session`] with options.
+///
+/// With the `env-config` feature enabled, [`SessionBuilder::default`] picks
+/// up the following environment variables, so that operators can tune
+/// deployed binaries without code changes:
+///
+///  - `OPENSSH_RS_CONNECT_TIMEOUT`: connection timeout in seconds, see
+///    [`SessionBuilder::connect_timeout`].
+///  - `OPENSSH_RS_CONTROL_DIR`: see [`SessionBuilder::control_directory`].
+///  - `OPENSSH_RS_BACKEND`: `process-mux` or `native-mux`, the backend used
+///    by [`SessionBuilder::connect_default`], provided that the corresponding
+///    feature is enabled. [`SessionBuilder::connect`] and
+///    [`SessionBuilder::connect_mux`] always use the backend they are named
+///    after.
+///
+/// Unset variables, and variables with an invalid value, are ignored.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionBuilder {
     user: Option<String>,
     port: Option<String>,
     keyfile: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    identity: Option<Identity>,
     connect_timeout: Option<String>,
     server_alive_interval: Option<u64>,
+    throttled_retries: u32,
     known_hosts_check: KnownHosts,
     control_dir: Option<PathBuf>,
+    control_socket_label: Option<String>,
+    control_socket_path: Option<PathBuf>,
     control_persist: ControlPersist,
     clean_history_control_dir: bool,
     config_file: Option<PathBuf>,
+    config: Option<ConfigWriter>,
     compression: Option<bool>,
-    jump_hosts: Vec<Box<str>>,
+    forward_agent: Option<bool>,
+    forward_x11: Option<(bool, bool)>,
+    jump_hosts: Vec<JumpHost>,
+    proxy_command: Option<String>,
     user_known_hosts_file: Option<Box<Path>>,
+    pinned_host_keys: Vec<String>,
     ssh_auth_sock: Option<Box<Path>>,
+    batch_mode: bool,
+    ssh_askpass: Option<Box<Path>>,
+    resolve_to: Option<IpAddr>,
+    master_priority: Option<(i32, Option<IoPriority>)>,
+    master_verbosity: u8,
+    keepalive_interval: Option<Duration>,
+    remote_command_mode: RemoteCommandMode,
+    remote_shell: RemoteShell,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    budget: Option<ConcurrencyBudget>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    metrics: Option<Metrics>,
+    /// Backend selected through `OPENSSH_RS_BACKEND`.
+    #[cfg(feature = "env-config")]
+    backend: Option<Backend>,
 }
 
 impl Default for SessionBuilder {
     fn default() -> Self {
-        Self {
+        #[allow(unused_mut)]
+        let mut builder = Self {
             user: None,
             port: None,
             keyfile: None,
+            identity: None,
             connect_timeout: None,
             server_alive_interval: None,
+            throttled_retries: 0,
             known_hosts_check: KnownHosts::Add,
             control_dir: None,
+            control_socket_label: None,
+            control_socket_path: None,
             control_persist: ControlPersist::Forever,
             clean_history_control_dir: false,
             config_file: None,
+            config: None,
             compression: None,
+            forward_agent: None,
+            forward_x11: None,
             jump_hosts: Vec::new(),
+            proxy_command: None,
             user_known_hosts_file: None,
+            pinned_host_keys: Vec::new(),
             ssh_auth_sock: None,
-        }
+            batch_mode: true,
+            ssh_askpass: None,
+            resolve_to: None,
+            master_priority: None,
+            master_verbosity: 0,
+            keepalive_interval: None,
+            remote_command_mode: RemoteCommandMode::Command,
+            remote_shell: RemoteShell::Posix,
+            budget: None,
+            metrics: None,
+            #[cfg(feature = "env-config")]
+            backend: None,
+        };
+
+        #[cfg(feature = "env-config")]
+        builder.apply_env(|key| std::env::var_os(key));
+
+        builder
     }
 }
 
 impl SessionBuilder {
+    #[cfg(feature = "env-config")]
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<OsString>) {
+        let var = |key| var(key).filter(|value| !value.is_empty());
+
+        if let Some(timeout) = var("OPENSSH_RS_CONNECT_TIMEOUT") {
+            if let Some(secs) = timeout.to_str().and_then(|secs| secs.parse().ok()) {
+                self.connect_timeout(Duration::from_secs(secs));
+            }
+        }
+
+        if let Some(dir) = var("OPENSSH_RS_CONTROL_DIR") {
+            self.control_dir = Some(dir.into());
+        }
+
+        if let Some(backend) = var("OPENSSH_RS_BACKEND") {
+            match backend.to_str() {
+                Some("process-mux") => self.backend = Some(Backend::ProcessMux),
+                Some("native-mux") => self.backend = Some(Backend::NativeMux),
+                _ => (),
+            }
+        }
+    }
+
     /// Return the user set in builder.
     pub fn get_user(&self) -> Option<&str> {
         self.user.as_deref()
@@ -151,6 +307,28 @@ impl SessionBuilder {
         self
     }
 
+    /// Set the private key to use from memory, e.g. a short-lived key
+    /// fetched from a secrets manager, which never has to be stored by the
+    /// caller.
+    ///
+    /// The key is written to a file only readable by the current user in
+    /// the temporary directory of the session, passed to `ssh -i`, and
+    /// overwritten with zeros then removed as soon as the master is
+    /// connected or fails to connect. Like [`keyfile`](Self::keyfile),
+    /// it disables the other keys of the ssh config.
+    ///
+    /// The key is zeroed when the builder is dropped, and is not part of
+    /// the [`ConnectionRecipe`] of the session, so it must be set again on
+    /// the builders returned by [`SessionBuilder::from_recipe`] and
+    /// [`Session::builder_snapshot`]. For the same reason, such a session
+    /// cannot be wrapped in a [`ReconnectingSession`](crate::ReconnectingSession).
+    ///
+    /// Defaults to `None`.
+    pub fn identity_from_memory(&mut self, key: impl Into<Vec<u8>>) -> &mut Self {
+        self.identity = Some(Identity(key.into()));
+        self
+    }
+
     /// See [`KnownHosts`].
     ///
     /// Default `KnownHosts::Add`.
@@ -178,6 +356,23 @@ impl SessionBuilder {
         self
     }
 
+    /// Set how many times connecting is retried if the server drops the
+    /// connection before sending its banner, which is what sshd does once
+    /// too many unauthenticated connections are pending (`MaxStartups`).
+    ///
+    /// Retries are delayed by a random duration, which grows exponentially
+    /// with every attempt. If the connection is still throttled after all
+    /// retries, [`Error::ServerThrottled`] is returned.
+    ///
+    /// Other reasons for the server to close the connection early, e.g. tcp
+    /// wrappers or fail2ban, look the same, so retries are opt-in.
+    ///
+    /// Defaults to `0`.
+    pub fn throttled_retries(&mut self, retries: u32) -> &mut Self {
+        self.throttled_retries = retries;
+        self
+    }
+
     /// Set the directory in which the temporary directory containing the control socket will
     /// be created.
     ///
@@ -191,6 +386,34 @@ impl SessionBuilder {
         self
     }
 
+    /// Embed `label` in the name of the temporary directory containing the
+    /// control socket, e.g. `.ssh-connection-billing-AbC123`, so that the
+    /// ssh masters seen in `lsof` or in the control directory can be
+    /// attributed to the part of the application that created them.
+    ///
+    /// Characters other than ASCII alphanumerics, `-` and `_` are replaced
+    /// with `_`, and the label is truncated to 32 characters to stay within
+    /// the length limit of unix socket paths.
+    pub fn control_socket_label(&mut self, label: impl AsRef<str>) -> &mut Self {
+        self.control_socket_label = Some(sanitize_label(label.as_ref()));
+        self
+    }
+
+    /// Create the control socket of the master at `path` instead of in the
+    /// temporary directory, e.g. to follow an existing `ControlPath` layout
+    /// or to let other processes share the master with [`Session::resume`].
+    ///
+    /// The path is used literally, without expanding `%` tokens, and the
+    /// temporary directory is still created for the log of the master.
+    /// Connecting fails if something already exists at `path`.
+    ///
+    /// Combine with [`control_persist`](Self::control_persist) to choose
+    /// how long the master lingers once unused.
+    pub fn control_socket_path(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.control_socket_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Clean up the temporary directories with the `.ssh-connection` prefix
     /// in directory specified by [`SessionBuilder::control_directory`], created by
     /// previous `openssh::Session` that is not cleaned up for some reasons
@@ -225,6 +448,18 @@ impl SessionBuilder {
         self
     }
 
+    /// Use a ssh_config fragment generated from `config`.
+    ///
+    /// It is written to the directory of the control socket when connecting,
+    /// and passed to ssh with `-F`, replacing the file set by
+    /// [`SessionBuilder::config_file`].
+    ///
+    /// Defaults to `None`.
+    pub fn config(&mut self, config: ConfigWriter) -> &mut Self {
+        self.config = Some(config);
+        self
+    }
+
     /// Enable or disable compression (including stdin, stdout, stderr, data
     /// for forwarded TCP and unix-domain connections, sftp and scp
     /// connections).
@@ -240,6 +475,44 @@ impl SessionBuilder {
         self
     }
 
+    /// Allow or forbid forwarding the authentication agent to the remote
+    /// host.
+    ///
+    /// This sets `ForwardAgent` on the master, which only forwards the agent
+    /// to the commands that ask for it with
+    /// [`OwningCommand::forward_agent`](crate::OwningCommand::forward_agent),
+    /// e.g. to run git over ssh on the remote host. The agent is the one of
+    /// `SSH_AUTH_SOCK`, or the one set with [`SessionBuilder::ssh_auth_sock`].
+    ///
+    /// Only forward the agent to hosts you trust, since anyone with enough
+    /// permissions on the remote host can use it while it is forwarded.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which
+    /// disables it unless configured otherwise.
+    pub fn forward_agent(&mut self, forward_agent: bool) -> &mut Self {
+        self.forward_agent = Some(forward_agent);
+        self
+    }
+
+    /// Allow or forbid forwarding X11 connections to the `DISPLAY` of this
+    /// process, e.g. to run GUI tests on the remote host.
+    ///
+    /// This passes `-X`, or `-Y` if `trusted`, to the master, which only
+    /// forwards X11 to the commands that ask for it with
+    /// [`OwningCommand::forward_x11`](crate::OwningCommand::forward_x11).
+    /// Untrusted clients are subject to the restrictions of the X11
+    /// SECURITY extension, while trusted ones have full access to the
+    /// display, so only trust hosts you trust.
+    ///
+    /// The server must also allow it with `X11Forwarding yes`.
+    ///
+    /// By default, ssh uses the values set in `~/.ssh/config`, which
+    /// disable it unless configured otherwise.
+    pub fn forward_x11(&mut self, forward_x11: bool, trusted: bool) -> &mut Self {
+        self.forward_x11 = Some((forward_x11, trusted));
+        self
+    }
+
     /// Specify one or multiple jump hosts.
     ///
     /// Connect to the target host by first making a ssh connection to the
@@ -252,15 +525,38 @@ impl SessionBuilder {
     /// Note that configuration directives specified by [`SessionBuilder`]
     /// do not apply to the jump hosts.
     ///
-    /// Use ~/.ssh/config to specify configuration for jump hosts.
+    /// Use [`SessionBuilder::jump_host`] or ~/.ssh/config to specify
+    /// configuration for jump hosts.
     pub fn jump_hosts<T: AsRef<str>>(&mut self, hosts: impl IntoIterator<Item = T>) -> &mut Self {
         self.jump_hosts = hosts
             .into_iter()
-            .map(|s| s.as_ref().to_string().into_boxed_str())
+            .map(|s| JumpHost::new(s.as_ref()))
             .collect();
         self
     }
 
+    /// Add a jump host with its own settings after the ones already
+    /// specified.
+    ///
+    /// If any jump host has settings, or with
+    /// [`proxy_command`](Self::proxy_command), the hops are chained with a
+    /// `ProxyCommand` running `ssh -W` for each of them instead of with
+    /// `ProxyJump`.
+    pub fn jump_host(&mut self, host: JumpHost) -> &mut Self {
+        self.jump_hosts.push(host);
+        self
+    }
+
+    /// Set the command used to connect to the first jump host, or to the
+    /// target host without jump hosts, e.g. `nc -X 5 -x proxy:1080 %h %p`.
+    ///
+    /// The command is run by the shell and its tokens are expanded by ssh,
+    /// as with the `ProxyCommand` directive, see `man 5 ssh_config`.
+    pub fn proxy_command(&mut self, command: impl Into<String>) -> &mut Self {
+        self.proxy_command = Some(command.into());
+        self
+    }
+
     /// Specify the path to the `known_hosts` file.
     ///
     /// The path provided may use tilde notation (`~`) to refer to the user's
@@ -273,6 +569,30 @@ impl SessionBuilder {
         self
     }
 
+    /// Only accept the host key `key`, e.g. `ssh-ed25519 AAAAC3Nz...` as
+    /// printed by `ssh-keyscan` without the host name, or as found in the
+    /// `/etc/ssh/ssh_host_*_key.pub` files of the host.
+    ///
+    /// The pinned keys are written to a known hosts file in the temporary
+    /// directory of the session, which replaces
+    /// [`user_known_hosts_file`](Self::user_known_hosts_file) and the global
+    /// known hosts files, and the connection is rejected unless the host
+    /// presents one of them, regardless of
+    /// [`known_hosts_check`](Self::known_hosts_check).
+    ///
+    /// Call this several times to accept any of several keys, e.g. during a
+    /// key rotation. Note that a fingerprint such as `SHA256:...` is not
+    /// enough, since ssh needs the full key to check the host.
+    ///
+    /// Connecting fails with [`Error::Connect`] of kind
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) if `key` does not
+    /// consist of a key type followed by the base64 encoded key, optionally
+    /// followed by a comment, on one line.
+    pub fn pinned_host_key(&mut self, key: impl Into<String>) -> &mut Self {
+        self.pinned_host_keys.push(key.into());
+        self
+    }
+
     /// Specify the path to the ssh-agent.
     ///
     /// The path provided may use tilde notation (`~`) to refer to the user's
@@ -284,6 +604,161 @@ impl SessionBuilder {
         self
     }
 
+    /// Enable or disable `BatchMode` on the master.
+    ///
+    /// In batch mode, ssh never asks for passwords or passphrases and fails
+    /// instead, since there is no terminal to ask them on. Disable it to
+    /// authenticate with passwords or keyboard-interactive authentication
+    /// through an askpass program, see
+    /// [`ssh_askpass`](Self::ssh_askpass).
+    ///
+    /// Note that [`connect_timeout`](Self::connect_timeout) does not apply
+    /// while the askpass program is waiting for an answer.
+    ///
+    /// Defaults to `true`.
+    pub fn batch_mode(&mut self, batch_mode: bool) -> &mut Self {
+        self.batch_mode = batch_mode;
+        self
+    }
+
+    /// Specify the askpass program the master runs to ask for passwords,
+    /// passphrases and keyboard-interactive answers, which it reads from
+    /// the stdout of the program.
+    ///
+    /// This sets `SSH_ASKPASS` and `SSH_ASKPASS_REQUIRE=force` for the
+    /// master, which requires OpenSSH 8.4 or later. The program is only run
+    /// with [`batch_mode`](Self::batch_mode) disabled.
+    ///
+    /// The default is `None`.
+    pub fn ssh_askpass(&mut self, ssh_askpass: impl AsRef<Path>) -> &mut Self {
+        self.ssh_askpass = Some(ssh_askpass.as_ref().to_owned().into_boxed_path());
+        self
+    }
+
+    /// Connect to `ip` instead of resolving the destination hostname
+    /// (`ssh -o HostName=ip -o HostKeyAlias=hostname`).
+    ///
+    /// The host key is still looked up and verified under the hostname
+    /// passed to `connect`, so that applications with their own service
+    /// discovery can bypass the system DNS without weakening host key
+    /// validation.
+    ///
+    /// This also decides whether IPv4 or IPv6 is used.
+    ///
+    /// The default is `None`.
+    pub fn resolve_to(&mut self, ip: impl Into<IpAddr>) -> &mut Self {
+        self.resolve_to = Some(ip.into());
+        self
+    }
+
+    /// Set the scheduling priority (see `nice(1)`) and, on Linux, the I/O
+    /// scheduling priority (see `ionice(1)`) of the ssh master process, so
+    /// that many masters do not starve the rest of a busy host.
+    ///
+    /// The priority sticks to the master for its whole lifetime, which may
+    /// extend past the [`Session`] depending on
+    /// [`control_persist`](SessionBuilder::control_persist). Processes
+    /// spawned for each command with the `process-mux` backend are not
+    /// affected.
+    ///
+    /// Note that lowering `nice` below 0 usually requires privileges,
+    /// without which connecting fails.
+    ///
+    /// The default is `None`, i.e. inherit the priority of this process.
+    pub fn master_priority(&mut self, nice: i32, ionice: Option<IoPriority>) -> &mut Self {
+        self.master_priority = Some((nice, ionice));
+        self
+    }
+
+    /// Make the ssh master log more details, as with `ssh -v`, where
+    /// `verbosity` is the number of `-v` and is capped at 3.
+    ///
+    /// The log can be followed with [`Session::master_events`].
+    ///
+    /// The default is 0.
+    pub fn master_verbosity(&mut self, verbosity: u8) -> &mut Self {
+        self.master_verbosity = verbosity.min(3);
+        self
+    }
+
+    /// Attach the sessions created by this builder to `budget`, limiting
+    /// the number of remote commands running simultaneously across all of
+    /// them, see [`ConcurrencyBudget`].
+    ///
+    /// The budget is not part of a [`ConnectionRecipe`].
+    ///
+    /// The default is `None`, i.e. no limit.
+    pub fn concurrency_budget(&mut self, budget: ConcurrencyBudget) -> &mut Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Report the metrics of the sessions created by this builder, such as
+    /// the commands spawned and their latency, to `sink`, see
+    /// [`MetricsSink`].
+    ///
+    /// The sink is not part of a [`ConnectionRecipe`].
+    ///
+    /// The default is `None`, i.e. no metrics.
+    pub fn metrics_sink(&mut self, sink: Arc<dyn MetricsSink>) -> &mut Self {
+        self.metrics = Some(Metrics(sink));
+        self
+    }
+
+    /// [Check](Session::check) the connection of the sessions created by
+    /// this builder every `interval` from a background task, so that
+    /// [`Session::subscribe_health`] reports a broken connection even when
+    /// the session is idle.
+    ///
+    /// The task stops when the [`Session`] is dropped.
+    ///
+    /// The default is `None`, i.e. no background check.
+    pub fn keepalive_interval(&mut self, interval: Duration) -> &mut Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set how the commands of the sessions created by this builder are
+    /// sent to the server, for servers that restrict what can be run, see
+    /// [`RemoteCommandMode`].
+    ///
+    /// Subsystems are not affected.
+    ///
+    /// The default is [`RemoteCommandMode::Command`].
+    pub fn remote_command_mode(&mut self, mode: RemoteCommandMode) -> &mut Self {
+        self.remote_command_mode = mode;
+        self
+    }
+
+    /// Set the shell interpreting the commands of the sessions created by
+    /// this builder on the remote host, so that the program and arguments
+    /// given to [`Session::command`] and [`OwningCommand::arg`] are escaped
+    /// for it, see [`RemoteShell`].
+    ///
+    /// The helpers of this crate running shell scripts, such as
+    /// [`Session::file_ops`], still require a POSIX `sh` on the remote
+    /// host. The helpers of [`OwningCommand`] adding shell syntax to the
+    /// command, i.e. [`force_env`], the redirections to remote files,
+    /// [`pipe`], [`setsid`] and [`trampoline_threshold`], only work with
+    /// [`RemoteShell::Posix`] and [`RemoteShell::Csh`], except
+    /// [`stderr_to_remote_file`] and trampolines which require the former.
+    /// With other shells, spawning such a command fails with
+    /// [`Error::UnsupportedShellSyntax`].
+    ///
+    /// The default is [`RemoteShell::Posix`].
+    ///
+    /// [`OwningCommand`]: crate::OwningCommand
+    /// [`OwningCommand::arg`]: crate::OwningCommand::arg
+    /// [`force_env`]: crate::OwningCommand::force_env
+    /// [`pipe`]: crate::OwningCommand::pipe
+    /// [`setsid`]: crate::OwningCommand::setsid
+    /// [`trampoline_threshold`]: crate::OwningCommand::trampoline_threshold
+    /// [`stderr_to_remote_file`]: crate::OwningCommand::stderr_to_remote_file
+    pub fn remote_shell(&mut self, shell: RemoteShell) -> &mut Self {
+        self.remote_shell = shell;
+        self
+    }
+
     /// Connect to the host at the given `host` over SSH using process impl, which will
     /// spawn a new ssh process for each `Child` created.
     ///
@@ -298,7 +773,7 @@ impl SessionBuilder {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub async fn connect<S: AsRef<str>>(&self, destination: S) -> Result<Session, Error> {
-        self.connect_impl(destination.as_ref(), Session::new_process_mux)
+        self.connect_impl(destination.as_ref(), Backend::ProcessMux)
             .await
     }
 
@@ -318,18 +793,193 @@ impl SessionBuilder {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub async fn connect_mux<S: AsRef<str>>(&self, destination: S) -> Result<Session, Error> {
-        self.connect_impl(destination.as_ref(), Session::new_native_mux)
+        self.connect_impl(destination.as_ref(), Backend::NativeMux)
             .await
     }
 
-    async fn connect_impl(
-        &self,
-        destination: &str,
-        f: fn(TempDir) -> Session,
-    ) -> Result<Session, Error> {
+    /// Connect to the host at the given `destination`, as with
+    /// [`connect`](Self::connect) if the `process-mux` feature is enabled,
+    /// and with [`connect_mux`](Self::connect_mux) otherwise.
+    ///
+    /// With the `env-config` feature enabled, the backend can be chosen
+    /// through `OPENSSH_RS_BACKEND` instead, see [`SessionBuilder`].
+    #[cfg(any(feature = "process-mux", feature = "native-mux"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "process-mux", feature = "native-mux"))))]
+    pub async fn connect_default<S: AsRef<str>>(&self, destination: S) -> Result<Session, Error> {
+        self.connect_impl(destination.as_ref(), self.default_backend())
+            .await
+    }
+
+    /// The backend used by [`connect_default`](Self::connect_default).
+    #[cfg(any(feature = "process-mux", feature = "native-mux"))]
+    fn default_backend(&self) -> Backend {
+        #[cfg(feature = "env-config")]
+        if let Some(backend) = self.backend.filter(|backend| backend.is_compiled()) {
+            return backend;
+        }
+
+        if cfg!(feature = "process-mux") {
+            Backend::ProcessMux
+        } else {
+            Backend::NativeMux
+        }
+    }
+
+    async fn connect_impl(&self, destination: &str, backend: Backend) -> Result<Session, Error> {
+        let f = backend.constructor().await?;
+
         let (builder, destination) = self.resolve(destination);
-        let tempdir = builder.launch_master(destination).await?;
-        Ok(f(tempdir))
+
+        let op = Op::session(&Target::new(Some(destination)), "connect");
+        let launch = async {
+            let mut attempt = 0;
+            loop {
+                match builder.launch_master(destination).await {
+                    Err(Error::ServerThrottled(_)) if attempt < builder.throttled_retries => {
+                        time::sleep(throttled_backoff(attempt)).await;
+                        attempt += 1;
+                    }
+                    res => break res,
+                }
+            }
+        };
+        let tempdir = op.run(launch, |_| None).await?;
+        let budget = builder.budget.clone();
+        let metrics = builder.metrics.clone();
+        let keepalive_interval = builder.keepalive_interval;
+        let remote_command_mode = builder.remote_command_mode.clone();
+        let remote_shell = builder.remote_shell;
+        let mut builder = builder.into_owned();
+        // Do not keep the key in memory for the lifetime of the session.
+        let in_memory_identity = builder.identity.take().is_some();
+        let recipe = ConnectionRecipe {
+            builder,
+            destination: destination.into(),
+            in_memory_identity,
+        };
+        let mut session = f(tempdir).with_recipe(recipe);
+        session.set_concurrency_budget(budget);
+        session.set_metrics_sink(metrics.map(|metrics| metrics.0));
+        session.set_remote_command_mode(remote_command_mode);
+        session.set_remote_shell(remote_shell);
+        Ok(match keepalive_interval {
+            Some(interval) => session.with_keepalive(interval),
+            None => session,
+        })
+    }
+
+    /// Create a builder with the settings captured in `recipe`, so that
+    /// connecting to [`ConnectionRecipe::destination`] reproduces the
+    /// session the recipe was exported from.
+    pub fn from_recipe(recipe: &ConnectionRecipe) -> Self {
+        recipe.builder.clone()
+    }
+
+    /// Find the control socket of a master already running for
+    /// `destination`, as set up by `ControlMaster auto` and `ControlPath`
+    /// in the ssh config, so that it can be reused with
+    /// [`Session::attach_mux`] or [`Session::attach`] instead of launching
+    /// a new master.
+    ///
+    /// `ControlPath` tokens such as `%C` are expanded by `ssh -G`, which
+    /// only evaluates the config for the user, port, config file, jump
+    /// hosts and [`resolve_to`](Self::resolve_to) of this builder, and
+    /// exits without connecting.
+    ///
+    /// Returns `None` if no `ControlPath` is configured or if there is no
+    /// socket at that path. The master is not checked to be alive, which
+    /// attaching to it does.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # async fn f() -> Result<(), openssh::Error> {
+    /// use openssh::{KnownHosts, Session, SessionBuilder};
+    ///
+    /// let builder = SessionBuilder::default();
+    /// let session = match builder.find_existing_master("me@ssh.example.com").await? {
+    ///     Some(socket) => Session::attach_mux(socket).await?,
+    ///     None => builder.connect_mux("me@ssh.example.com").await?,
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn find_existing_master<S: AsRef<str>>(
+        &self,
+        destination: S,
+    ) -> Result<Option<PathBuf>, Error> {
+        let (builder, destination) = self.resolve(destination.as_ref());
+        builder.find_existing_master_impl(destination).await
+    }
+
+    async fn find_existing_master_impl(&self, destination: &str) -> Result<Option<PathBuf>, Error> {
+        let config = self.resolved_config(destination).await?;
+        let socket = match parse_control_path(&config) {
+            Some(socket) => socket,
+            None => return Ok(None),
+        };
+
+        use std::os::unix::fs::FileTypeExt;
+        Ok(fs::metadata(&socket)
+            .map_or(false, |metadata| metadata.file_type().is_socket())
+            .then_some(socket))
+    }
+
+    /// Evaluate the ssh config for `destination` with `ssh -G`, taking the
+    /// user, port, config file, jump hosts and `resolve_to` of this builder
+    /// into account.
+    pub(crate) async fn resolved_config(&self, destination: &str) -> Result<String, Error> {
+        let mut cmd = process::Command::new("ssh");
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .arg("-G");
+
+        if let Some(ref port) = self.port {
+            cmd.arg("-p").arg(port);
+        }
+
+        if let Some(ref user) = self.user {
+            cmd.arg("-l").arg(user);
+        }
+
+        let dir;
+        let config_file = if let Some(ref config) = self.config {
+            dir = Builder::new()
+                .prefix(".ssh-config")
+                .tempdir()
+                .map_err(Error::Connect)?;
+            let generated_config = dir.path().join("config");
+            config.write_to(&generated_config).map_err(Error::Connect)?;
+            Some(Cow::Owned(generated_config))
+        } else {
+            self.config_file.as_deref().map(Cow::Borrowed)
+        };
+        if let Some(config_file) = config_file {
+            cmd.arg("-F").arg(&*config_file);
+        }
+
+        // `%C` covers `ProxyJump`, but not `ProxyCommand`.
+        if !self.jump_hosts.is_empty()
+            && self.proxy_command.is_none()
+            && self.jump_hosts.iter().all(JumpHost::is_plain)
+        {
+            cmd.arg("-J").arg(proxy_jump(&self.jump_hosts));
+        }
+
+        if let Some(ip) = self.resolve_to {
+            cmd.arg("-o").arg(format!("HostName={}", ip));
+        }
+
+        let output = cmd
+            .arg(destination)
+            .output()
+            .await
+            .map_err(Error::Connect)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::interpret_ssh_error(&stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     }
 
     /// [`SessionBuilder`] support for `destination` parsing.
@@ -386,6 +1036,8 @@ impl SessionBuilder {
     /// Create ssh master session and return [`TempDir`] which
     /// contains the ssh control socket.
     pub async fn launch_master(&self, destination: &str) -> Result<TempDir, Error> {
+        check_pinned_host_keys(&self.pinned_host_keys)?;
+
         let socketdir = if let Some(socketdir) = self.control_dir.as_ref() {
             socketdir
         } else {
@@ -398,13 +1050,42 @@ impl SessionBuilder {
             let _ = clean_history_control_dir(socketdir, prefix);
         }
 
+        let labelled_prefix;
         let dir = Builder::new()
-            .prefix(prefix)
+            .prefix(match &self.control_socket_label {
+                Some(label) => {
+                    labelled_prefix = format!("{}-{}-", prefix, label);
+                    labelled_prefix.as_str()
+                }
+                None => prefix,
+            })
             .tempdir_in(socketdir)
             .map_err(Error::Master)?;
 
         let log = dir.path().join("log");
 
+        let master = dir.path().join("master");
+        let socket = match &self.control_socket_path {
+            Some(path) => {
+                let path = if path.is_relative() {
+                    std::env::current_dir().map_err(Error::Master)?.join(path)
+                } else {
+                    path.clone()
+                };
+                if fs::symlink_metadata(&path).is_ok() {
+                    return Err(Error::Master(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        "the control socket path already exists",
+                    )));
+                }
+
+                // The session finds the socket in the temporary directory.
+                std::os::unix::fs::symlink(&path, &master).map_err(Error::Master)?;
+                escape_tokens(&path)
+            }
+            None => master.into_os_string(),
+        };
+
         let mut init = process::Command::new("ssh");
 
         init.stdin(Stdio::null())
@@ -413,16 +1094,24 @@ impl SessionBuilder {
             .arg("-E")
             .arg(&log)
             .arg("-S")
-            .arg(dir.path().join("master"))
+            .arg(socket)
             .arg("-M")
             .arg("-f")
             .arg("-N")
             .arg("-o")
             .arg(self.control_persist.as_option().deref())
             .arg("-o")
-            .arg("BatchMode=yes")
+            .arg(if self.batch_mode {
+                "BatchMode=yes"
+            } else {
+                "BatchMode=no"
+            })
             .arg("-o")
-            .arg(self.known_hosts_check.as_option());
+            .arg(if self.pinned_host_keys.is_empty() {
+                self.known_hosts_check.as_option()
+            } else {
+                KnownHosts::Strict.as_option()
+            });
 
         if let Some(ref timeout) = self.connect_timeout {
             init.arg("-o").arg(format!("ConnectTimeout={}", timeout));
@@ -441,13 +1130,32 @@ impl SessionBuilder {
             init.arg("-l").arg(user);
         }
 
-        if let Some(ref k) = self.keyfile {
+        // Shredded on drop, once the master is connected or failed to.
+        let identity = match &self.identity {
+            Some(key) => Some(IdentityFile::create(dir.path().join("identity"), key)?),
+            None => None,
+        };
+
+        if self.keyfile.is_some() || identity.is_some() {
             // if the user gives a keyfile, _only_ use that keyfile
             init.arg("-o").arg("IdentitiesOnly=yes");
+        }
+        if let Some(ref k) = self.keyfile {
             init.arg("-i").arg(k);
         }
+        if let Some(identity) = &identity {
+            init.arg("-i").arg(&identity.path);
+        }
 
-        if let Some(ref config_file) = self.config_file {
+        let generated_config;
+        let config_file = if let Some(ref config) = self.config {
+            generated_config = dir.path().join("config");
+            config.write_to(&generated_config).map_err(Error::Master)?;
+            Some(generated_config.as_path())
+        } else {
+            self.config_file.as_deref()
+        };
+        if let Some(config_file) = config_file {
             init.arg("-F").arg(config_file);
         }
 
@@ -457,47 +1165,282 @@ impl SessionBuilder {
             init.arg("-o").arg(format!("Compression={}", arg));
         }
 
-        if let Some(ssh_auth_sock) = self.ssh_auth_sock.as_deref() {
-            init.env("SSH_AUTH_SOCK", ssh_auth_sock);
+        if let Some(forward_agent) = self.forward_agent {
+            let arg = if forward_agent { "yes" } else { "no" };
+
+            init.arg("-o").arg(format!("ForwardAgent={}", arg));
         }
 
-        let mut it = self.jump_hosts.iter();
+        match self.forward_x11 {
+            Some((true, true)) => {
+                init.arg("-Y");
+            }
+            Some((true, false)) => {
+                // Override `ForwardX11Trusted yes` in the ssh config.
+                init.arg("-X").arg("-o").arg("ForwardX11Trusted=no");
+            }
+            Some((false, _)) => {
+                init.arg("-x");
+            }
+            None => (),
+        }
 
-        if let Some(jump_host) = it.next() {
-            let s = jump_host.to_string();
+        if let Some(ssh_auth_sock) = self.ssh_auth_sock.as_deref() {
+            init.env("SSH_AUTH_SOCK", ssh_auth_sock);
+        }
 
-            let dest = it.fold(s, |mut s, jump_host| {
-                s.push(',');
-                s.push_str(jump_host);
-                s
-            });
+        if let Some(ssh_askpass) = self.ssh_askpass.as_deref() {
+            // Use the program even though `DISPLAY` may be unset.
+            init.env("SSH_ASKPASS", ssh_askpass)
+                .env("SSH_ASKPASS_REQUIRE", "force");
+        }
 
-            init.arg("-J").arg(&dest);
+        if self.proxy_command.is_some() || !self.jump_hosts.iter().all(JumpHost::is_plain) {
+            let proxy = proxy_command(&self.jump_hosts, self.proxy_command.as_deref(), config_file);
+            if let Some(proxy) = proxy {
+                let mut option: OsString = "ProxyCommand=".into();
+                option.push(proxy);
+                init.arg("-o").arg(option);
+            }
+        } else if !self.jump_hosts.is_empty() {
+            init.arg("-J").arg(proxy_jump(&self.jump_hosts));
         }
 
-        if let Some(user_known_hosts_file) = &self.user_known_hosts_file {
+        let pinned_known_hosts;
+        let user_known_hosts_file = if self.pinned_host_keys.is_empty() {
+            self.user_known_hosts_file.as_deref()
+        } else {
+            pinned_known_hosts = dir.path().join("known_hosts");
+            let known_hosts: String = self
+                .pinned_host_keys
+                .iter()
+                .map(|key| format!("* {}\n", key.trim()))
+                .collect();
+            std::fs::write(&pinned_known_hosts, known_hosts).map_err(Error::Master)?;
+
+            init.arg("-o")
+                .arg("GlobalKnownHostsFile=/dev/null")
+                .arg("-o")
+                .arg("UpdateHostKeys=no");
+            Some(pinned_known_hosts.as_path())
+        };
+        if let Some(user_known_hosts_file) = user_known_hosts_file {
             let mut option: OsString = "UserKnownHostsFile=".into();
-            option.push(&**user_known_hosts_file);
+            option.push(user_known_hosts_file);
             init.arg("-o").arg(option);
         }
 
+        if let Some(ip) = self.resolve_to {
+            let hostname = destination
+                .rfind('@')
+                .map_or(destination, |at| &destination[(at + 1)..]);
+
+            init.arg("-o")
+                .arg(format!("HostName={}", ip))
+                .arg("-o")
+                .arg(format!("HostKeyAlias={}", hostname));
+        }
+
+        for _ in 0..self.master_verbosity {
+            init.arg("-v");
+        }
+
         init.arg(destination);
 
+        if let Some((nice, ionice)) = self.master_priority {
+            // Safety: only async-signal-safe functions are called.
+            unsafe {
+                init.pre_exec(move || set_priority(nice, ionice));
+            }
+        }
+
         // we spawn and immediately wait, because the process is supposed to fork.
         let status = init.status().await.map_err(Error::Connect)?;
+        drop(identity);
 
         if !status.success() {
             let output = fs::read_to_string(log).map_err(Error::Connect)?;
 
-            Err(Error::interpret_ssh_error(&output))
+            Err(Error::interpret_ssh_error(&strip_debug_lines(&output)))
         } else {
             Ok(dir)
         }
     }
 }
 
+/// A private key given to [`SessionBuilder::identity_from_memory`], which is
+/// zeroed on drop and never printed.
+#[derive(Clone)]
+struct Identity(Vec<u8>);
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Identity(..)")
+    }
+}
+
+/// Overwrite `bytes` with zeros, in a way the compiler cannot optimize out.
+fn zero(bytes: &mut [u8]) {
+    for byte in bytes {
+        // Safety: `byte` is a valid and aligned reference.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        zero(&mut self.0);
+    }
+}
+
+/// The file an [`Identity`] is written to for ssh, which is overwritten
+/// with zeros and removed on drop.
+struct IdentityFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl IdentityFile {
+    fn create(path: PathBuf, identity: &Identity) -> Result<Self, Error> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(Error::Master)?;
+
+        // ssh fails to parse keys without a final newline.
+        let newline = !identity.0.ends_with(b"\n");
+        // Shreds whatever was written if writing fails.
+        let this = Self {
+            path,
+            len: identity.0.len() + usize::from(newline),
+        };
+        file.write_all(&identity.0).map_err(Error::Master)?;
+        if newline {
+            file.write_all(b"\n").map_err(Error::Master)?;
+        }
+        Ok(this)
+    }
+}
+
+impl Drop for IdentityFile {
+    fn drop(&mut self) {
+        use std::io::Write;
+
+        if let Ok(mut file) = fs::OpenOptions::new().write(true).open(&self.path) {
+            let _ = file.write_all(&vec![0; self.len]);
+            let _ = file.sync_all();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Extract the expanded `ControlPath` from the output of `ssh -G`.
+fn parse_control_path(config: &str) -> Option<PathBuf> {
+    config
+        .lines()
+        .find_map(|line| line.strip_prefix("controlpath "))
+        .filter(|path| *path != "none")
+        .map(PathBuf::from)
+}
+
+/// Escape the `%` of `path`, which ssh would expand as tokens in
+/// `ControlPath`.
+fn escape_tokens(path: &Path) -> OsString {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let mut escaped = Vec::new();
+    for &b in path.as_os_str().as_bytes() {
+        escaped.push(b);
+        if b == b'%' {
+            escaped.push(b'%');
+        }
+    }
+    OsString::from_vec(escaped)
+}
+
+/// Check that the keys given to [`SessionBuilder::pinned_host_key`] look
+/// like a line of a known hosts file without the host name.
+fn check_pinned_host_keys(keys: &[String]) -> Result<(), Error> {
+    for key in keys {
+        let mut fields = key.split_whitespace();
+        let valid = matches!(
+            (fields.next(), fields.next()),
+            (Some(key_type), Some(_)) if !key_type.starts_with("SHA256:")
+        ) && !key.contains('\n');
+        if !valid {
+            return Err(Error::Connect(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid host key {:?}", key),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The settings a [`Session`] was established with, as returned by
+/// [`Session::export_recipe`].
+///
+/// With the `serde` feature enabled, a recipe can be serialized and sent to
+/// another process, which can then connect with identical settings using
+/// [`SessionBuilder::from_recipe`].
+///
+/// Note that paths (keyfile, config file, control directory, ...) are
+/// carried over verbatim, and must thus be valid wherever the recipe is used.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionRecipe {
+    builder: SessionBuilder,
+    destination: Box<str>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    in_memory_identity: bool,
+}
+
+impl ConnectionRecipe {
+    /// Return the destination the session was connected to.
+    ///
+    /// The user and port from an `ssh://` destination are already part
+    /// of the builder settings, so this is only the host.
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Whether the session was connected with
+    /// [`SessionBuilder::identity_from_memory`], whose key is not part of
+    /// the recipe.
+    pub(crate) fn in_memory_identity(&self) -> bool {
+        self.in_memory_identity
+    }
+
+    /// The settings of this recipe, without those that only make sense
+    /// for its destination.
+    pub(crate) fn sibling_builder(&self) -> SessionBuilder {
+        SessionBuilder {
+            resolve_to: None,
+            control_socket_path: None,
+            ..self.builder.clone()
+        }
+    }
+
+    /// Evaluate the ssh config for the destination with `ssh -G`.
+    pub(crate) async fn resolved_config(&self) -> Result<String, Error> {
+        self.builder.resolved_config(&self.destination).await
+    }
+
+    /// Connect again with the settings of this recipe, using `backend` for
+    /// the new [`Session`].
+    pub(crate) async fn reconnect(&self, backend: Backend) -> Result<Session, Error> {
+        self.builder.connect_impl(&self.destination, backend).await
+    }
+}
+
 /// Specifies how long the controlling ssh process should stay alive.
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum ControlPersist {
     /// Will stay alive indefinitely.
@@ -520,8 +1463,106 @@ impl ControlPersist {
     }
 }
 
+/// How remote commands are sent to the server, see
+/// [`SessionBuilder::remote_command_mode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum RemoteCommandMode {
+    /// Send commands as they are constructed, to be run by the login shell
+    /// of the remote user.
+    Command,
+
+    /// The server only allows subsystems, e.g. an sftp-only account, so
+    /// spawning anything else fails with [`Error::CommandRejected`] instead
+    /// of a confusing error from the server.
+    SubsystemOnly,
+
+    /// The server runs a forced command (`command=` in `authorized_keys` or
+    /// `ForceCommand` in `sshd_config`), which receives the command sent by
+    /// the client in `SSH_ORIGINAL_COMMAND` and expects it in a specific
+    /// format.
+    ///
+    /// Every occurrence of `{command}` in `template` is replaced with the
+    /// command line as it would be sent with [`RemoteCommandMode::Command`],
+    /// i.e. with arguments escaped by [`OwningCommand::arg`] and joined with
+    /// spaces, and the result is sent instead. For example, with the
+    /// template `run --audit -- {command}`, `session.command("ls").arg("-l")`
+    /// sends `run --audit -- ls -l`.
+    ///
+    /// Trampolines are not used in this mode, see
+    /// [`OwningCommand::trampoline_threshold`].
+    ///
+    /// [`OwningCommand::arg`]: crate::OwningCommand::arg
+    /// [`OwningCommand::trampoline_threshold`]: crate::OwningCommand::trampoline_threshold
+    ForcedCommandPayload {
+        /// The format of the payload.
+        template: String,
+    },
+}
+
+/// The shell interpreting the commands on the remote host, which selects
+/// how [`OwningCommand::arg`] escapes arguments, and which of the helpers
+/// adding shell syntax to the command can be used, see
+/// [`SessionBuilder::remote_shell`].
+///
+/// [`OwningCommand::arg`]: crate::OwningCommand::arg
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum RemoteShell {
+    /// A POSIX shell, such as `sh` or `bash`.
+    #[default]
+    Posix,
+
+    /// `csh` or `tcsh`.
+    Csh,
+
+    /// PowerShell, e.g. set as the default shell of a Windows OpenSSH
+    /// server.
+    PowerShell,
+
+    /// `cmd.exe`, the default shell of Windows OpenSSH servers.
+    Cmd,
+
+    /// No shell: the command line is split by the remote program itself,
+    /// following the convention of `CommandLineToArgvW`.
+    NoShell,
+}
+
+impl RemoteShell {
+    /// Escape `arg` so that it is passed verbatim to the remote program.
+    pub(crate) fn escape<'a>(&self, arg: &'a OsStr) -> Cow<'a, OsStr> {
+        match self {
+            RemoteShell::Posix => escape::posix(arg),
+            RemoteShell::Csh => escape::csh(arg),
+            RemoteShell::PowerShell => escape::powershell(arg),
+            RemoteShell::Cmd => escape::cmd(arg),
+            RemoteShell::NoShell => escape::argv(arg),
+        }
+    }
+}
+
+/// I/O scheduling priority of the ssh master, see
+/// [`SessionBuilder::master_priority`].
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum IoPriority {
+    /// Best-effort scheduling with the given level, from 0 (highest)
+    /// to 7 (lowest).
+    ///
+    /// This corresponds to `ionice -c 2 -n level`.
+    BestEffort(u8),
+    /// Only get disk time when no other process needs it.
+    ///
+    /// This corresponds to `ionice -c 3`.
+    Idle,
+}
+
 /// Specifies how the host's key fingerprint should be handled.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum KnownHosts {
     /// The host's fingerprint must match what is in the known hosts file.
     ///
@@ -540,7 +1581,7 @@ pub enum KnownHosts {
 }
 
 impl KnownHosts {
-    fn as_option(&self) -> &'static str {
+    pub(crate) fn as_option(&self) -> &'static str {
         match *self {
             KnownHosts::Strict => "StrictHostKeyChecking=yes",
             KnownHosts::Add => "StrictHostKeyChecking=accept-new",
@@ -551,7 +1592,140 @@ impl KnownHosts {
 
 #[cfg(test)]
 mod tests {
-    use super::SessionBuilder;
+    use super::{ConnectionRecipe, SessionBuilder};
+
+    #[test]
+    fn set_priority() {
+        use super::IoPriority;
+        use std::os::unix::process::CommandExt;
+
+        // Lowering the nice value requires privileges, so never go below
+        // the one the tests run with.
+        let current = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+        let nice = current.clamp(5, 19);
+
+        let mut cmd = std::process::Command::new("nice");
+        unsafe {
+            cmd.pre_exec(move || super::set_priority(nice, Some(IoPriority::Idle)));
+        }
+        let output = cmd.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, format!("{}\n", nice).as_bytes());
+    }
+
+    #[cfg(feature = "env-config")]
+    #[test]
+    fn apply_env() {
+        use super::Backend;
+        use std::ffi::OsString;
+
+        let mut b = SessionBuilder::default();
+        b.apply_env(|key| {
+            let value = match key {
+                "OPENSSH_RS_CONNECT_TIMEOUT" => "5",
+                "OPENSSH_RS_CONTROL_DIR" => "/tmp/openssh-rs",
+                "OPENSSH_RS_BACKEND" => "native-mux",
+                _ => return None,
+            };
+            Some(OsString::from(value))
+        });
+        assert_eq!(b.connect_timeout.as_deref(), Some("5"));
+        assert_eq!(b.control_dir.as_deref(), Some("/tmp/openssh-rs".as_ref()));
+        assert_eq!(b.backend, Some(Backend::NativeMux));
+        #[cfg(feature = "native-mux")]
+        assert_eq!(b.default_backend(), Backend::NativeMux);
+
+        let mut b = SessionBuilder::default();
+        b.apply_env(|key| {
+            let value = match key {
+                "OPENSSH_RS_CONNECT_TIMEOUT" => "soon",
+                "OPENSSH_RS_CONTROL_DIR" => "",
+                "OPENSSH_RS_BACKEND" => "libssh",
+                _ => return None,
+            };
+            Some(OsString::from(value))
+        });
+        assert_eq!(b.connect_timeout, None);
+        assert_eq!(b.control_dir, None);
+        assert_eq!(b.backend, None);
+        #[cfg(feature = "process-mux")]
+        assert_eq!(b.default_backend(), Backend::ProcessMux);
+    }
+
+    #[test]
+    fn parse_control_path() {
+        let config = "user test\ncontrolmaster auto\ncontrolpath /root/.ssh/cm-3381\n";
+        assert_eq!(
+            super::parse_control_path(config).as_deref(),
+            Some(std::path::Path::new("/root/.ssh/cm-3381"))
+        );
+
+        assert_eq!(super::parse_control_path("controlpath none\n"), None);
+        assert_eq!(super::parse_control_path("user test\n"), None);
+    }
+
+    #[test]
+    fn escape_tokens() {
+        assert_eq!(
+            super::escape_tokens(std::path::Path::new("/run/cm-100%-%h")),
+            "/run/cm-100%%-%%h"
+        );
+    }
+
+    #[test]
+    fn control_socket_label() {
+        let mut b = SessionBuilder::default();
+        b.control_socket_label("billing/export job.é");
+        assert_eq!(
+            b.control_socket_label.as_deref(),
+            Some("billing_export_job__")
+        );
+
+        b.control_socket_label("x".repeat(100));
+        assert_eq!(b.control_socket_label.unwrap().len(), 32);
+    }
+
+    #[test]
+    fn check_pinned_host_keys() {
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        super::check_pinned_host_keys(&[key.to_owned(), format!("{} host key", key)]).unwrap();
+
+        for invalid in [
+            "",
+            "ssh-ed25519",
+            "SHA256:abc def",
+            "ssh-ed25519 AAAA\nssh-rsa AAAA",
+        ] {
+            match super::check_pinned_host_keys(&[invalid.to_owned()]).unwrap_err() {
+                crate::Error::Connect(err) => {
+                    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput)
+                }
+                e => unreachable!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn sibling_builder() {
+        let mut b = SessionBuilder::default();
+        b.resolve_to([10, 0, 0, 1])
+            .control_socket_path("/run/a.sock")
+            .keyfile("/keys/a");
+        let (b, d) = b.resolve("ssh://test-user@a:2222");
+        let recipe = ConnectionRecipe {
+            builder: b.into_owned(),
+            destination: d.into(),
+            in_memory_identity: false,
+        };
+
+        let b = recipe.sibling_builder();
+        assert_eq!(b.resolve_to, None);
+        assert_eq!(b.control_socket_path, None);
+        assert_eq!(b.keyfile.as_deref(), Some(std::path::Path::new("/keys/a")));
+        assert_eq!(b.user.as_deref(), Some("test-user"));
+        assert_eq!(b.port.as_deref(), Some("2222"));
+    }
 
     #[test]
     fn resolve() {
@@ -0,0 +1,279 @@
+use super::Error;
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+#[cfg(feature = "gzip")]
+use flate2::{write::GzEncoder, Compression};
+#[cfg(feature = "gzip")]
+use std::io::Write;
+
+/// Options for [`OwningCommand::capture_to_file`](crate::OwningCommand::capture_to_file).
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOptions {
+    #[cfg(feature = "gzip")]
+    compress: bool,
+    rotate_mb: u64,
+}
+
+impl CaptureOptions {
+    /// Create the default options, which write each stream to a single
+    /// uncompressed file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compress the files with gzip, appending `.gz` to their names.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "gzip")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "gzip")))]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Start a new file once `rotate_mb` MiB of output are written to the
+    /// current one, before compression, or never if `0`.
+    ///
+    /// Defaults to `0`.
+    pub fn rotate_mb(mut self, rotate_mb: u64) -> Self {
+        self.rotate_mb = rotate_mb;
+        self
+    }
+
+    fn compressed(&self) -> bool {
+        #[cfg(feature = "gzip")]
+        {
+            self.compress
+        }
+        #[cfg(not(feature = "gzip"))]
+        {
+            false
+        }
+    }
+}
+
+/// An output stream of a remote process written to local files by
+/// [`OwningCommand::capture_to_file`](crate::OwningCommand::capture_to_file).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedStream {
+    /// Number of bytes written by the process, before compression.
+    pub len: u64,
+    /// The files written, in order.
+    pub files: Vec<PathBuf>,
+}
+
+/// The output of a finished remote process, written to local files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedOutput {
+    /// The status (exit code) of the process.
+    pub status: ExitStatus,
+    /// Where the data that the process wrote to stdout was written.
+    pub stdout: CapturedStream,
+    /// Where the data that the process wrote to stderr was written.
+    pub stderr: CapturedStream,
+}
+
+/// `base` with `suffix` appended to its file name, e.g. `build.log.stdout`.
+pub(crate) fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(base);
+    path.push(suffix);
+    path.into()
+}
+
+/// A local file being written.
+struct Segment {
+    file: File,
+    #[cfg(feature = "gzip")]
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl Segment {
+    async fn create(path: &Path, compress: bool) -> Result<Self, Error> {
+        #[cfg(not(feature = "gzip"))]
+        debug_assert!(!compress, "compression requires the gzip feature");
+
+        let file = File::create(path).await.map_err(Error::LocalIo)?;
+        Ok(Self {
+            file,
+            #[cfg(feature = "gzip")]
+            encoder: compress.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+        })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        #[cfg(feature = "gzip")]
+        if let Some(encoder) = &mut self.encoder {
+            encoder.write_all(data).map_err(Error::LocalIo)?;
+            let compressed = encoder.get_mut();
+            self.file
+                .write_all(compressed)
+                .await
+                .map_err(Error::LocalIo)?;
+            compressed.clear();
+            return Ok(());
+        }
+
+        self.file.write_all(data).await.map_err(Error::LocalIo)
+    }
+
+    async fn finish(mut self) -> Result<(), Error> {
+        #[cfg(feature = "gzip")]
+        if let Some(encoder) = self.encoder.take() {
+            let compressed = encoder.finish().map_err(Error::LocalIo)?;
+            self.file
+                .write_all(&compressed)
+                .await
+                .map_err(Error::LocalIo)?;
+        }
+
+        self.file.flush().await.map_err(Error::LocalIo)
+    }
+}
+
+/// Read `reader` to the end, writing its data to files named after `base`
+/// as set by `options`.
+///
+/// At least one file is written, even if `reader` is empty.
+pub(crate) async fn capture_stream<R: AsyncRead + Unpin>(
+    mut reader: R,
+    base: &Path,
+    options: &CaptureOptions,
+) -> Result<CapturedStream, Error> {
+    let limit = match options.rotate_mb {
+        0 => u64::MAX,
+        mb => mb.saturating_mul(1 << 20),
+    };
+    let path = |index: usize| {
+        let mut path = match options.rotate_mb {
+            0 => base.to_owned(),
+            _ => with_suffix(base, &format!(".{}", index)),
+        };
+        if options.compressed() {
+            path = with_suffix(&path, ".gz");
+        }
+        path
+    };
+
+    let mut captured = CapturedStream::default();
+    let mut segment: Option<(Segment, u64)> = None;
+    let mut buf = [0; 8192];
+
+    loop {
+        let n = reader.read(&mut buf).await.map_err(Error::ChildIo)?;
+        if n == 0 {
+            break;
+        }
+
+        let mut chunk = &buf[..n];
+        while !chunk.is_empty() {
+            let (current, written) = match &mut segment {
+                Some(segment) => segment,
+                None => {
+                    let path = path(captured.files.len());
+                    let current = Segment::create(&path, options.compressed()).await?;
+                    captured.files.push(path);
+                    segment.insert((current, 0))
+                }
+            };
+
+            let len = (limit - *written).min(chunk.len() as u64) as usize;
+            current.write(&chunk[..len]).await?;
+            *written += len as u64;
+            captured.len += len as u64;
+            chunk = &chunk[len..];
+
+            if *written == limit {
+                if let Some((full, _)) = segment.take() {
+                    full.finish().await?;
+                }
+            }
+        }
+    }
+
+    match segment {
+        Some((current, _)) => current.finish().await?,
+        None if captured.files.is_empty() => {
+            let path = path(0);
+            Segment::create(&path, options.compressed())
+                .await?
+                .finish()
+                .await?;
+            captured.files.push(path);
+        }
+        None => (),
+    }
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capture_stream, CaptureOptions};
+
+    use std::fs;
+
+    #[tokio::test]
+    async fn capture_stream_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("out");
+        let data: Vec<u8> = (0..(5 << 19) as u32).map(|i| i as u8).collect();
+
+        let options = CaptureOptions::new();
+        let captured = capture_stream(&data[..], &base, &options).await.unwrap();
+        assert_eq!(captured.len, data.len() as u64);
+        assert_eq!(captured.files, [base.clone()]);
+        assert_eq!(fs::read(&base).unwrap(), data);
+
+        // 2.5 MiB in files of 1 MiB.
+        let options = CaptureOptions::new().rotate_mb(1);
+        let captured = capture_stream(&data[..], &base, &options).await.unwrap();
+        assert_eq!(captured.files.len(), 3);
+        let mut written = Vec::new();
+        for (index, path) in captured.files.iter().enumerate() {
+            assert_eq!(path, &dir.path().join(format!("out.{}", index)));
+            written.extend(fs::read(path).unwrap());
+        }
+        assert_eq!(fs::metadata(&captured.files[0]).unwrap().len(), 1 << 20);
+        assert_eq!(written, data);
+
+        let captured = capture_stream(&[][..], &base, &options).await.unwrap();
+        assert_eq!(captured.len, 0);
+        assert_eq!(captured.files, [dir.path().join("out.0")]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn capture_stream_gzip() {
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("out");
+        let data = b"line\n".repeat(1 << 19);
+
+        let options = CaptureOptions::new().compress(true).rotate_mb(1);
+        let captured = capture_stream(&data[..], &base, &options).await.unwrap();
+        assert_eq!(captured.len, data.len() as u64);
+        let names: Vec<_> = captured
+            .files
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap())
+            .collect();
+        assert_eq!(names, ["out.0.gz", "out.1.gz", "out.2.gz"]);
+
+        let mut written = Vec::new();
+        for path in &captured.files {
+            let compressed = fs::read(path).unwrap();
+            assert!(compressed.len() < 1 << 20);
+            MultiGzDecoder::new(&compressed[..])
+                .read_to_end(&mut written)
+                .unwrap();
+        }
+        assert_eq!(written, data);
+    }
+}
@@ -2,6 +2,154 @@
 use crate::*;
 
 /// TODO: RENAME THIS INTO THE NEXT VERSION BEFORE RELEASE
+///
+/// ## Added
+///  - Add new fn [`OwningCommand::chaff_keepalive`]
+///  - Add new fns [`Session::state_dir`] and [`Session::detach_to`]
+///  - Add new fn [`OwningCommand::trampoline_threshold`]
+///  - Add new fn [`preflight`]
+///  - Add new fns [`Error::is_transient`], [`Error::is_auth_failure`] and
+///    [`Error::is_not_found`]
+///  - Add new fns [`Session::export_recipe`] and [`SessionBuilder::from_recipe`],
+///    along with [`ConnectionRecipe`], which is serializable with the new
+///    `serde` feature
+///  - Add new fn [`SessionBuilder::throttled_retries`] to retry connecting
+///    when throttled by the server, and new variant [`Error::ServerThrottled`]
+///  - Add new fn [`SessionBuilder::resolve_to`]
+///  - Add new feature `env-config` to read [`SessionBuilder`] defaults from
+///    `OPENSSH_RS_*` environment variables, and new fn
+///    [`SessionBuilder::connect_default`] using the backend they select
+///  - Add new fns [`Session::is_degraded`] and [`Session::last_error`]
+///  - Add new fn [`OwningCommand::setsid`]
+///  - Add new module [`prelude`] and alias [`Result`]
+///  - Add new fn [`SessionBuilder::master_priority`] along with [`IoPriority`]
+///  - Add new fn [`Child::try_wait`]
+///  - Add new fns [`OwningCommand::env`] and [`OwningCommand::envs`]
+///  - Add new fns [`OwningCommand::output_in`] and
+///    [`Child::wait_with_output_in`], along with trait [`BufferPool`]
+///  - Add new fn [`SessionBuilder::config`], along with [`ConfigWriter`] and
+///    [`HostConfig`] to generate a ssh_config fragment
+///  - Add new fns [`Session::master_log`], [`Session::master_events`] and
+///    [`SessionBuilder::master_verbosity`], along with [`MasterEvents`],
+///    [`MasterEvent`], [`MasterEventKind`] and [`LogLevel`]
+///  - Add new fns [`SessionBuilder::concurrency_budget`] and
+///    [`Session::set_concurrency_budget`], along with [`ConcurrencyBudget`]
+///  - Add new fns [`SessionBuilder::keepalive_interval`] and
+///    [`Session::subscribe_health`], along with [`ConnectionHealth`]
+///  - Add new type [`ReconnectingSession`], along with [`RetryPolicy`]
+///  - Add new fns [`OwningCommand::request_pty`] and
+///    [`Session::notify_window_change`], along with [`PtyOptions`]
+///  - Add new fns [`OwningCommand::capture_head_tail`] and
+///    [`Child::wait_with_head_tail`], along with [`SampledOutput`] and
+///    [`Sample`]
+///  - Add new fn [`Session::shell_interactive`], along with
+///    [`InteractiveShell`] and [`ShellSignal`]
+///  - Add new fn [`Session::scp`] along with [`Scp`], to transfer files
+///    without the sftp subsystem
+///  - Add new variant [`Error::LocalIo`]
+///  - Add new fn [`SessionBuilder::remote_command_mode`] and
+///    [`Session::set_remote_command_mode`], along with [`RemoteCommandMode`]
+///    and [`Error::CommandRejected`]
+///  - Add new fn [`SessionBuilder::control_socket_label`]
+///  - Add new fn [`SessionBuilder::control_socket_path`]
+///  - Add new fns [`Session::attach`], [`Session::attach_mux`] and
+///    [`Session::ownership`], along with [`Ownership`]
+///  - Add new fns [`OwningCommand::locale`], [`OwningCommand::timezone`] and
+///    [`OwningCommand::force_env`]
+///  - Add new fn [`Session::builder_snapshot`]
+///  - Add new fns [`OwningCommand::stdin_from_remote_file`],
+///    [`OwningCommand::stdout_to_remote_file`] and
+///    [`OwningCommand::stderr_to_remote_file`]
+///  - Add new fn [`OwningCommand::pipe`]
+///  - Implement `From<UnixStream>` for [`Stdio`]
+///  - Add new fn [`SessionBuilder::find_existing_master`]
+///  - Add new fn [`Session::info`] along with [`SessionInfo`]
+///  - Add new module [`fleet`] with [`fleet::Report`] to aggregate the
+///    results of a command across many hosts
+///  - Add new fn [`Session::execute_plan`] along with [`Plan`], [`PlanError`]
+///    and [`StepFuture`]
+///  - Add new fns [`SessionBuilder::forward_agent`] and
+///    [`OwningCommand::forward_agent`]
+///  - Add new fn [`SessionBuilder::identity_from_memory`]
+///  - Add new fns [`SessionBuilder::forward_x11`] and
+///    [`OwningCommand::forward_x11`]
+///  - Add new fn [`OwningCommand::max_output_size`] along with
+///    [`Error::OutputTooLarge`]
+///  - Add new fn [`Session::measure_latency`] along with [`Latency`]
+///  - Add new fn [`OwningCommand::stream`] along with [`OutputStream`] and
+///    [`OutputChunk`]
+///  - Add new fn [`OwningCommand::stdin_transform`]
+///  - Add new fns [`SessionBuilder::batch_mode`] and
+///    [`SessionBuilder::ssh_askpass`]
+///  - Add new type [`ForwardGuard`]
+///  - Add new fn [`Session::remote_lock`] along with [`RemoteLock`]
+///  - Add new fn [`OwningCommand::capture_to_file`] along with
+///    [`CaptureOptions`], [`CapturedOutput`] and [`CapturedStream`], and new
+///    feature `gzip` to compress the files
+///  - Add new fn [`Session::open_direct_tcpip`] along with [`DirectTcpip`]
+///  - Add new fn [`Session::open_direct_streamlocal`] along with
+///    [`DirectStreamlocal`]
+///  - Add new fn [`SessionBuilder::pinned_host_key`]
+///  - Add new traits [`RemoteExecutor`] and [`FileTransfer`], along with
+///    [`ExecFuture`]
+///  - Add new fn [`Error::connect_error`] along with [`SshConnectError`]
+///  - Add new fns [`SessionBuilder::metrics_sink`] and
+///    [`Session::set_metrics_sink`] along with [`MetricsSink`], and new
+///    feature `metrics` with [`MetricsFacade`]
+///  - Add new fns [`SessionBuilder::remote_shell`] and
+///    [`Session::set_remote_shell`] along with [`RemoteShell`], to escape
+///    arguments for csh, PowerShell, `cmd.exe` or programs splitting their
+///    command line themselves, and new variant
+///    [`Error::UnsupportedShellSyntax`]
+///  - Add new fn [`available_backends`] along with [`Backend`] and
+///    [`Error::BackendUnavailable`]
+///  - Add new module [`escape`] with the escaping used for each
+///    [`RemoteShell`]
+///  - Add new fn [`OwningCommand::priority`] along with [`Priority`]
+///  - Add new fn [`Error::transfer_error`] along with [`TransferError`]
+///  - Add new fn [`OverSsh::over_ssh_with_opts`] along with
+///    [`TranslateOpts`], [`EnvStrategy`] and [`CwdStrategy`]
+///  - Add new fns [`FileOps::chown`] and [`FileOps::user_id`]
+///  - Add new feature `harness` with new module [`harness`], along with
+///    [`harness::TestServer`] to run integration tests against a disposable
+///    sshd
+///  - Add new fns [`Session::stop_accepting`] and [`Session::terminate`]
+///  - Add new fn [`Session::file_ops`] along with [`FileOps`]
+///  - Add new variant [`Error::SessionClosing`], returned by new commands
+///    and port forwarding requests once [`Session::stop_accepting`] is called
+///  - Add new fn [`Session::wait_idle`]
+///  - Add new fn [`broadcast`] along with [`Broadcast`]
+///  - Add new feature `encoding` with new fn
+///    [`OwningCommand::output_encoding`], along with [`DecodeErrors`] and
+///    [`Error::InvalidOutputEncoding`]
+///  - Add new type [`SessionPool`] to manage the sessions to many hosts
+///  - Add new fns [`SessionBuilder::jump_host`] and
+///    [`SessionBuilder::proxy_command`], along with [`JumpHost`]
+///  - Add new fn [`Session::schedule`] along with [`ScheduledJob`],
+///    [`Scheduler`], [`JobStatus`] and [`When`]
+///  - Add new fn [`Session::compat`] along with [`Compat`] and [`Userland`]
+///  - Add new fn [`Session::master_log_reader`] along with [`MasterLogReader`]
+///  - Add new variants [`MasterEventKind::HostKey`],
+///    [`MasterEventKind::CipherNegotiated`] and [`MasterEventKind::Warning`]
+/// ## Changed
+///  - Connecting fails with [`Error::ServerThrottled`] instead of
+///    [`Error::Connect`] when the server closes the connection before
+///    sending its banner, e.g. once `MaxStartups` is exceeded
+///  - [`Session::request_port_forward`] now returns a [`ForwardGuard`], which
+///    closes the forwarding once dropped, and supports port 0 for local
+///    forwardings
+///  - A jump host or proxy command failing to reach the host is no longer
+///    reported as [`Error::ServerThrottled`]
+///  - Connecting, and attaching with the `process-mux` backend, fail with
+///    [`Error::BackendUnavailable`] when the local `ssh` cannot be executed
+///  - Shutting down [`ChildStdin`] now closes the pipe, so that the remote
+///    child receives EOF without the handle having to be dropped
+///  - With the `process-mux` backend, cancelling a port forwarding request
+///    or [`Session::check`] now kills the `ssh -O` process it started
+///  - With the `tracing` feature, commands, port forwarding requests and
+///    connecting and closing sessions are instrumented with `debug` spans,
+///    with the same fields for both backends, instead of only logging the
+///    command being run
 #[doc(hidden)]
 pub mod unreleased {}
 
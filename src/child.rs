@@ -1,9 +1,18 @@
-use super::{ChildStderr, ChildStdin, ChildStdout, Error};
+use super::budget::BudgetPermit;
+use super::capture::{capture_stream, with_suffix, CaptureOptions, CapturedOutput, CapturedStream};
+use super::metrics_sink::{Meter, Metrics};
+use super::sample::{read_sample, Sample, SampledOutput};
+use super::session::{ChannelGuard, Health};
+use super::{BufferPool, ChildStderr, ChildStdin, ChildStdout, Error};
 
 use std::io;
+use std::path::Path;
 use std::process::{ExitStatus, Output};
+use std::sync::Arc;
+use std::time::Instant;
 
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinHandle;
 use tokio::try_join;
 
 #[derive(Debug)]
@@ -40,6 +49,40 @@ macro_rules! delegate {
     }};
 }
 
+/// Read `stream` until EOF into `buf`, failing with
+/// [`Error::OutputTooLarge`] once more than `max` bytes are read.
+async fn read_to_end_capped<R>(
+    stream: R,
+    buf: &mut Vec<u8>,
+    max: Option<usize>,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+{
+    // Read one more byte than allowed to tell whether there is more.
+    let limit = max.map_or(u64::MAX, |max| (max as u64).saturating_add(1));
+    let read = stream
+        .take(limit)
+        .read_to_end(buf)
+        .await
+        .map_err(Error::ChildIo)?;
+
+    match max {
+        Some(max) if read > max => Err(Error::OutputTooLarge),
+        _ => Ok(()),
+    }
+}
+
+/// Aborts the background task it holds once dropped.
+#[derive(Debug)]
+pub(crate) struct AbortOnDrop<T = ()>(pub(crate) JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 /// Representation of a running or exited remote child process.
 ///
 /// This structure is used to represent and manage remote child
@@ -80,10 +123,25 @@ macro_rules! delegate {
 pub struct Child<S> {
     session: S,
     imp: RemoteChildImp,
+    health: Arc<Health>,
 
     stdin: Option<ChildStdin>,
     stdout: Option<ChildStdout>,
     stderr: Option<ChildStderr>,
+
+    keepalive: Option<AbortOnDrop>,
+    /// Slot of the [`ConcurrencyBudget`](crate::ConcurrencyBudget), if any,
+    /// released on drop.
+    permit: Option<BudgetPermit>,
+    /// Released once the child exits or is dropped.
+    channel: Option<ChannelGuard>,
+    /// Set with [`OwningCommand::max_output_size`](crate::OwningCommand::max_output_size).
+    max_output_size: Option<usize>,
+    /// Task set up by [`OwningCommand::stdin_transform`](crate::OwningCommand::stdin_transform).
+    stdin_pump: Option<AbortOnDrop<Result<(), Error>>>,
+    /// Sink of the session and spawn time, reported to once the child
+    /// exits.
+    metrics: Option<(Metrics, Instant)>,
 }
 
 impl<S> Child<S> {
@@ -95,6 +153,7 @@ impl<S> Child<S> {
             Option<ChildStdout>,
             Option<ChildStderr>,
         ),
+        health: Arc<Health>,
     ) -> Self {
         Self {
             session,
@@ -102,9 +161,57 @@ impl<S> Child<S> {
             stdout,
             stderr,
             imp,
+            channel: Some(health.open_channel()),
+            health,
+
+            keepalive: None,
+            permit: None,
+            max_output_size: None,
+            stdin_pump: None,
+            metrics: None,
         }
     }
 
+    pub(crate) fn with_permit(mut self, permit: Option<BudgetPermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    pub(crate) fn with_max_output_size(mut self, max_output_size: Option<usize>) -> Self {
+        self.max_output_size = max_output_size;
+        self
+    }
+
+    pub(crate) fn with_stdin_pump(mut self, pump: JoinHandle<Result<(), Error>>) -> Self {
+        self.stdin_pump = Some(AbortOnDrop(pump));
+        self
+    }
+
+    pub(crate) fn with_metrics(mut self, metrics: Option<Metrics>) -> Self {
+        if let Some(metrics) = metrics {
+            metrics.0.command_spawned();
+            if let Some(stdin) = &mut self.stdin {
+                stdin.set_meter(Meter::stdin(metrics.clone()));
+            }
+            if let Some(stdout) = &mut self.stdout {
+                stdout.set_meter(Meter::stdout(metrics.clone()));
+            }
+            self.metrics = Some((metrics, Instant::now()));
+        }
+        self
+    }
+
+    fn record_exit(&mut self) {
+        if let Some((metrics, spawned)) = self.metrics.take() {
+            metrics.0.command_exited(spawned.elapsed());
+        }
+    }
+
+    pub(crate) fn with_keepalive(mut self, keepalive: JoinHandle<()>) -> Self {
+        self.keepalive = Some(AbortOnDrop(keepalive));
+        self
+    }
+
     /// Disconnect from this given remote child process.
     ///
     /// Note that disconnecting does _not_ kill the remote process, it merely kills the local
@@ -126,7 +233,53 @@ impl<S> Child<S> {
         // it would return EOF and the remote process can exit.
         self.stdin().take();
 
-        delegate!(self.imp, imp, { imp.wait().await })
+        let res = delegate!(self.imp, imp, { imp.wait().await });
+        self.health.record(&res);
+        let status = res?;
+        // `self.imp` is moved out, so `record_exit` cannot be called.
+        if let Some((metrics, spawned)) = self.metrics.take() {
+            metrics.0.command_exited(spawned.elapsed());
+        }
+
+        if let Some(mut pump) = self.stdin_pump.take() {
+            // The remote process may exit without reading all of stdin, in
+            // which case the task is aborted on drop.
+            if pump.0.is_finished() {
+                match (&mut pump.0).await {
+                    Ok(Err(Error::ChildIo(err))) if err.kind() == io::ErrorKind::BrokenPipe => (),
+                    Ok(res) => res?,
+                    Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                    Err(_) => (),
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// Attempts to collect the exit status of the remote child if it has
+    /// already exited, without blocking and without consuming the `Child`.
+    ///
+    /// Returns `Ok(None)` if the remote child is still running, which makes
+    /// it cheap to poll many children from a supervisor loop. Once it
+    /// returns an exit status, further calls (and [`wait`](Child::wait))
+    /// return the same status.
+    ///
+    /// Unlike [`wait`](Child::wait), stdin is not closed.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        // Not using `delegate!`, which would not compile without any backend.
+        let res = match self.imp {
+            #[cfg(feature = "process-mux")]
+            RemoteChildImp::ProcessImpl(ref mut imp) => imp.try_wait(),
+
+            #[cfg(feature = "native-mux")]
+            RemoteChildImp::NativeMuxImpl(ref mut imp) => imp.try_wait(),
+        };
+        self.health.record(&res);
+        if let Ok(Some(_)) = res {
+            self.channel = None;
+            self.record_exit();
+        }
+        res
     }
 
     /// Simultaneously waits for the remote child to exit and collect all remaining output on the
@@ -139,18 +292,44 @@ impl<S> Child<S> {
     /// By default, stdin, stdout and stderr are inherited from the parent. In order to capture the
     /// output into this `Result<Output>` it is necessary to create new pipes between parent and
     /// child. Use `stdout(Stdio::piped())` or `stderr(Stdio::piped())`, respectively.
-    pub async fn wait_with_output(mut self) -> Result<Output, Error> {
+    ///
+    /// The size of the output is limited by
+    /// [`OwningCommand::max_output_size`](crate::OwningCommand::max_output_size).
+    pub async fn wait_with_output(self) -> Result<Output, Error> {
+        self.wait_with_output_into(Vec::new(), Vec::new()).await
+    }
+
+    /// Same as [`wait_with_output`](Child::wait_with_output), except that
+    /// stdout and stderr are read into buffers taken from `pool`.
+    ///
+    /// The buffers are returned in the resulting [`Output`] and can be given
+    /// back to the pool with [`BufferPool::put`] once processed. On error,
+    /// they are dropped.
+    pub async fn wait_with_output_in<P>(self, pool: &P) -> Result<Output, Error>
+    where
+        P: BufferPool + ?Sized,
+    {
+        let mut stdout = pool.get();
+        stdout.clear();
+        let mut stderr = pool.get();
+        stderr.clear();
+
+        self.wait_with_output_into(stdout, stderr).await
+    }
+
+    async fn wait_with_output_into(
+        mut self,
+        mut stdout: Vec<u8>,
+        mut stderr: Vec<u8>,
+    ) -> Result<Output, Error> {
         self.stdin().take();
 
+        let max = self.max_output_size;
+
         let child_stdout = self.stdout.take();
         let stdout_read = async move {
-            let mut stdout = Vec::new();
-
-            if let Some(mut child_stdout) = child_stdout {
-                child_stdout
-                    .read_to_end(&mut stdout)
-                    .await
-                    .map_err(Error::ChildIo)?;
+            if let Some(child_stdout) = child_stdout {
+                read_to_end_capped(child_stdout, &mut stdout, max).await?;
             }
 
             Ok::<_, Error>(stdout)
@@ -158,13 +337,8 @@ impl<S> Child<S> {
 
         let child_stderr = self.stderr.take();
         let stderr_read = async move {
-            let mut stderr = Vec::new();
-
-            if let Some(mut child_stderr) = child_stderr {
-                child_stderr
-                    .read_to_end(&mut stderr)
-                    .await
-                    .map_err(Error::ChildIo)?;
+            if let Some(child_stderr) = child_stderr {
+                read_to_end_capped(child_stderr, &mut stderr, max).await?;
             }
 
             Ok::<_, Error>(stderr)
@@ -186,6 +360,86 @@ impl<S> Child<S> {
         })
     }
 
+    /// Same as [`wait_with_output`](Child::wait_with_output), except that only
+    /// the first `head_len` and the last `tail_len` bytes of stdout and
+    /// stderr are retained, along with the number of bytes dropped between
+    /// them.
+    pub async fn wait_with_head_tail(
+        mut self,
+        head_len: usize,
+        tail_len: usize,
+    ) -> Result<SampledOutput, Error> {
+        self.stdin().take();
+
+        let child_stdout = self.stdout.take();
+        let stdout_read = async move {
+            match child_stdout {
+                Some(child_stdout) => read_sample(child_stdout, head_len, tail_len)
+                    .await
+                    .map_err(Error::ChildIo),
+                None => Ok(Sample::default()),
+            }
+        };
+
+        let child_stderr = self.stderr.take();
+        let stderr_read = async move {
+            match child_stderr {
+                Some(child_stderr) => read_sample(child_stderr, head_len, tail_len)
+                    .await
+                    .map_err(Error::ChildIo),
+                None => Ok(Sample::default()),
+            }
+        };
+
+        // See `wait_with_output_into`.
+        let (stdout, stderr) = try_join!(stdout_read, stderr_read)?;
+        Ok(SampledOutput {
+            status: self.wait().await?,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Same as [`wait_with_output`](Child::wait_with_output), except that
+    /// stdout and stderr are written to the local files `{path}.stdout` and
+    /// `{path}.stderr` as they arrive, instead of being kept in memory.
+    ///
+    /// See [`OwningCommand::capture_to_file`](crate::OwningCommand::capture_to_file)
+    /// for details.
+    pub async fn wait_to_files(
+        mut self,
+        path: &Path,
+        options: &CaptureOptions,
+    ) -> Result<CapturedOutput, Error> {
+        self.stdin().take();
+
+        let child_stdout = self.stdout.take();
+        let stdout_base = with_suffix(path, ".stdout");
+        let stdout_write = async {
+            match child_stdout {
+                Some(child_stdout) => capture_stream(child_stdout, &stdout_base, options).await,
+                None => Ok(CapturedStream::default()),
+            }
+        };
+
+        let child_stderr = self.stderr.take();
+        let stderr_base = with_suffix(path, ".stderr");
+        let stderr_write = async {
+            match child_stderr {
+                Some(child_stderr) => capture_stream(child_stderr, &stderr_base, options).await,
+                None => Ok(CapturedStream::default()),
+            }
+        };
+
+        // See `wait_with_output_into`.
+        let (stdout, stderr) = try_join!(stdout_write, stderr_write)?;
+        Ok(CapturedOutput {
+            status: self.wait().await?,
+            stdout,
+            stderr,
+        })
+    }
+
     /// Access the handle for reading from the remote child's standard input (stdin), if requested.
     pub fn stdin(&mut self) -> &mut Option<ChildStdin> {
         &mut self.stdin
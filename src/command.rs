@@ -1,14 +1,31 @@
-use crate::escape::escape;
+use crate::escape;
 
 use super::child::Child;
-use super::stdio::TryFromChildIo;
+use super::metrics_sink::Metrics;
+use super::session::Health;
+use super::stdio::{StdioImpl, TryFromChildIo};
+use super::trace::{Op, Target};
 use super::Stdio;
-use super::{Error, Session};
+#[cfg(feature = "encoding")]
+use super::{encoding::OutputEncoding, DecodeErrors};
+use super::{
+    BufferPool, CaptureOptions, CapturedOutput, ChildStdin, ConcurrencyBudget, Error, OutputStream,
+    Priority, RemoteCommandMode, RemoteShell, SampledOutput, Session,
+};
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::fmt;
 use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::process;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::JoinHandle;
+use tokio::time;
 
 #[derive(Debug)]
 pub(crate) enum CommandImp {
@@ -52,6 +69,58 @@ macro_rules! delegate {
     }};
 }
 
+/// Wrap the remote command `cmd` so that `sh -s` reads all of it before
+/// running it, and exits afterwards instead of reading more commands
+/// from stdin.
+fn trampoline_script(cmd: &[u8]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(cmd.len() + 16);
+    script.extend_from_slice(b"{ ");
+    script.extend_from_slice(cmd);
+    script.extend_from_slice(b"\n}; exit $?\n");
+    script
+}
+
+/// How [`OverSsh::over_ssh_with_opts`] carries the environment variables of
+/// the source command over to the remote command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EnvStrategy {
+    /// Fail with [`Error::CommandHasEnv`], as [`OverSsh::over_ssh`] does.
+    #[default]
+    Reject,
+
+    /// Run the program with `env`, e.g. `env -u OLD KEY=VAL program`.
+    PrefixEnvCommand,
+}
+
+/// How [`OverSsh::over_ssh_with_opts`] carries the current directory of the
+/// source command over to the remote command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CwdStrategy {
+    /// Fail with [`Error::CommandHasCwd`], as [`OverSsh::over_ssh`] does.
+    #[default]
+    Reject,
+
+    /// Change to the directory first, e.g. `cd dir && program`, so that the
+    /// program is not run if the directory does not exist.
+    CdAnd,
+}
+
+/// How [`OverSsh::over_ssh_with_opts`] translates what ssh cannot carry
+/// over, both rejected by default.
+///
+/// The translations are escaped for, and run by, a POSIX shell on the
+/// remote host, regardless of [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TranslateOpts {
+    /// How the environment variables are carried over.
+    pub env: EnvStrategy,
+
+    /// How the current directory is carried over.
+    pub cwd: CwdStrategy,
+}
+
 /// If a command is `OverSsh` then it can be executed over an SSH session.
 ///
 /// Primarily a way to allow `std::process::Command` to be turned directly into an `openssh::Command`.
@@ -62,9 +131,9 @@ pub trait OverSsh {
     ///
     /// The command to be executed on the remote machine should not explicitly
     /// set environment variables or the current working directory. It errors if the source command
-    /// has environment variables or a current working directory set, since `openssh` doesn't (yet) have
-    /// a method to set environment variables and `ssh` doesn't support setting a current working directory
-    /// outside of `bash/dash/zsh` (which is not always available).
+    /// has environment variables or a current working directory set, since `ssh` can only carry
+    /// them over through the remote shell, which [`over_ssh_with_opts`](Self::over_ssh_with_opts)
+    /// does on request.
     ///
     /// ###  Examples
     ///
@@ -117,6 +186,46 @@ pub trait OverSsh {
         &self,
         session: S,
     ) -> Result<OwningCommand<S>, crate::Error>;
+
+    /// Like [`over_ssh`](Self::over_ssh), but translate the environment
+    /// variables and the current working directory of the command as
+    /// requested by `opts` instead of failing.
+    ///
+    /// ```no_run
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     use std::process::Command;
+    ///     use openssh::{CwdStrategy, EnvStrategy, KnownHosts, OverSsh, Session, TranslateOpts};
+    ///
+    ///     let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///     let opts = TranslateOpts {
+    ///         env: EnvStrategy::PrefixEnvCommand,
+    ///         cwd: CwdStrategy::CdAnd,
+    ///     };
+    ///     // Runs `cd /tmp && env MY_ENV_VAR=foo printenv MY_ENV_VAR`.
+    ///     let printenv = Command::new("printenv")
+    ///         .arg("MY_ENV_VAR")
+    ///         .env("MY_ENV_VAR", "foo")
+    ///         .current_dir("/tmp")
+    ///         .over_ssh_with_opts(&session, opts)?
+    ///         .output()
+    ///         .await?;
+    ///     assert_eq!(printenv.stdout, b"foo\n");
+    ///
+    /// #   Ok(())
+    /// }
+    /// ```
+    ///
+    /// By default, `opts` is ignored and this is the same as
+    /// [`over_ssh`](Self::over_ssh).
+    fn over_ssh_with_opts<S: Deref<Target = Session> + Clone>(
+        &self,
+        session: S,
+        opts: TranslateOpts,
+    ) -> Result<OwningCommand<S>, crate::Error> {
+        let _ = opts;
+        self.over_ssh(session)
+    }
 }
 
 impl OverSsh for std::process::Command {
@@ -124,20 +233,60 @@ impl OverSsh for std::process::Command {
         &self,
         session: S,
     ) -> Result<OwningCommand<S>, crate::Error> {
+        self.over_ssh_with_opts(session, TranslateOpts::default())
+    }
+
+    fn over_ssh_with_opts<S: Deref<Target = Session> + Clone>(
+        &self,
+        session: S,
+        opts: TranslateOpts,
+    ) -> Result<OwningCommand<S>, crate::Error> {
+        let mut prefix: Vec<Cow<'_, OsStr>> = Vec::new();
+
         // I'd really like `!self.get_envs().is_empty()` here, but that's
         // behind a `exact_size_is_empty` feature flag.
-        if self.get_envs().len() > 0 {
+        let has_env = self.get_envs().len() > 0;
+        if has_env && opts.env == EnvStrategy::Reject {
             return Err(crate::Error::CommandHasEnv);
         }
 
-        if self.get_current_dir().is_some() {
-            return Err(crate::Error::CommandHasCwd);
+        if let Some(dir) = self.get_current_dir() {
+            if opts.cwd == CwdStrategy::Reject {
+                return Err(crate::Error::CommandHasCwd);
+            }
+            prefix.push(Cow::Borrowed(OsStr::new("cd")));
+            prefix.push(escape::posix(dir.as_os_str()));
+            prefix.push(Cow::Borrowed(OsStr::new("&&")));
         }
 
-        let program_escaped: Cow<'_, OsStr> = escape(self.get_program());
-        let mut command = Session::to_raw_command(session, program_escaped);
+        if has_env {
+            prefix.push(Cow::Borrowed(OsStr::new("env")));
+            // The options of `env` must come before the assignments.
+            for (key, _) in self.get_envs().filter(|(_, value)| value.is_none()) {
+                prefix.push(Cow::Borrowed(OsStr::new("-u")));
+                prefix.push(escape::posix(key));
+            }
+            for (key, value) in self.get_envs() {
+                if let Some(value) = value {
+                    let mut assignment = key.to_os_string();
+                    assignment.push("=");
+                    assignment.push(value);
+                    prefix.push(Cow::Owned(escape::posix(&assignment).into_owned()));
+                }
+            }
+        }
 
-        let args = self.get_args().map(escape);
+        let program_escaped: Cow<'_, OsStr> = escape::posix(self.get_program());
+        let mut command = match prefix.split_first() {
+            Some((first, rest)) => {
+                let mut command = Session::to_raw_command(session, first);
+                command.raw_args(rest).raw_arg(program_escaped);
+                command
+            }
+            None => Session::to_raw_command(session, program_escaped),
+        };
+
+        let args = self.get_args().map(escape::posix);
         command.raw_args(args);
         Ok(command)
     }
@@ -150,6 +299,14 @@ impl OverSsh for tokio::process::Command {
     ) -> Result<OwningCommand<S>, crate::Error> {
         self.as_std().over_ssh(session)
     }
+
+    fn over_ssh_with_opts<S: Deref<Target = Session> + Clone>(
+        &self,
+        session: S,
+        opts: TranslateOpts,
+    ) -> Result<OwningCommand<S>, crate::Error> {
+        self.as_std().over_ssh_with_opts(session, opts)
+    }
 }
 
 impl<S> OverSsh for &S
@@ -162,6 +319,14 @@ where
     ) -> Result<OwningCommand<U>, crate::Error> {
         <S as OverSsh>::over_ssh(self, session)
     }
+
+    fn over_ssh_with_opts<U: Deref<Target = Session> + Clone>(
+        &self,
+        session: U,
+        opts: TranslateOpts,
+    ) -> Result<OwningCommand<U>, crate::Error> {
+        <S as OverSsh>::over_ssh_with_opts(self, session, opts)
+    }
 }
 
 impl<S> OverSsh for &mut S
@@ -174,6 +339,105 @@ where
     ) -> Result<OwningCommand<U>, crate::Error> {
         <S as OverSsh>::over_ssh(self, session)
     }
+
+    fn over_ssh_with_opts<U: Deref<Target = Session> + Clone>(
+        &self,
+        session: U,
+        opts: TranslateOpts,
+    ) -> Result<OwningCommand<U>, crate::Error> {
+        <S as OverSsh>::over_ssh_with_opts(self, session, opts)
+    }
+}
+
+/// Write the lines of `reader` to `stdin` once transformed by `transform`.
+async fn pump_stdin<R, F>(
+    mut reader: R,
+    mut transform: F,
+    mut stdin: ChildStdin,
+) -> Result<(), Error>
+where
+    R: AsyncBufRead + Unpin,
+    F: FnMut(String) -> String,
+{
+    let mut line = String::new();
+    loop {
+        if reader.read_line(&mut line).await.map_err(Error::LocalIo)? == 0 {
+            break;
+        }
+
+        let newline = line.ends_with('\n');
+        if newline {
+            line.pop();
+        }
+        let mut transformed = transform(std::mem::take(&mut line));
+        if newline {
+            transformed.push('\n');
+        }
+        stdin
+            .write_all(transformed.as_bytes())
+            .await
+            .map_err(Error::ChildIo)?;
+    }
+    stdin.flush().await.map_err(Error::ChildIo)
+}
+
+type StdinPump = Box<dyn FnOnce(ChildStdin) -> JoinHandle<Result<(), Error>> + Send>;
+
+/// Forward what is read from `reader` to `stdin`, writing `filler` whenever
+/// nothing was read for `interval`, until `reader` reaches EOF.
+async fn forward_with_chaff<R>(
+    mut reader: R,
+    mut stdin: ChildStdin,
+    interval: Duration,
+    filler: Vec<u8>,
+) where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = vec![0; 8192];
+    loop {
+        let res = match time::timeout(interval, reader.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => break,
+            Ok(Ok(n)) => stdin.write_all(&buf[..n]).await,
+            Err(_) => stdin.write_all(&filler).await,
+        };
+        // The remote process closed its stdin, which the writes to the
+        // local pipe then report once it is dropped.
+        if res.is_err() {
+            return;
+        }
+    }
+    let _ = stdin.shutdown().await;
+}
+
+/// Spawns the task set up by [`OwningCommand::stdin_transform`].
+///
+/// The mutex keeps [`OwningCommand`] `Sync`.
+struct StdinTransform(Mutex<StdinPump>);
+
+impl fmt::Debug for StdinTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StdinTransform(..)")
+    }
+}
+
+/// Options for the pseudo-terminal requested with
+/// [`OwningCommand::request_pty`].
+#[derive(Debug, Clone, Default)]
+pub struct PtyOptions {
+    term: Option<String>,
+}
+
+impl PtyOptions {
+    /// Create the default options, which send the `TERM` of this process.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the terminal type, i.e. the value of `TERM` on the remote side.
+    pub fn term(mut self, term: impl Into<String>) -> Self {
+        self.term = Some(term.into());
+        self
+    }
 }
 
 /// A remote process builder, providing fine-grained control over how a new remote process should
@@ -212,29 +476,134 @@ where
 pub struct OwningCommand<S> {
     session: S,
     imp: CommandImp,
+    health: Arc<Health>,
+    budget: Option<ConcurrencyBudget>,
+    priority: Priority,
 
     stdin_set: bool,
     stdout_set: bool,
     stderr_set: bool,
+
+    stdin_null: bool,
+    stdin_piped: bool,
+
+    chaff_keepalive: Option<(Duration, Vec<u8>)>,
+
+    trampoline_threshold: Option<usize>,
+    /// The remote command, once it is replaced by `sh -s`.
+    trampolined: Option<Vec<u8>>,
+
+    remote_command_mode: RemoteCommandMode,
+    remote_shell: RemoteShell,
+    /// The first helper used which adds shell syntax not understood by
+    /// `remote_shell`.
+    unsupported_syntax: Option<&'static str>,
+    /// The remote command, once it is replaced by the payload of
+    /// [`RemoteCommandMode::ForcedCommandPayload`].
+    wrapped: Option<Vec<u8>>,
+
+    target: Target,
+    metrics: Option<Metrics>,
+
+    max_output_size: Option<usize>,
+    stdin_transform: Option<StdinTransform>,
+
+    #[cfg(feature = "encoding")]
+    output_encoding: Option<OutputEncoding>,
 }
 
 impl<S> OwningCommand<S> {
-    pub(crate) fn new(session: S, imp: CommandImp) -> Self {
+    pub(crate) fn new(
+        session: S,
+        imp: CommandImp,
+        health: Arc<Health>,
+        budget: Option<ConcurrencyBudget>,
+    ) -> Self {
         Self {
             session,
             imp,
+            health,
+            budget,
+            priority: Priority::Normal,
 
             stdin_set: false,
             stdout_set: false,
             stderr_set: false,
+
+            stdin_null: false,
+            stdin_piped: false,
+
+            chaff_keepalive: None,
+
+            trampoline_threshold: None,
+            trampolined: None,
+
+            remote_command_mode: RemoteCommandMode::Command,
+            remote_shell: RemoteShell::Posix,
+            unsupported_syntax: None,
+            wrapped: None,
+
+            target: Target::default(),
+            metrics: None,
+
+            max_output_size: None,
+            stdin_transform: None,
+
+            #[cfg(feature = "encoding")]
+            output_encoding: None,
+        }
+    }
+
+    pub(crate) fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub(crate) fn with_metrics(mut self, metrics: Option<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Start instrumenting operation `op` of this command.
+    fn op(&self, op: &'static str) -> Op {
+        Op::command(&self.target, op, || {
+            match self.trampolined.as_ref().or(self.wrapped.as_ref()) {
+                Some(cmd) => cmd.clone(),
+                None => delegate!(&self.imp, imp, { imp.remote_command() }),
+            }
+        })
+    }
+
+    pub(crate) fn with_remote_command_mode(mut self, mode: RemoteCommandMode) -> Self {
+        self.remote_command_mode = mode;
+        self
+    }
+
+    pub(crate) fn with_remote_shell(mut self, shell: RemoteShell) -> Self {
+        self.remote_shell = shell;
+        self
+    }
+
+    /// Record that `helper` adds POSIX shell syntax to the command, which
+    /// csh also understands if `csh`, so that spawning fails with
+    /// [`Error::UnsupportedShellSyntax`] for the other shells.
+    fn require_posix_syntax(&mut self, helper: &'static str, csh: bool) {
+        let supported = match self.remote_shell {
+            RemoteShell::Posix => true,
+            RemoteShell::Csh => csh,
+            _ => false,
+        };
+        if !supported && self.unsupported_syntax.is_none() {
+            self.unsupported_syntax = Some(helper);
         }
     }
 
     /// Adds an argument to pass to the remote program.
     ///
     /// Before it is passed to the remote host, `arg` is escaped so that special characters aren't
-    /// evaluated by the remote shell. If you do not want this behavior, use
-    /// [`raw_arg`](Self::raw_arg).
+    /// evaluated by the remote shell, as set with
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell). If you do not want
+    /// this behavior, use [`raw_arg`](Self::raw_arg).
     ///
     /// Only one argument can be passed per use. So instead of:
     ///
@@ -255,7 +624,8 @@ impl<S> OwningCommand<S> {
     ///
     /// To pass multiple arguments see [`args`](Self::args).
     pub fn arg<A: AsRef<str>>(&mut self, arg: A) -> &mut Self {
-        self.raw_arg(&*shell_escape::unix::escape(Cow::Borrowed(arg.as_ref())))
+        let shell = self.remote_shell;
+        self.raw_arg(&*shell.escape(OsStr::new(arg.as_ref())))
     }
 
     /// Adds an argument to pass to the remote program.
@@ -267,9 +637,14 @@ impl<S> OwningCommand<S> {
     ///
     /// To pass multiple unescaped arguments see [`raw_args`](Self::raw_args).
     pub fn raw_arg<A: AsRef<OsStr>>(&mut self, arg: A) -> &mut Self {
-        delegate!(&mut self.imp, imp, {
-            imp.raw_arg(arg.as_ref());
-        });
+        if let Some(cmd) = self.trampolined.as_mut().or(self.wrapped.as_mut()) {
+            cmd.push(b' ');
+            cmd.extend_from_slice(arg.as_ref().as_bytes());
+        } else {
+            delegate!(&mut self.imp, imp, {
+                imp.raw_arg(arg.as_ref());
+            });
+        }
         self
     }
 
@@ -310,6 +685,98 @@ impl<S> OwningCommand<S> {
         self
     }
 
+    /// Sets an environment variable for the remote process, which is sent by
+    /// ssh as with `ssh -o SetEnv`.
+    ///
+    /// Unlike prepending `env` to the command, this also works for
+    /// subsystems and is not subject to escaping, but the remote sshd only
+    /// accepts the variables allowed by its `AcceptEnv` and silently drops
+    /// the others. It requires OpenSSH 7.8 or later locally.
+    ///
+    /// This is only supported by the `process-mux` backend: with
+    /// `native-mux`, spawning the command fails with [`Error::CommandHasEnv`].
+    pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        delegate!(&mut self.imp, imp, {
+            imp.env(key.as_ref(), val.as_ref());
+        });
+        self
+    }
+
+    /// Sets multiple environment variables for the remote process.
+    ///
+    /// See [`env`](Self::env) for details.
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self.env(key, val);
+        }
+        self
+    }
+
+    /// Run the remote process with `LC_ALL` set to `locale`, e.g. `C.UTF-8`
+    /// or `C`, so that the output of remote tools, such as dates, numbers
+    /// and sort orders, is the same on every host and can be parsed.
+    ///
+    /// See [`force_env`](Self::force_env) for how the variable is set.
+    pub fn locale(&mut self, locale: &str) -> &mut Self {
+        self.force_env("LC_ALL", locale)
+    }
+
+    /// Run the remote process with `TZ` set to `timezone`, e.g. `UTC`.
+    ///
+    /// See [`force_env`](Self::force_env) for how the variable is set.
+    pub fn timezone(&mut self, timezone: &str) -> &mut Self {
+        self.force_env("TZ", timezone)
+    }
+
+    /// Sets an environment variable for the remote process regardless of
+    /// the `AcceptEnv` of the remote sshd.
+    ///
+    /// Unlike [`env`](Self::env), the command is prefixed with
+    /// `env key=val`, which works with both backends. It thus only applies
+    /// to the remote program, not to shell syntax passed to
+    /// [`Session::raw_command`] such as `a && b`.
+    ///
+    /// Subsystems cannot be prefixed, so the variable is sent with
+    /// [`env`](Self::env) instead, and is subject to its limitations.
+    ///
+    /// This requires a POSIX shell or csh on the remote host, see
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    pub fn force_env(&mut self, key: &str, val: &str) -> &mut Self {
+        let var = format!("{}={}", key, val);
+        let cmd: Option<Vec<u8>> = delegate!(&mut self.imp, imp, { imp.replace_command(b"") });
+
+        match cmd {
+            Some(cmd) => {
+                self.require_posix_syntax("force_env", true);
+
+                let mut prefixed = b"env ".to_vec();
+                let var = self.remote_shell.escape(OsStr::new(&var));
+                prefixed.extend_from_slice(var.as_bytes());
+                prefixed.push(b' ');
+                prefixed.extend_from_slice(&cmd);
+
+                delegate!(&mut self.imp, imp, {
+                    imp.replace_command(&prefixed);
+                });
+            }
+            None => {
+                delegate!(&mut self.imp, imp, {
+                    imp.env(OsStr::new(key), OsStr::new(val));
+                });
+            }
+        }
+        self
+    }
+
     /// Configuration for the remote process's standard input (stdin) handle.
     ///
     /// Defaults to [`inherit`] when used with `spawn` or `status`, and
@@ -318,10 +785,48 @@ impl<S> OwningCommand<S> {
     /// [`inherit`]: struct.Stdio.html#method.inherit
     /// [`null`]: struct.Stdio.html#method.null
     pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        let cfg = cfg.into();
+        self.stdin_null = matches!(cfg.0, StdioImpl::Null);
+        self.stdin_piped = matches!(cfg.0, StdioImpl::Pipe);
+
         delegate!(&mut self.imp, imp, {
-            imp.stdin(cfg.into());
+            imp.stdin(cfg);
         });
         self.stdin_set = true;
+        self.stdin_transform = None;
+        self
+    }
+
+    /// Stream the lines of `reader` into the stdin of the remote process,
+    /// each transformed by `transform`, e.g. to fill in a template or inject
+    /// secrets into a script fed to a remote interpreter, without holding
+    /// the transformed content in memory.
+    ///
+    /// `transform` receives each line without its `\n`, which is added back
+    /// to the line returned. Lines are read lazily as the remote process
+    /// consumes them, by a task spawned along with the process, and stdin
+    /// is closed once `reader` reaches EOF.
+    ///
+    /// This sets stdin to [`Stdio::piped`], but [`Child::stdin`] is then
+    /// `None`. Only the next process spawned is fed, since `reader` is
+    /// consumed.
+    ///
+    /// If `reader` fails or yields invalid UTF-8, stdin is closed early and
+    /// [`Child::wait`], along with the functions using it, returns the
+    /// error instead of the exit status.
+    ///
+    /// [`Child::stdin`]: crate::Child::stdin
+    /// [`Child::wait`]: crate::Child::wait
+    pub fn stdin_transform<R, F>(&mut self, reader: R, transform: F) -> &mut Self
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        F: FnMut(String) -> String + Send + 'static,
+    {
+        self.stdin(Stdio::piped());
+
+        let pump: StdinPump =
+            Box::new(move |stdin| tokio::spawn(pump_stdin(reader, transform, stdin)));
+        self.stdin_transform = Some(StdinTransform(Mutex::new(pump)));
         self
     }
 
@@ -354,13 +859,415 @@ impl<S> OwningCommand<S> {
         self.stderr_set = true;
         self
     }
+
+    /// Make the remote shell read stdin from the remote file `path`, so
+    /// that its content does not go through the connection.
+    ///
+    /// See [`stdout_to_remote_file`](Self::stdout_to_remote_file) for
+    /// details.
+    pub fn stdin_from_remote_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        if self.redirect("stdin_from_remote_file", "<", path.as_ref()) {
+            self.stdin(Stdio::null());
+        }
+        self
+    }
+
+    /// Make the remote shell write stdout to the remote file `path`,
+    /// truncating it, so that large outputs which only need to land on the
+    /// remote disk are not shipped through the connection.
+    ///
+    /// The redirection is appended to the command and thus only applies to
+    /// the remote program, not to shell syntax passed to
+    /// [`Session::raw_command`] such as `a && b`. A relative `path` is
+    /// relative to the home directory of the remote user.
+    ///
+    /// Locally, stdout is set to [`Stdio::null`]. Subsystems cannot be
+    /// redirected, so this has no effect on them.
+    ///
+    /// This requires a POSIX shell or csh on the remote host, see
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    pub fn stdout_to_remote_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        if self.redirect("stdout_to_remote_file", ">", path.as_ref()) {
+            self.stdout(Stdio::null());
+        }
+        self
+    }
+
+    /// Make the remote shell write stderr to the remote file `path`,
+    /// truncating it.
+    ///
+    /// See [`stdout_to_remote_file`](Self::stdout_to_remote_file) for
+    /// details. Unlike the other redirections, this requires a POSIX shell
+    /// on the remote host, since csh cannot redirect stderr alone.
+    pub fn stderr_to_remote_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        if self.redirect("stderr_to_remote_file", "2>", path.as_ref()) {
+            self.stderr(Stdio::null());
+        }
+        self
+    }
+
+    /// Pipe the stdout of this command into the stdin of `next`, as with
+    /// `a | b`, so that the data flows on the remote host without going
+    /// through the connection.
+    ///
+    /// Both are run by the remote shell as a single command, configured
+    /// and spawned through `self`: only the program and arguments of
+    /// `next` are kept, and the exit status is that of `next`. Arguments
+    /// added to `self` afterwards go to `next`. To set variables for
+    /// `next`, call `next.force_env(..)` before passing it to `pipe`:
+    /// [`env`](Self::env) is dropped along with the rest of the
+    /// configuration of `next`, and [`force_env`](Self::force_env) called
+    /// on `self` afterwards only applies to the first program.
+    ///
+    /// Commands running on different sessions can be connected locally
+    /// instead, by converting the [`ChildStdout`](crate::ChildStdout) of
+    /// one to the [`Stdio`] of the other with `Stdio::try_from`.
+    ///
+    /// This requires a POSIX shell or csh on the remote host, see
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `next` does not run on the same session as `self`, or if
+    /// either of them is a subsystem.
+    pub fn pipe(&mut self, mut next: OwningCommand<S>) -> &mut Self {
+        assert!(
+            Arc::ptr_eq(&self.health, &next.health),
+            "piped commands must run on the same session"
+        );
+
+        let next = next.take_remote_command();
+        let (mut cmd, next) = match self.take_remote_command().zip(next) {
+            Some(cmds) => cmds,
+            None => panic!("subsystems cannot be piped"),
+        };
+
+        cmd.extend_from_slice(b" | ");
+        cmd.extend_from_slice(&next);
+        delegate!(&mut self.imp, imp, {
+            imp.replace_command(&cmd);
+        });
+        self.require_posix_syntax("pipe", true);
+        self
+    }
+
+    /// Take the remote command out, leaving it empty, or return `None` for
+    /// subsystems.
+    fn take_remote_command(&mut self) -> Option<Vec<u8>> {
+        delegate!(&mut self.imp, imp, { imp.replace_command(b"") })
+    }
+
+    /// Append `redirection` of `path` to the remote command for `helper`,
+    /// returning `false` for subsystems.
+    fn redirect(&mut self, helper: &'static str, redirection: &str, path: &Path) -> bool {
+        let cmd: Option<Vec<u8>> = delegate!(&mut self.imp, imp, { imp.replace_command(b"") });
+
+        match cmd {
+            Some(mut cmd) => {
+                // csh only redirects stderr along with stdout.
+                self.require_posix_syntax(helper, redirection != "2>");
+
+                cmd.push(b' ');
+                cmd.extend_from_slice(redirection.as_bytes());
+                cmd.push(b' ');
+                let path = self.remote_shell.escape(path.as_os_str());
+                cmd.extend_from_slice(path.as_bytes());
+
+                delegate!(&mut self.imp, imp, {
+                    imp.replace_command(&cmd);
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Allocate a pseudo-terminal for the remote program, as with `ssh -tt`.
+    ///
+    /// The window size and terminal modes are taken from stdin if it is a
+    /// terminal, e.g. the follower side of a pty opened by the caller. After
+    /// resizing it, call [`Session::notify_window_change`] to forward the new
+    /// size to the remote side.
+    ///
+    /// Note that stdout and stderr are merged by the remote pty, and that its
+    /// line discipline translates newlines into `\r\n`.
+    pub fn request_pty(&mut self, options: PtyOptions) -> &mut Self {
+        delegate!(&mut self.imp, imp, {
+            imp.request_pty(options.term.as_deref());
+        });
+        self
+    }
+
+    /// Whether the backend puts stdin in raw mode once the pty requested with
+    /// [`request_pty`](Self::request_pty) is allocated, as ssh does.
+    pub(crate) fn enters_raw_mode(&self) -> bool {
+        // Not using `delegate!`, which would not compile without any backend.
+        match self.imp {
+            #[cfg(feature = "process-mux")]
+            CommandImp::ProcessImpl(_) => true,
+
+            #[cfg(feature = "native-mux")]
+            CommandImp::NativeMuxImpl(_) => false,
+        }
+    }
+
+    /// Ask the master to forward the authentication agent to the remote
+    /// program, e.g. for git to authenticate to another host over ssh.
+    ///
+    /// The master only forwards it if allowed by `ForwardAgent`, see
+    /// [`SessionBuilder::forward_agent`](crate::SessionBuilder::forward_agent),
+    /// and exposes it through `SSH_AUTH_SOCK` on the remote side.
+    ///
+    /// By default, the `process-mux` backend uses the value set in
+    /// `~/.ssh/config`, while the `native-mux` backend does not forward the
+    /// agent.
+    pub fn forward_agent(&mut self, forward_agent: bool) -> &mut Self {
+        delegate!(&mut self.imp, imp, {
+            imp.forward_agent(forward_agent);
+        });
+        self
+    }
+
+    /// Ask the master to forward X11 connections of the remote program to
+    /// the `DISPLAY` of the master.
+    ///
+    /// The master only forwards them if allowed, see
+    /// [`SessionBuilder::forward_x11`](crate::SessionBuilder::forward_x11),
+    /// and if its `DISPLAY` is set, in which case `DISPLAY` is set on the
+    /// remote side.
+    ///
+    /// By default, the `process-mux` backend uses the value set in
+    /// `~/.ssh/config`, while the `native-mux` backend does not forward X11.
+    pub fn forward_x11(&mut self, forward_x11: bool) -> &mut Self {
+        delegate!(&mut self.imp, imp, {
+            imp.forward_x11(forward_x11);
+        });
+        self
+    }
+
+    /// Launch the remote program in a new session with `setsid -w`, detached
+    /// from the session of the remote login.
+    ///
+    /// This allows the remote process to intentionally survive the loss of
+    /// its channel, e.g. when the [`Child`] is [disconnected](Child::disconnect)
+    /// or the connection drops, as it no longer receives the `SIGHUP` sent by
+    /// sshd. Until then, the exit status is reported as usual and stdio is
+    /// forwarded over the channel, so redirect stdio on the remote side if the
+    /// process is meant to outlive it.
+    ///
+    /// The remote command line is prefixed with `setsid -w`, so it must be a
+    /// simple command; use [`Session::shell`] for anything else. This requires
+    /// the `setsid` of util-linux on the remote host, and has no effect on
+    /// subsystems. It also requires a POSIX shell or csh on the remote
+    /// host, see
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    ///
+    /// Defaults to `false`.
+    pub fn setsid(&mut self, setsid: bool) -> &mut Self {
+        delegate!(&mut self.imp, imp, {
+            imp.setsid(setsid);
+        });
+        if setsid {
+            self.require_posix_syntax("setsid", true);
+        }
+        self
+    }
+
+    /// Keep the connection of a long-idle interactive channel busy, e.g.
+    /// through middleboxes dropping idle NAT entries, by writing `filler` to
+    /// the stdin of the remote process whenever nothing was written to it
+    /// for `interval`.
+    ///
+    /// `filler` must be harmless to the remote process wherever it lands,
+    /// e.g. `\n` for a shell reading commands, since it is written between
+    /// any two chunks written to [`Child::stdin`] more than `interval`
+    /// apart.
+    ///
+    /// This sets stdin to [`Stdio::piped`], and is disabled if stdin is set
+    /// to anything else afterwards, or is fed by
+    /// [`stdin_transform`](Self::stdin_transform). Writes to
+    /// [`Child::stdin`] are forwarded by a task spawned along with the
+    /// process, which stops once it is closed or the `Child` is dropped.
+    ///
+    /// Without a stdin to write to, use
+    /// [`SessionBuilder::server_alive_interval`](crate::SessionBuilder::server_alive_interval),
+    /// which keeps the whole connection busy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero or `filler` is empty.
+    ///
+    /// [`Child::stdin`]: crate::Child::stdin
+    pub fn chaff_keepalive(&mut self, interval: Duration, filler: impl Into<Vec<u8>>) -> &mut Self {
+        let filler = filler.into();
+        assert!(!interval.is_zero(), "keepalive interval must be non-zero");
+        assert!(!filler.is_empty(), "keepalive filler must be non-empty");
+
+        self.stdin(Stdio::piped());
+        self.chaff_keepalive = Some((interval, filler));
+        self
+    }
+
+    /// Run the remote command through a trampoline if, once constructed,
+    /// it is longer than `threshold` bytes.
+    ///
+    /// Very long command lines may exceed `ARG_MAX` on either end or
+    /// the limits of the remote sshd, and then fail in confusing ways.
+    /// With a trampoline, `sh -s` is launched on the remote host instead,
+    /// and the command is written to its stdin before anything else, so
+    /// the command still receives whatever is written to stdin afterwards.
+    ///
+    /// Note that the command is then interpreted by `sh` rather than by
+    /// the login shell of the remote user, so this requires
+    /// [`RemoteShell::Posix`], see
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    ///
+    /// Trampolines are only used if stdin is [`Stdio::piped`] or
+    /// [`Stdio::null`] (the default for [`output`](Self::output)),
+    /// and never for subsystems.
+    ///
+    /// Defaults to `None`.
+    pub fn trampoline_threshold(&mut self, threshold: usize) -> &mut Self {
+        self.trampoline_threshold = Some(threshold);
+        self
+    }
+
+    /// Set the priority of this command when waiting for a slot of the
+    /// [`ConcurrencyBudget`] of the session, e.g. [`Priority::High`] for
+    /// health checks which must not be starved by bulk commands spawned on
+    /// the same master.
+    ///
+    /// Defaults to [`Priority::Normal`].
+    pub fn priority(&mut self, priority: Priority) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Fail with [`Error::OutputTooLarge`] as soon as more than `max` bytes
+    /// are read from either stdout or stderr, instead of buffering the
+    /// output of runaway commands without bound.
+    ///
+    /// This applies to [`output`](Self::output),
+    /// [`output_in`](Self::output_in) and
+    /// [`Child::wait_with_output`] of spawned children. The channel is
+    /// closed once the limit is exceeded, which does not kill the remote
+    /// process, although it then usually dies of `SIGPIPE` or `SIGHUP`.
+    ///
+    /// Defaults to `None`.
+    pub fn max_output_size(&mut self, max: usize) -> &mut Self {
+        self.max_output_size = Some(max);
+        self
+    }
+
+    /// Transcode stdout and stderr captured by [`output`](Self::output) and
+    /// [`output_in`](Self::output_in) from `encoding` to UTF-8, for remote
+    /// hosts using a locale such as Shift-JIS or Latin-1.
+    ///
+    /// Invalid bytes are handled according to `errors`.
+    #[cfg(feature = "encoding")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encoding")))]
+    pub fn output_encoding(
+        &mut self,
+        encoding: &'static encoding_rs::Encoding,
+        errors: DecodeErrors,
+    ) -> &mut Self {
+        self.output_encoding = Some(OutputEncoding { encoding, errors });
+        self
+    }
+
+    /// Transcode `output` as requested with
+    /// [`output_encoding`](Self::output_encoding).
+    fn transcode(&self, output: process::Output) -> Result<process::Output, Error> {
+        #[cfg(feature = "encoding")]
+        if let Some(encoding) = &self.output_encoding {
+            return encoding.transcode_output(output);
+        }
+        Ok(output)
+    }
+
+    /// Replace the remote command with the payload expected by the forced
+    /// command of the server.
+    fn prepare_payload(&mut self, template: &str) {
+        let cmd = match &mut self.wrapped {
+            Some(cmd) => cmd,
+            None => {
+                let cmd: Option<Vec<u8>> =
+                    delegate!(&mut self.imp, imp, { imp.replace_command(b"") });
+                match cmd {
+                    Some(cmd) => self.wrapped.insert(cmd),
+                    // Subsystems are sent as-is.
+                    None => return,
+                }
+            }
+        };
+
+        let mut payload = Vec::with_capacity(template.len() + cmd.len());
+        for (i, part) in template.split("{command}").enumerate() {
+            if i != 0 {
+                payload.extend_from_slice(cmd);
+            }
+            payload.extend_from_slice(part.as_bytes());
+        }
+
+        delegate!(&mut self.imp, imp, {
+            imp.replace_command(&payload);
+        });
+    }
+
+    fn prepare_trampoline(&mut self) {
+        if self.trampolined.is_none() {
+            let threshold = match self.trampoline_threshold {
+                Some(threshold) if self.stdin_null || self.stdin_piped => threshold,
+                _ => return,
+            };
+
+            let len: usize = delegate!(&self.imp, imp, { imp.remote_command_len() });
+            if len <= threshold {
+                return;
+            }
+
+            self.trampolined = delegate!(&mut self.imp, imp, { imp.replace_command(b"sh -s") });
+            if self.trampolined.is_some() {
+                // The arguments are escaped for the remote shell, not `sh`.
+                self.require_posix_syntax("trampoline_threshold", false);
+            }
+        }
+
+        if self.trampolined.is_some() {
+            // The script is sent through stdin.
+            delegate!(&mut self.imp, imp, {
+                imp.stdin(Stdio::piped());
+            });
+        }
+    }
 }
 
 impl<S: Clone> OwningCommand<S> {
     async fn spawn_impl(&mut self) -> Result<Child<S>, Error> {
-        Ok(Child::new(
-            self.session.clone(),
-            delegate!(&mut self.imp, imp, {
+        self.health.ensure_open()?;
+
+        match &self.remote_command_mode {
+            RemoteCommandMode::Command => self.prepare_trampoline(),
+            // The mode is only set for commands, not for subsystems.
+            RemoteCommandMode::SubsystemOnly => return Err(Error::CommandRejected),
+            RemoteCommandMode::ForcedCommandPayload { template } => {
+                let template = template.clone();
+                self.prepare_payload(&template);
+            }
+        }
+
+        if let Some(helper) = self.unsupported_syntax {
+            return Err(Error::UnsupportedShellSyntax(helper));
+        }
+
+        let permit = match &self.budget {
+            Some(budget) => Some(budget.acquire(self.priority).await),
+            None => None,
+        };
+
+        let imp = &mut self.imp;
+        let spawned = async move {
+            Ok(delegate!(imp, imp, {
                 let (imp, stdin, stdout, stderr) = imp.spawn().await?;
                 (
                     imp.into(),
@@ -368,8 +1275,53 @@ impl<S: Clone> OwningCommand<S> {
                     stdout.map(TryFromChildIo::try_from).transpose()?,
                     stderr.map(TryFromChildIo::try_from).transpose()?,
                 )
-            }),
-        ))
+            }))
+        }
+        .await
+        .map_err(|err: Error| {
+            self.health.record_err(&err);
+            err
+        })?;
+
+        let mut child = Child::new(self.session.clone(), spawned, self.health.clone())
+            .with_permit(permit)
+            .with_max_output_size(self.max_output_size)
+            .with_metrics(self.metrics.clone());
+
+        if let Some(cmd) = &self.trampolined {
+            let mut stdin = child
+                .stdin()
+                .take()
+                .expect("stdin is piped for trampolines");
+            stdin
+                .write_all(&trampoline_script(cmd))
+                .await
+                .map_err(Error::ChildIo)?;
+
+            if self.stdin_piped {
+                *child.stdin() = Some(stdin);
+            }
+        }
+
+        if let Some(StdinTransform(pump)) = self.stdin_transform.take() {
+            let stdin = child
+                .stdin()
+                .take()
+                .expect("stdin is piped for stdin_transform");
+            let pump = pump.into_inner().unwrap_or_else(PoisonError::into_inner);
+            child = child.with_stdin_pump(pump(stdin));
+        }
+
+        if let Some((interval, filler)) = &self.chaff_keepalive {
+            if let Some(stdin) = child.stdin().take() {
+                let (local, reader) = ChildStdin::local_pipe().map_err(Error::ChildIo)?;
+                *child.stdin() = Some(local);
+                let chaff = forward_with_chaff(reader, stdin, *interval, filler.clone());
+                child = child.with_keepalive(tokio::spawn(chaff));
+            }
+        }
+
+        Ok(child)
     }
 
     /// Executes the remote command without waiting for it, returning a handle to it
@@ -377,6 +1329,11 @@ impl<S: Clone> OwningCommand<S> {
     ///
     /// By default, stdin, stdout and stderr are inherited.
     pub async fn spawn(&mut self) -> Result<Child<S>, Error> {
+        self.inherit_stdio();
+        self.op("spawn").run(self.spawn_impl(), |_| None).await
+    }
+
+    fn inherit_stdio(&mut self) {
         if !self.stdin_set {
             self.stdin(Stdio::inherit());
         }
@@ -386,15 +1343,9 @@ impl<S: Clone> OwningCommand<S> {
         if !self.stderr_set {
             self.stderr(Stdio::inherit());
         }
-
-        self.spawn_impl().await
     }
 
-    /// Executes the remote command, waiting for it to finish and collecting all of its output.
-    ///
-    /// By default, stdout and stderr are captured (and used to provide the resulting
-    /// output) and stdin is set to `Stdio::null()`.
-    pub async fn output(&mut self) -> Result<process::Output, Error> {
+    fn capture_output(&mut self) {
         if !self.stdin_set {
             self.stdin(Stdio::null());
         }
@@ -404,14 +1355,100 @@ impl<S: Clone> OwningCommand<S> {
         if !self.stderr_set {
             self.stderr(Stdio::piped());
         }
+    }
+
+    /// Executes the remote command, waiting for it to finish and collecting all of its output.
+    ///
+    /// By default, stdout and stderr are captured (and used to provide the resulting
+    /// output) and stdin is set to `Stdio::null()`.
+    pub async fn output(&mut self) -> Result<process::Output, Error> {
+        self.capture_output();
+        let op = self.op("output");
+        let fut = async { self.spawn_impl().await?.wait_with_output().await };
+        let output = op.run(fut, |output| Some(output.status)).await?;
+        self.transcode(output)
+    }
+
+    /// Same as [`output`](Self::output), except that stdout and stderr are
+    /// read into buffers taken from `pool`.
+    ///
+    /// See [`Child::wait_with_output_in`] for details.
+    pub async fn output_in<P>(&mut self, pool: &P) -> Result<process::Output, Error>
+    where
+        P: BufferPool + ?Sized,
+    {
+        self.capture_output();
+        let op = self.op("output");
+        let fut = async { self.spawn_impl().await?.wait_with_output_in(pool).await };
+        let output = op.run(fut, |output| Some(output.status)).await?;
+        self.transcode(output)
+    }
+
+    /// Same as [`output`](Self::output), except that only the first
+    /// `head_len` and the last `tail_len` bytes of stdout and stderr are
+    /// retained, along with the number of bytes dropped between them.
+    ///
+    /// This gives a diagnostic sample of huge outputs without storing them.
+    pub async fn capture_head_tail(
+        &mut self,
+        head_len: usize,
+        tail_len: usize,
+    ) -> Result<SampledOutput, Error> {
+        self.capture_output();
+        let op = self.op("output");
+        let fut = async {
+            self.spawn_impl()
+                .await?
+                .wait_with_head_tail(head_len, tail_len)
+                .await
+        };
+        op.run(fut, |output| Some(output.status)).await
+    }
 
-        self.spawn_impl().await?.wait_with_output().await
+    /// Same as [`output`](Self::output), except that stdout and stderr are
+    /// streamed to the local files `{path}.stdout` and `{path}.stderr`,
+    /// optionally compressed and rotated as set by `options`, and only a
+    /// summary of them is returned.
+    ///
+    /// This keeps the output of verbose commands, e.g. builds or backups,
+    /// out of memory. With rotation, the files are further suffixed with
+    /// their index, starting at `.0`, and with compression, with `.gz`.
+    /// Existing files are overwritten.
+    pub async fn capture_to_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: CaptureOptions,
+    ) -> Result<CapturedOutput, Error> {
+        self.capture_output();
+        let op = self.op("output");
+        let fut = async {
+            self.spawn_impl()
+                .await?
+                .wait_to_files(path.as_ref(), &options)
+                .await
+        };
+        op.run(fut, |output| Some(output.status)).await
+    }
+
+    /// Executes the remote command without waiting for it, returning a
+    /// [`Stream`](futures_core::Stream) of its stdout and stderr as they
+    /// arrive, e.g. to show the progress of long-running jobs.
+    ///
+    /// By default, stdout and stderr are captured and stdin is set to
+    /// `Stdio::null()`, as with [`output`](Self::output).
+    pub async fn stream(&mut self) -> Result<OutputStream<S>, Error> {
+        self.capture_output();
+        let child = self.op("spawn").run(self.spawn_impl(), |_| None).await?;
+        Ok(OutputStream::new(child))
     }
 
     /// Executes the remote command, waiting for it to finish and collecting its exit status.
     ///
     /// By default, stdin, stdout and stderr are inherited.
     pub async fn status(&mut self) -> Result<process::ExitStatus, Error> {
-        self.spawn().await?.wait().await
+        self.inherit_stdio();
+        let op = self.op("status");
+        let fut = async { self.spawn_impl().await?.wait().await };
+        op.run(fut, |status| Some(*status)).await
     }
 }
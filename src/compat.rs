@@ -0,0 +1,164 @@
+use super::escape;
+use super::file_ops::remote_error;
+use super::{Error, OwningCommand, Session, Stdio};
+
+use std::io;
+use std::path::Path;
+
+/// Prints `gnu`, `busybox` or the name of the kernel otherwise.
+const PROBE: &str = "if stat --version 2>/dev/null | grep -q GNU; then echo gnu; \
+                     elif stat --help 2>&1 | grep -q BusyBox; then echo busybox; \
+                     else uname -s; fi";
+
+/// Userland of the remote host, which determines the flags accepted by
+/// common commands, as detected by [`Session::compat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Userland {
+    /// GNU coreutils, as on most Linux distributions.
+    Gnu,
+    /// BusyBox, as on Alpine and many embedded systems.
+    Busybox,
+    /// The BSD userland, as on FreeBSD, OpenBSD, NetBSD and macOS.
+    Bsd,
+    /// Anything else, for which the flags of GNU coreutils are tried.
+    Unknown,
+}
+
+impl Userland {
+    fn from_probe(output: &str) -> Self {
+        match output.trim() {
+            "gnu" => Userland::Gnu,
+            "busybox" => Userland::Busybox,
+            kernel if kernel.ends_with("BSD") || kernel == "Darwin" || kernel == "DragonFly" => {
+                Userland::Bsd
+            }
+            _ => Userland::Unknown,
+        }
+    }
+
+    async fn probe(session: &Session) -> Result<Self, Error> {
+        let mut cmd = session.command("sh");
+        cmd.arg("-c").arg(PROBE);
+        Compat::output(cmd)
+            .await
+            .map(|output| Self::from_probe(&output))
+    }
+}
+
+/// Helpers running common commands with the flags supported by the
+/// userland of the remote host, as returned by [`Session::compat`].
+///
+/// GNU coreutils, BusyBox and the BSDs disagree on the flags of `stat`,
+/// `readlink` and `mktemp`, so that scripts written for one of them
+/// silently break on the others, e.g. on Alpine or embedded targets.
+#[derive(Debug, Clone, Copy)]
+pub struct Compat<'s> {
+    session: &'s Session,
+    userland: Userland,
+}
+
+impl<'s> Compat<'s> {
+    pub(crate) async fn new(session: &'s Session) -> Result<Compat<'s>, Error> {
+        Ok(Self {
+            session,
+            userland: Userland::probe(session).await?,
+        })
+    }
+
+    /// The detected userland.
+    pub fn userland(&self) -> Userland {
+        self.userland
+    }
+
+    /// Run `cmd`, returning its trimmed stdout.
+    async fn output(mut cmd: OwningCommand<&'s Session>) -> Result<String, Error> {
+        let output = cmd.stderr(Stdio::piped()).output().await?;
+
+        if !output.status.success() {
+            return Err(remote_error(&output.stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned())
+    }
+
+    /// Run `program` with `args` followed by `path`.
+    async fn run(&self, program: &str, args: &[&str], path: &Path) -> Result<String, Error> {
+        let mut cmd = self.session.command(program);
+        cmd.args(args).raw_arg(&*escape::posix(path.as_os_str()));
+        Self::output(cmd).await
+    }
+
+    async fn stat(&self, path: &Path, gnu_format: &str, bsd_format: &str) -> Result<u64, Error> {
+        let args = match self.userland {
+            Userland::Bsd => ["-L", "-f", bsd_format],
+            _ => ["-L", "-c", gnu_format],
+        };
+        let output = self.run("stat", &args, path).await?;
+
+        output.parse().map_err(|_| {
+            Error::Remote(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected output of stat: {}", output),
+            ))
+        })
+    }
+
+    /// Size in bytes of the remote file `path`, following symlinks.
+    pub async fn file_size(&self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        self.stat(path.as_ref(), "%s", "%z").await
+    }
+
+    /// Last modification time of the remote file `path`, in seconds since
+    /// the Unix epoch, following symlinks.
+    pub async fn modified(&self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        self.stat(path.as_ref(), "%Y", "%m").await
+    }
+
+    /// Absolute path of the remote file `path`, with all symlinks resolved.
+    pub async fn canonicalize(&self, path: impl AsRef<Path>) -> Result<String, Error> {
+        match self.userland {
+            // readlink -f is missing from older macOS.
+            Userland::Bsd => self.run("realpath", &[], path.as_ref()).await,
+            _ => self.run("readlink", &["-f"], path.as_ref()).await,
+        }
+    }
+
+    /// Create a new remote temporary file, or directory if `dir` is `true`,
+    /// in `$TMPDIR` or `/tmp`, and return its path.
+    pub async fn mktemp(&self, dir: bool) -> Result<String, Error> {
+        // Only an explicit template ending in exactly six `X` is accepted by
+        // all of them.
+        let script = if dir {
+            "mktemp -d \"${TMPDIR:-/tmp}/tmp.XXXXXX\""
+        } else {
+            "mktemp \"${TMPDIR:-/tmp}/tmp.XXXXXX\""
+        };
+        let mut cmd = self.session.command("sh");
+        cmd.arg("-c").arg(script);
+        Self::output(cmd).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Userland;
+
+    #[test]
+    fn from_probe() {
+        let cases = [
+            ("gnu\n", Userland::Gnu),
+            ("busybox\n", Userland::Busybox),
+            ("FreeBSD\n", Userland::Bsd),
+            ("OpenBSD\n", Userland::Bsd),
+            ("Darwin\n", Userland::Bsd),
+            ("SunOS\n", Userland::Unknown),
+            ("", Userland::Unknown),
+        ];
+
+        for (output, userland) in cases {
+            assert_eq!(Userland::from_probe(output), userland, "{output}");
+        }
+    }
+}
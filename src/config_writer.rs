@@ -0,0 +1,269 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A ssh_config fragment, generated from typed settings and passed to the
+/// master connection via `ssh -F` when set with [`SessionBuilder::config`].
+///
+/// It lets programs express complex per-destination behaviour, e.g. host
+/// aliases, `ProxyJump` chains or identity files, without shipping config
+/// templates:
+///
+/// ```rust
+/// use openssh::{ConfigWriter, HostConfig};
+///
+/// let mut config = ConfigWriter::new();
+/// config
+///     .host(
+///         HostConfig::new("bastion")
+///             .host_name("bastion.example.com")
+///             .user("ops")
+///             .identity_file("/etc/keys/bastion"),
+///     )
+///     .host(
+///         HostConfig::new("db-*")
+///             .proxy_jump(["bastion"])
+///             .option("ServerAliveInterval", "30"),
+///     );
+///
+/// assert!(config.render().unwrap().contains("ProxyJump bastion\n"));
+/// ```
+///
+/// Note that ssh does not read `~/.ssh/config` when `-F` is given, use
+/// [`ConfigWriter::include`] to pull it in.
+///
+/// [`SessionBuilder::config`]: crate::SessionBuilder::config
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfigWriter {
+    includes: Vec<PathBuf>,
+    hosts: Vec<HostConfig>,
+}
+
+impl ConfigWriter {
+    /// Create an empty config.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an `Include` directive for `path`, e.g. `~/.ssh/config`.
+    ///
+    /// Includes are written before all `Host` blocks, so since ssh uses the
+    /// first value obtained for each option, the included files take
+    /// precedence over the generated blocks.
+    pub fn include(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.includes.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a `Host` block.
+    ///
+    /// Blocks are written in the order they are added, so more specific
+    /// blocks should be added before the generic ones.
+    pub fn host(&mut self, host: HostConfig) -> &mut Self {
+        self.hosts.push(host);
+        self
+    }
+
+    /// Render the config in the ssh_config format.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if a value cannot be
+    /// represented, e.g. because it contains a newline, a double quote or
+    /// is a path that is not valid UTF-8.
+    pub fn render(&self) -> io::Result<String> {
+        let mut config = String::new();
+
+        for include in &self.includes {
+            write_directive(&mut config, "Include", path_to_str(include)?)?;
+        }
+
+        for host in &self.hosts {
+            config.push_str("\nHost");
+            for pattern in &host.patterns {
+                config.push(' ');
+                push_value(&mut config, pattern)?;
+            }
+            config.push('\n');
+
+            for (keyword, value) in &host.options {
+                config.push_str("    ");
+                write_directive(&mut config, keyword, value)?;
+            }
+            for identity_file in &host.identity_files {
+                config.push_str("    ");
+                write_directive(&mut config, "IdentityFile", path_to_str(identity_file)?)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Render the config and write it to `path`.
+    pub(crate) fn write_to(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.render()?)
+    }
+}
+
+/// A `Host` block of a [`ConfigWriter`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HostConfig {
+    patterns: Vec<String>,
+    options: Vec<(String, String)>,
+    identity_files: Vec<PathBuf>,
+}
+
+impl HostConfig {
+    /// Create a block applying to the hosts matching `pattern`, which may
+    /// contain the `*` and `?` wildcards and be negated with `!`.
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            patterns: vec![pattern.into()],
+            options: Vec::new(),
+            identity_files: Vec::new(),
+        }
+    }
+
+    /// Add another pattern to match hosts against.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Set the real host name to connect to.
+    pub fn host_name(self, host_name: impl Into<String>) -> Self {
+        self.option("HostName", host_name)
+    }
+
+    /// Set the user to log in as.
+    pub fn user(self, user: impl Into<String>) -> Self {
+        self.option("User", user)
+    }
+
+    /// Set the port to connect to.
+    pub fn port(self, port: u16) -> Self {
+        self.option("Port", port.to_string())
+    }
+
+    /// Add an identity file to authenticate with.
+    ///
+    /// Can be called multiple times, in which case the files are tried in
+    /// order.
+    pub fn identity_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.identity_files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Connect through the given chain of jump hosts, which may themselves
+    /// be configured with their own [`HostConfig`].
+    pub fn proxy_jump<T: AsRef<str>>(self, hosts: impl IntoIterator<Item = T>) -> Self {
+        let mut chain = String::new();
+        for host in hosts {
+            if !chain.is_empty() {
+                chain.push(',');
+            }
+            chain.push_str(host.as_ref());
+        }
+        self.option("ProxyJump", chain)
+    }
+
+    /// Set an arbitrary option, e.g. `option("ServerAliveInterval", "30")`.
+    ///
+    /// See `man 5 ssh_config` for the available options.
+    pub fn option(mut self, keyword: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.push((keyword.into(), value.into()));
+        self
+    }
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn path_to_str(path: &Path) -> io::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| invalid_input("path in ssh config is not valid UTF-8"))
+}
+
+/// Append `value`, quoting it if it contains whitespace.
+fn push_value(config: &mut String, value: &str) -> io::Result<()> {
+    if value.is_empty() || value.contains(['"', '\n', '\r']) {
+        return Err(invalid_input("invalid value in ssh config"));
+    }
+
+    if value.contains(char::is_whitespace) {
+        config.push('"');
+        config.push_str(value);
+        config.push('"');
+    } else {
+        config.push_str(value);
+    }
+    Ok(())
+}
+
+fn write_directive(config: &mut String, keyword: &str, value: &str) -> io::Result<()> {
+    if keyword.is_empty() || !keyword.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(invalid_input("invalid keyword in ssh config"));
+    }
+
+    // Writing to a `String` never fails.
+    let _ = write!(config, "{} ", keyword);
+    push_value(config, value)?;
+    config.push('\n');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigWriter, HostConfig};
+
+    #[test]
+    fn render() {
+        let mut config = ConfigWriter::new();
+        config
+            .include("~/.ssh/config")
+            .host(
+                HostConfig::new("target")
+                    .pattern("target.example.com")
+                    .host_name("10.0.0.1")
+                    .port(2222)
+                    .proxy_jump(["jump1", "user@jump2:22"])
+                    .identity_file("/keys/id one")
+                    .identity_file("/keys/id_two"),
+            )
+            .host(HostConfig::new("*").user("me"));
+
+        let expected = r#"Include ~/.ssh/config
+
+Host target target.example.com
+    HostName 10.0.0.1
+    Port 2222
+    ProxyJump jump1,user@jump2:22
+    IdentityFile "/keys/id one"
+    IdentityFile /keys/id_two
+
+Host *
+    User me
+"#;
+        assert_eq!(config.render().unwrap(), expected);
+    }
+
+    #[test]
+    fn invalid() {
+        let invalid = [
+            HostConfig::new("a").user("me\nProxyCommand evil"),
+            HostConfig::new("a").option("Proxy Command", "evil"),
+            HostConfig::new("a").option("User", ""),
+            HostConfig::new("a").host_name("\"quoted\""),
+        ];
+
+        for host in invalid {
+            let mut config = ConfigWriter::new();
+            config.host(host);
+
+            let err = config.render().unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Why ssh failed to connect to the remote host, as parsed from its
+/// output and returned by [`Error::connect_error`](crate::Error::connect_error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SshConnectError {
+    /// The host presented another key than the one in the known hosts
+    /// file, along with the file and the line number of the known key, if
+    /// ssh reported them.
+    HostKeyMismatch {
+        /// The known hosts file and the line number of the known key.
+        offending_line: Option<(PathBuf, usize)>,
+    },
+    /// The host is not in the known hosts file, and strict checking is
+    /// requested, see [`KnownHosts::Strict`](crate::KnownHosts::Strict).
+    HostKeyUnknown,
+    /// The host rejected every authentication method tried, e.g.
+    /// `publickey`, or there were too many authentication failures.
+    AuthFailed {
+        /// The methods that the host accepts, which were all tried.
+        methods_tried: Vec<String>,
+    },
+    /// Connecting to the host timed out.
+    Timeout,
+    /// The host refused the connection.
+    Refused,
+    /// The host is unreachable from the local host.
+    Unreachable,
+    /// The hostname could not be resolved.
+    ResolveFailure,
+    /// The jump host or proxy command failed to reach the host.
+    ProxyError,
+    /// Any other failure.
+    Other,
+}
+
+impl SshConnectError {
+    /// Parse the output of a failed ssh.
+    pub(crate) fn parse(stderr: &str) -> Self {
+        if stderr.contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+            || stderr.contains("has changed and you have requested strict checking")
+        {
+            // `Offending ED25519 key in /home/user/.ssh/known_hosts:3`
+            let offending_line = stderr.lines().find_map(|line| {
+                let (_, location) = line.trim().strip_prefix("Offending ")?.split_once(" in ")?;
+                let (path, line) = location.rsplit_once(':')?;
+                Some((PathBuf::from(path), line.parse().ok()?))
+            });
+            return SshConnectError::HostKeyMismatch { offending_line };
+        }
+        if stderr.contains("Host key verification failed") {
+            return SshConnectError::HostKeyUnknown;
+        }
+
+        // `user@host: Permission denied (publickey,password).`
+        if let Some((_, rest)) = stderr.split_once("Permission denied (") {
+            let methods = rest.split(')').next().unwrap_or("");
+            return SshConnectError::AuthFailed {
+                methods_tried: methods.split(',').map(str::to_owned).collect(),
+            };
+        }
+        if stderr.contains("Too many authentication failures") {
+            return SshConnectError::AuthFailed {
+                methods_tried: Vec::new(),
+            };
+        }
+
+        // Checked before the connection errors, since the errors of the proxy
+        // include the ones of the connections it makes.
+        if stderr.contains("stdio forwarding failed")
+            || stderr.contains("channel 0: open failed")
+            || stderr.contains("ProxyCommand")
+        {
+            return SshConnectError::ProxyError;
+        }
+
+        if stderr.contains("Could not resolve hostname") {
+            SshConnectError::ResolveFailure
+        } else if stderr.contains("timed out") {
+            SshConnectError::Timeout
+        } else if stderr.contains("Connection refused") {
+            SshConnectError::Refused
+        } else if stderr.contains("Network is unreachable") || stderr.contains("No route to host") {
+            SshConnectError::Unreachable
+        } else {
+            SshConnectError::Other
+        }
+    }
+}
+
+/// The payload of the io error of [`Error::Connect`](crate::Error::Connect)
+/// when ssh fails, which displays as the output of ssh.
+#[derive(Debug)]
+pub(crate) struct ConnectFailure {
+    pub(crate) reason: SshConnectError,
+    pub(crate) stderr: String,
+}
+
+impl fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stderr)
+    }
+}
+
+impl error::Error for ConnectFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::SshConnectError;
+
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse() {
+        let changed = "\
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@
+@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@
+IT IS POSSIBLE THAT SOMEONE IS DOING SOMETHING NASTY!
+Offending ED25519 key in /home/user/.ssh/known_hosts:3
+  remove with:
+  ssh-keygen -f \"/home/user/.ssh/known_hosts\" -R \"[127.0.0.1]:2222\"
+Host key for [127.0.0.1]:2222 has changed and you have requested strict checking.
+Host key verification failed.";
+        assert_eq!(
+            SshConnectError::parse(changed),
+            SshConnectError::HostKeyMismatch {
+                offending_line: Some((PathBuf::from("/home/user/.ssh/known_hosts"), 3))
+            }
+        );
+
+        assert_eq!(
+            SshConnectError::parse(
+                "No ED25519 host key is known for [127.0.0.1]:2222 and you have requested \
+                 strict checking.\r\nHost key verification failed."
+            ),
+            SshConnectError::HostKeyUnknown
+        );
+        assert_eq!(
+            SshConnectError::parse("test-user@127.0.0.1: Permission denied (publickey,password)."),
+            SshConnectError::AuthFailed {
+                methods_tried: vec!["publickey".to_owned(), "password".to_owned()]
+            }
+        );
+        assert_eq!(
+            SshConnectError::parse("connect to host 192.0.2.1 port 22: Connection timed out"),
+            SshConnectError::Timeout
+        );
+        assert_eq!(
+            SshConnectError::parse("connect to host 127.0.0.1 port 9: Connection refused"),
+            SshConnectError::Refused
+        );
+        assert_eq!(
+            SshConnectError::parse("connect to host 10.0.0.1 port 22: No route to host"),
+            SshConnectError::Unreachable
+        );
+        assert_eq!(
+            SshConnectError::parse(
+                "Could not resolve hostname bad.invalid: Name or service not known"
+            ),
+            SshConnectError::ResolveFailure
+        );
+        assert_eq!(
+            SshConnectError::parse(
+                "channel 0: open failed: connect failed: Connection refused\r\n\
+                 stdio forwarding failed\r\n\
+                 kex_exchange_identification: Connection closed by remote host"
+            ),
+            SshConnectError::ProxyError
+        );
+        assert_eq!(
+            SshConnectError::parse("Bad configuration option: foo"),
+            SshConnectError::Other
+        );
+    }
+}
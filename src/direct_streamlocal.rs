@@ -0,0 +1,192 @@
+use super::backend::ssh_version;
+use super::direct_tcpip::closed;
+use super::file_ops::remote_error;
+use super::{Child, ChildStdin, ChildStdout, DirectTcpip, Error, Session, Stdio};
+
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Relay stdio to the unix socket `$1` on the remote host, with python or
+/// perl since neither `socat` nor `nc` are commonly installed.
+const RELAY: &str = r#"if command -v python3 > /dev/null 2>&1; then
+    exec python3 -c '
+import os, socket, sys, threading
+s = socket.socket(socket.AF_UNIX)
+try:
+    s.connect(sys.argv[1])
+except OSError as e:
+    sys.exit("%s: %s" % (sys.argv[1], e.strerror))
+def send():
+    while True:
+        data = os.read(0, 65536)
+        if not data:
+            break
+        s.sendall(data)
+    s.shutdown(socket.SHUT_WR)
+threading.Thread(target=send, daemon=True).start()
+out = sys.stdout.buffer
+while True:
+    data = s.recv(65536)
+    if not data:
+        break
+    out.write(data)
+    out.flush()
+' "$1"
+elif command -v perl > /dev/null 2>&1; then
+    exec perl -e '
+use IO::Socket::UNIX;
+my $s = IO::Socket::UNIX->new(Peer => $ARGV[0]) or die "$ARGV[0]: $!\n";
+my $pid = fork // die "fork: $!\n";
+if (!$pid) {
+    while (sysread(STDIN, my $data, 65536)) { print $s $data or exit 1; }
+    shutdown($s, 1);
+    exit 0;
+}
+$| = 1;
+while (sysread($s, my $data, 65536)) { print $data or last; }
+kill "TERM", $pid;
+' "$1"
+else
+    echo "relaying to $1 requires python3 or perl" >&2
+    exit 127
+fi"#;
+
+/// Whether the local ssh forwards stdio to unix sockets with `-W`, which
+/// is supported since OpenSSH 9.4.
+async fn forwards_unix_sockets() -> bool {
+    ssh_version()
+        .await
+        .map_or(false, |version| version >= (9, 4))
+}
+
+#[derive(Debug)]
+enum Imp<'s> {
+    Forward(DirectTcpip),
+    Relay {
+        child: Box<Child<&'s Session>>,
+        stdin: Option<ChildStdin>,
+        stdout: ChildStdout,
+    },
+}
+
+/// A connection to a unix socket on the remote host, tunneled through the
+/// ssh connection, as returned by
+/// [`Session::open_direct_streamlocal`](crate::Session::open_direct_streamlocal).
+///
+/// As with [`DirectTcpip`], reading and writing it reads from and writes
+/// to the connection, [shutting it down](tokio::io::AsyncWriteExt::shutdown)
+/// closes the sending half, and the tunnel is closed once this is dropped.
+#[derive(Debug)]
+pub struct DirectStreamlocal<'s>(Imp<'s>);
+
+impl<'s> DirectStreamlocal<'s> {
+    pub(crate) async fn open(
+        session: &'s Session,
+        ctl: &Path,
+        path: &str,
+    ) -> Result<DirectStreamlocal<'s>, Error> {
+        // ssh would parse other paths as `host:port`.
+        if path.starts_with('/') && !path.contains(':') && forwards_unix_sockets().await {
+            return DirectTcpip::open(ctl, path.as_ref()).map(|tunnel| Self(Imp::Forward(tunnel)));
+        }
+
+        let mut child = session
+            .command("sh")
+            .arg("-c")
+            .arg(RELAY)
+            .arg("sh")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .await?;
+
+        let stdin = child.stdin().take();
+        let stdout = child.stdout().take().expect("stdout is piped");
+        Ok(Self(Imp::Relay {
+            child: Box::new(child),
+            stdin,
+            stdout,
+        }))
+    }
+
+    /// Wait for the tunnel to be closed and report why it failed, if it
+    /// did, as with [`DirectTcpip::wait`].
+    pub async fn wait(self) -> Result<(), Error> {
+        match self.0 {
+            Imp::Forward(tunnel) => tunnel.wait().await,
+            Imp::Relay { child, stdin, .. } => {
+                drop(stdin);
+
+                let output = child.wait_with_output().await?;
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(remote_error(&output.stderr))
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for DirectStreamlocal<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Imp::Forward(tunnel) => Pin::new(tunnel).poll_read(cx, buf),
+            Imp::Relay { stdout, .. } => Pin::new(stdout).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for DirectStreamlocal<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            Imp::Forward(tunnel) => Pin::new(tunnel).poll_write(cx, buf),
+            Imp::Relay {
+                stdin: Some(stdin), ..
+            } => Pin::new(stdin).poll_write(cx, buf),
+            Imp::Relay { stdin: None, .. } => Poll::Ready(Err(closed())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Imp::Forward(tunnel) => Pin::new(tunnel).poll_flush(cx),
+            Imp::Relay {
+                stdin: Some(stdin), ..
+            } => Pin::new(stdin).poll_flush(cx),
+            Imp::Relay { stdin: None, .. } => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().0 {
+            Imp::Forward(tunnel) => Pin::new(tunnel).poll_shutdown(cx),
+            Imp::Relay { stdin, .. } => {
+                if let Some(pipe) = stdin {
+                    match Pin::new(pipe).poll_shutdown(cx) {
+                        Poll::Ready(Ok(())) => (),
+                        poll => return poll,
+                    }
+                }
+                // The relay only closes the sending half once stdin is
+                // closed.
+                *stdin = None;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
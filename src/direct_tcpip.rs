@@ -0,0 +1,131 @@
+use super::file_ops::remote_error;
+use super::Error;
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// A tcp connection opened from the remote host and tunneled through the
+/// ssh connection, as returned by [`Session::open_direct_tcpip`](crate::Session::open_direct_tcpip).
+///
+/// Reading and writing it reads from and writes to the connection, and
+/// [shutting it down](tokio::io::AsyncWriteExt::shutdown) closes the
+/// sending half. The tunnel is closed once this is dropped.
+///
+/// If the remote host fails to connect, reads return EOF right away and
+/// [`wait`](Self::wait) returns the error.
+#[derive(Debug)]
+pub struct DirectTcpip {
+    ssh: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl DirectTcpip {
+    /// Forward stdio to `target`, i.e. `[host]:port` or the path of a unix
+    /// socket.
+    pub(crate) fn open(ctl: &Path, target: &OsStr) -> Result<Self, Error> {
+        let mut ssh = Command::new("ssh")
+            .arg("-S")
+            .arg(ctl)
+            .args(["-o", "BatchMode=yes", "-T"])
+            // See `process_impl::Session::raw_command`.
+            .args(["-p", "9"])
+            .arg("-W")
+            .arg(target)
+            .arg("none")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::ChildIo)?;
+
+        let stdin = ssh.stdin.take();
+        let stdout = ssh.stdout.take().expect("stdout is piped");
+        Ok(Self { ssh, stdin, stdout })
+    }
+
+    /// Wait for the tunnel to be closed, by both ends closing their sending
+    /// half or by the connection being lost, and report why it failed, if
+    /// it did.
+    ///
+    /// The sending half is closed first.
+    pub async fn wait(mut self) -> Result<(), Error> {
+        self.stdin = None;
+
+        let status = self.ssh.wait().await.map_err(Error::ChildIo)?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.ssh.stderr.take() {
+            pipe.read_to_string(&mut stderr)
+                .await
+                .map_err(Error::ChildIo)?;
+        }
+        // ssh prints e.g. `channel 0: open failed: connect failed: Connection
+        // refused`, followed by `stdio forwarding failed`.
+        match stderr.lines().next().map(str::trim) {
+            Some(err) if !err.is_empty() => Err(remote_error(err.as_bytes())),
+            _ => Err(Error::Disconnected),
+        }
+    }
+}
+
+impl AsyncRead for DirectTcpip {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+/// The error of writes once the sending half of a tunnel is closed.
+pub(crate) fn closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the sending half of the tunnel is closed",
+    )
+}
+
+impl AsyncWrite for DirectTcpip {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.stdin {
+            Some(stdin) => Pin::new(stdin).poll_write(cx, buf),
+            None => Poll::Ready(Err(closed())),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.stdin {
+            Some(stdin) => Pin::new(stdin).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(stdin) = &mut self.stdin {
+            match Pin::new(stdin).poll_shutdown(cx) {
+                Poll::Ready(Ok(())) => (),
+                poll => return poll,
+            }
+        }
+        // ssh only closes the sending half once stdin is closed.
+        self.stdin = None;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,80 @@
+use super::Error;
+
+use std::borrow::Cow;
+use std::process::Output;
+
+use encoding_rs::Encoding;
+
+/// How bytes that are invalid in the encoding passed to
+/// [`OwningCommand::output_encoding`](crate::OwningCommand::output_encoding)
+/// are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeErrors {
+    /// Replace them with U+FFFD REPLACEMENT CHARACTER.
+    Replace,
+    /// Fail with [`Error::InvalidOutputEncoding`].
+    Fail,
+}
+
+/// Encoding of the output of a command, to transcode it to UTF-8.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OutputEncoding {
+    pub(crate) encoding: &'static Encoding,
+    pub(crate) errors: DecodeErrors,
+}
+
+impl OutputEncoding {
+    fn transcode(&self, bytes: &mut Vec<u8>) -> Result<(), Error> {
+        let (text, had_errors) = self.encoding.decode_without_bom_handling(bytes);
+        if had_errors && self.errors == DecodeErrors::Fail {
+            return Err(Error::InvalidOutputEncoding(self.encoding.name()));
+        }
+
+        if let Cow::Owned(text) = text {
+            *bytes = text.into_bytes();
+        }
+        Ok(())
+    }
+
+    /// Transcode stdout and stderr of `output` to UTF-8.
+    pub(crate) fn transcode_output(&self, mut output: Output) -> Result<Output, Error> {
+        self.transcode(&mut output.stdout)?;
+        self.transcode(&mut output.stderr)?;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeErrors, OutputEncoding};
+    use crate::Error;
+
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn transcode() {
+        let latin1 = OutputEncoding {
+            encoding: WINDOWS_1252,
+            errors: DecodeErrors::Fail,
+        };
+        let mut bytes = b"caf\xe9".to_vec();
+        latin1.transcode(&mut bytes).unwrap();
+        assert_eq!(bytes, "café".as_bytes());
+
+        let mut sjis = OutputEncoding {
+            encoding: SHIFT_JIS,
+            errors: DecodeErrors::Replace,
+        };
+        let mut bytes = b"\x82\xa0 \xff".to_vec();
+        sjis.transcode(&mut bytes).unwrap();
+        assert_eq!(bytes, "あ \u{fffd}".as_bytes());
+
+        sjis.errors = DecodeErrors::Fail;
+        let mut bytes = b"\x82\xa0 \xff".to_vec();
+        match sjis.transcode(&mut bytes).unwrap_err() {
+            Error::InvalidOutputEncoding(name) => assert_eq!(name, "Shift_JIS"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+}
@@ -1,5 +1,13 @@
+use super::connect_error::{ConnectFailure, SshConnectError};
+use super::transfer_error::{TransferError, TransferFailure};
+use super::Backend;
+
 use std::io;
 
+/// Convenience alias for a [`Result`](std::result::Result) whose error
+/// defaults to [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
 /// Errors that occur when interacting with a remote process.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -12,6 +20,12 @@ pub enum Error {
     #[error("failed to connect to the remote host")]
     Connect(#[source] io::Error),
 
+    /// The backend is not compiled in, or cannot be used on this host,
+    /// e.g. since the local `ssh` is missing, see
+    /// [`available_backends`](crate::available_backends).
+    #[error("the {0} backend is unavailable")]
+    BackendUnavailable(Backend),
+
     /// Failed to run the `ssh` command locally.
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
@@ -30,6 +44,15 @@ pub enum Error {
     #[error("invalid command: Command contains null byte.")]
     InvalidCommand,
 
+    /// The server kept dropping the connection before sending its banner,
+    /// even after retrying.
+    ///
+    /// This is typically caused by sshd throttling new connections once
+    /// too many of them are pending authentication (`MaxStartups`).
+    /// See [`SessionBuilder::throttled_retries`](crate::SessionBuilder::throttled_retries).
+    #[error("the server is throttling new connections")]
+    ServerThrottled(#[source] io::Error),
+
     /// The remote process failed.
     #[error("the remote command could not be executed")]
     Remote(#[source] io::Error),
@@ -69,14 +92,56 @@ pub enum Error {
     ChildIo(#[source] io::Error),
 
     /// The command has some env variables that it expects to carry over ssh.
-    /// However, OverSsh does not support passing env variables over ssh.
+    /// However, OverSsh does not support passing env variables over ssh
+    /// unless translated with
+    /// [`EnvStrategy::PrefixEnvCommand`](crate::EnvStrategy::PrefixEnvCommand),
+    /// and neither does the `native-mux` backend for
+    /// [`OwningCommand::env`](crate::OwningCommand::env).
     #[error("rejected runing a command over ssh that expects env variables to be carried over to remote.")]
     CommandHasEnv,
 
     /// The command expects to be in a specific working directory in remote.
-    /// However, OverSsh does not support setting a working directory for commands to be executed over ssh.
+    /// However, OverSsh does not support setting a working directory for commands to be executed over ssh
+    /// unless translated with [`CwdStrategy::CdAnd`](crate::CwdStrategy::CdAnd).
     #[error("rejected runing a command over ssh that expects a specific working directory to be carried over to remote.")]
     CommandHasCwd,
+
+    /// The command was not spawned since the session only allows
+    /// subsystems, see
+    /// [`RemoteCommandMode::SubsystemOnly`](crate::RemoteCommandMode::SubsystemOnly).
+    #[error("rejected running a command on a session that only allows subsystems")]
+    CommandRejected,
+
+    /// The command was not spawned since the helper it names, e.g.
+    /// [`OwningCommand::pipe`](crate::OwningCommand::pipe), relies on shell
+    /// syntax not understood by the remote shell set with
+    /// [`SessionBuilder::remote_shell`](crate::SessionBuilder::remote_shell).
+    #[error("`{0}` is not supported by the remote shell")]
+    UnsupportedShellSyntax(&'static str),
+
+    /// The session is draining after
+    /// [`Session::stop_accepting`](crate::Session::stop_accepting), so no
+    /// new command or port forwarding request is accepted.
+    #[error("the session is closing")]
+    SessionClosing,
+
+    /// Failed to read or write a local file, e.g. while transferring it
+    /// with [`Session::scp`](crate::Session::scp).
+    #[error("failed to access local file")]
+    LocalIo(#[source] io::Error),
+
+    /// The remote command wrote more to stdout or stderr than allowed by
+    /// [`OwningCommand::max_output_size`](crate::OwningCommand::max_output_size).
+    #[error("the output of the remote command exceeds the maximum size")]
+    OutputTooLarge,
+
+    /// The output of the command is not valid in the encoding given to
+    /// [`OwningCommand::output_encoding`](crate::OwningCommand::output_encoding),
+    /// whose name is included.
+    #[cfg(feature = "encoding")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "encoding")))]
+    #[error("the output of the remote command is not valid {0}")]
+    InvalidOutputEncoding(&'static str),
 }
 
 #[cfg(feature = "native-mux")]
@@ -103,6 +168,112 @@ impl From<openssh_mux_client::Error> for Error {
 }
 
 impl Error {
+    /// The underlying io error, if any.
+    fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::Master(err)
+            | Error::Connect(err)
+            | Error::ServerThrottled(err)
+            | Error::Remote(err)
+            | Error::Cleanup(err)
+            | Error::ChildIo(err)
+            | Error::LocalIo(err) => Some(err),
+
+            #[cfg(feature = "process-mux")]
+            Error::Ssh(err) => Some(err),
+
+            #[cfg(feature = "native-mux")]
+            Error::SshMux(openssh_mux_client::Error::IOError(err)) => Some(err),
+
+            _ => None,
+        }
+    }
+
+    /// Return `true` if the error indicates that the connection to the ssh
+    /// multiplex master is broken.
+    pub(crate) fn is_master_failure(&self) -> bool {
+        match self {
+            Error::Master(_) | Error::Disconnected => true,
+
+            #[cfg(feature = "native-mux")]
+            Error::SshMux(_) => true,
+
+            _ => false,
+        }
+    }
+
+    /// Return `true` if the error is likely to be temporary, so that retrying
+    /// the operation (possibly after reconnecting) may succeed.
+    ///
+    /// This covers [`Error::Disconnected`] as well as timeouts, refused,
+    /// reset or aborted connections and interrupted io in any of the variants
+    /// carrying an io error.
+    pub fn is_transient(&self) -> bool {
+        use io::ErrorKind::*;
+
+        match self {
+            Error::Disconnected | Error::ServerThrottled(_) => true,
+            err => err.io_error().map_or(false, |err| {
+                matches!(
+                    err.kind(),
+                    TimedOut
+                        | ConnectionRefused
+                        | ConnectionReset
+                        | ConnectionAborted
+                        | NotConnected
+                        | Interrupted
+                        | WouldBlock
+                )
+            }),
+        }
+    }
+
+    /// Return `true` if the remote host rejected the credentials presented
+    /// while establishing the connection.
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self, Error::Connect(err) if err.kind() == io::ErrorKind::PermissionDenied)
+    }
+
+    /// Return `true` if something could not be found, e.g. the hostname could
+    /// not be resolved, the local `ssh` binary is missing, or any of the
+    /// variants carrying an io error of kind [`io::ErrorKind::NotFound`].
+    pub fn is_not_found(&self) -> bool {
+        match self.io_error() {
+            Some(err) if err.kind() == io::ErrorKind::NotFound => true,
+            Some(err) if matches!(self, Error::Connect(_)) => {
+                err.to_string().starts_with("Could not resolve")
+            }
+            _ => false,
+        }
+    }
+
+    /// Why ssh failed to connect, if this is an [`Error::Connect`] caused
+    /// by ssh exiting with an error, e.g. to tell a changed host key from
+    /// rejected credentials.
+    pub fn connect_error(&self) -> Option<&SshConnectError> {
+        match self {
+            Error::Connect(err) => err
+                .get_ref()?
+                .downcast_ref::<ConnectFailure>()
+                .map(|failure| &failure.reason),
+            _ => None,
+        }
+    }
+
+    /// Why the remote host failed to access a file, if this is an
+    /// [`Error::Remote`] whose message is recognized, e.g. to retry on
+    /// another host once the quota is exceeded but not when the permission
+    /// is denied.
+    pub fn transfer_error(&self) -> Option<TransferError> {
+        match self {
+            Error::Remote(err) => err
+                .get_ref()?
+                .downcast_ref::<TransferFailure>()
+                .map(|failure| failure.reason),
+            _ => None,
+        }
+    }
+
     pub(crate) fn interpret_ssh_error(stderr: &str) -> Self {
         // we want to turn the string-only ssh error into something a little more "handleable".
         // we do this by trying to interpret the output from `ssh`. this is error-prone, but
@@ -118,6 +289,19 @@ impl Error {
             // added to hosts file -- let's ignore that message
             stderr = stderr.split_once('\n').map(|x| x.1.trim()).unwrap_or("");
         }
+        let reason = SshConnectError::parse(stderr);
+        if stderr.contains("Exceeded MaxStartups")
+            || (reason != SshConnectError::ProxyError
+                && (stderr.contains("kex_exchange_identification: ")
+                    || stderr.contains("ssh_exchange_identification: "))
+                && (stderr.contains("Connection closed by remote host")
+                    || stderr.contains("Connection reset by peer")))
+        {
+            // sshd drops connections before sending its banner once
+            // `MaxStartups` is exceeded.
+            return Error::ServerThrottled(io::Error::new(io::ErrorKind::ConnectionReset, stderr));
+        }
+
         let mut kind = io::ErrorKind::ConnectionAborted;
         let mut err = stderr.splitn(2, ": ");
         if let Some(ssh_error) = err.next() {
@@ -157,15 +341,18 @@ impl Error {
             }
         }
 
-        // NOTE: we may want to provide more structured connection errors than just io::Error?
         // NOTE: can we re-use this method for non-connect cases?
-        Error::Connect(io::Error::new(kind, stderr))
+        let failure = ConnectFailure {
+            reason,
+            stderr: stderr.to_owned(),
+        };
+        Error::Connect(io::Error::new(kind, failure))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{io, Error};
+    use super::{io, Error, SshConnectError};
 
     #[test]
     fn parse_error() {
@@ -234,4 +421,61 @@ mod tests {
         assert!(!format!("{}", e).is_empty());
         assert!(e.source().is_none());
     }
+
+    #[test]
+    fn categories() {
+        let connect = |stderr| Error::interpret_ssh_error(stderr);
+
+        let e = connect("ssh: connect to host 127.0.0.1 port 9: Connection refused");
+        assert!(e.is_transient());
+        assert!(!e.is_auth_failure());
+        assert!(!e.is_not_found());
+
+        let e = connect("ssh: connect to host 192.0.2.1 port 22: Connection timed out");
+        assert!(e.is_transient());
+
+        let e = connect("test-user@127.0.0.1: Permission denied (publickey).");
+        assert!(!e.is_transient());
+        assert!(e.is_auth_failure());
+        assert!(!e.is_not_found());
+
+        let e = connect("ssh: Could not resolve hostname bad.invalid: Name or service not known");
+        assert!(!e.is_transient());
+        assert!(!e.is_auth_failure());
+        assert!(e.is_not_found());
+
+        let e = Error::Disconnected;
+        assert!(e.is_transient());
+        assert!(!e.is_auth_failure());
+        assert!(!e.is_not_found());
+
+        let e = Error::ChildIo(io::Error::new(io::ErrorKind::NotFound, "test"));
+        assert!(!e.is_transient());
+        assert!(e.is_not_found());
+
+        let e = connect("kex_exchange_identification: Connection closed by remote host\r\nConnection closed by 127.0.0.1 port 2222");
+        assert!(matches!(e, Error::ServerThrottled(_)));
+        assert!(e.is_transient());
+
+        let e = connect("kex_exchange_identification: read: Connection reset by peer");
+        assert!(matches!(e, Error::ServerThrottled(_)));
+
+        let e = connect("test-user@127.0.0.1: Permission denied (publickey).");
+        assert_eq!(
+            e.connect_error(),
+            Some(&SshConnectError::AuthFailed {
+                methods_tried: vec!["publickey".to_owned()]
+            })
+        );
+        assert_eq!(e.to_string(), "failed to connect to the remote host");
+
+        // A jump host that cannot reach the host is not throttled.
+        let e = connect("channel 0: open failed: connect failed: Connection refused\r\nstdio forwarding failed\r\nkex_exchange_identification: Connection closed by remote host");
+        assert_eq!(e.connect_error(), Some(&SshConnectError::ProxyError));
+
+        assert!(Error::Disconnected.connect_error().is_none());
+
+        assert!(!Error::RemoteProcessTerminated.is_transient());
+        assert!(!Error::CommandHasEnv.is_transient());
+    }
 }
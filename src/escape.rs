@@ -1,8 +1,20 @@
-//! Escape characters that may have special meaning in a shell, including spaces.
-//! This is a modified version of the [`shell-escape::unix`] module of [`shell-escape`] crate.
+//! Escaping of arguments for the shell of the remote host, as done by
+//! [`OwningCommand::arg`](crate::OwningCommand::arg) for each
+//! [`RemoteShell`](crate::RemoteShell).
 //!
-//! [`shell-escape`]: https://crates.io/crates/shell-escape
-//! [`shell-escape::unix`]: https://docs.rs/shell-escape/latest/src/shell_escape/lib.rs.html#101
+//! These are useful to build the arguments given to
+//! [`OwningCommand::raw_arg`](crate::OwningCommand::raw_arg), or scripts,
+//! with the same escaping as the crate. Arguments which need no escaping are
+//! returned as is.
+//!
+//! ```
+//! use openssh::escape;
+//! use std::ffi::OsStr;
+//!
+//! assert_eq!(escape::posix(OsStr::new("it's")), OsStr::new(r"'it'\''s'"));
+//! assert_eq!(escape::powershell(OsStr::new("it's")), OsStr::new("'it''s'"));
+//! assert_eq!(escape::cmd(OsStr::new("a & b")), OsStr::new(r#"^"a ^& b^""#));
+//! ```
 
 use std::{
     borrow::Cow,
@@ -15,15 +27,14 @@ fn allowed(byte: u8) -> bool {
     matches!(byte, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'=' | b'/' | b',' | b'.' | b'+')
 }
 
-/// Escape characters that may have special meaning in a shell, including spaces.
+/// Escape characters that may have special meaning in a POSIX shell,
+/// including spaces, by single quoting `s`.
 ///
-/// **Note**: This function is an adaptation of [`shell-escape::unix::escape`].
-/// This function exists only for type compatibility and the implementation is
-/// almost exactly the same as [`shell-escape::unix::escape`].
+/// **Note**: This function is an adaptation of [`shell-escape::unix::escape`],
+/// whose implementation is almost exactly the same.
 ///
 /// [`shell-escape::unix::escape`]: https://docs.rs/shell-escape/latest/src/shell_escape/lib.rs.html#101
-///
-pub(crate) fn escape(s: &OsStr) -> Cow<'_, OsStr> {
+pub fn posix(s: &OsStr) -> Cow<'_, OsStr> {
     let as_bytes = s.as_bytes();
     let all_allowed = as_bytes.iter().copied().all(allowed);
 
@@ -50,6 +61,122 @@ pub(crate) fn escape(s: &OsStr) -> Cow<'_, OsStr> {
     OsString::from_vec(escaped).into()
 }
 
+/// Same as [`posix`], for csh and tcsh, in which newlines have to be escaped
+/// even within single quotes.
+pub fn csh(s: &OsStr) -> Cow<'_, OsStr> {
+    let escaped = posix(s);
+    if !escaped.as_bytes().contains(&b'\n') {
+        return escaped;
+    }
+
+    let mut bytes = Vec::with_capacity(escaped.len() + 1);
+    for &b in escaped.as_bytes() {
+        if b == b'\n' {
+            bytes.push(b'\\');
+        }
+        bytes.push(b);
+    }
+    OsString::from_vec(bytes).into()
+}
+
+/// Quote `s` as a verbatim string of PowerShell, in which only single
+/// quotes, including their typographic variants, are special.
+pub fn powershell(s: &OsStr) -> Cow<'_, OsStr> {
+    let as_bytes = s.as_bytes();
+    let all_allowed = as_bytes
+        .iter()
+        .all(|&b| allowed(b) && !matches!(b, b',' | b'+'));
+
+    if !as_bytes.is_empty() && all_allowed {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = Vec::with_capacity(as_bytes.len() + 2);
+    escaped.push(b'\'');
+    for (i, &b) in as_bytes.iter().enumerate() {
+        escaped.push(b);
+        // U+2018 to U+201B, encoded as `E2 80 98` to `E2 80 9B`.
+        let typographic = i >= 2
+            && as_bytes[i - 2] == 0xE2
+            && as_bytes[i - 1] == 0x80
+            && matches!(b, 0x98..=0x9B);
+        if b == b'\'' {
+            escaped.push(b);
+        } else if typographic {
+            escaped.extend_from_slice(&as_bytes[i - 2..=i]);
+        }
+    }
+    escaped.push(b'\'');
+    OsString::from_vec(escaped).into()
+}
+
+/// Quote `s` so that it is parsed back as a single argument by programs
+/// splitting their command line themselves following the convention of
+/// `CommandLineToArgvW`, e.g. on Windows.
+///
+/// This is not enough for command lines interpreted by `cmd.exe`, see
+/// [`cmd`].
+pub fn argv(s: &OsStr) -> Cow<'_, OsStr> {
+    let as_bytes = s.as_bytes();
+    if !as_bytes.is_empty()
+        && !as_bytes
+            .iter()
+            .any(|b| matches!(b, b' ' | b'\t' | b'\n' | b'"'))
+    {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = Vec::with_capacity(as_bytes.len() + 2);
+    escaped.push(b'"');
+    let mut backslashes = 0;
+    for &b in as_bytes {
+        match b {
+            b'\\' => backslashes += 1,
+            b'"' => {
+                // Backslashes preceding a quote are escaped, as is the quote.
+                escaped.resize(escaped.len() + backslashes * 2 + 1, b'\\');
+                backslashes = 0;
+            }
+            _ => {
+                escaped.resize(escaped.len() + backslashes, b'\\');
+                backslashes = 0;
+            }
+        }
+        if b != b'\\' {
+            escaped.push(b);
+        }
+    }
+    // Backslashes preceding the closing quote are escaped.
+    escaped.resize(escaped.len() + backslashes * 2, b'\\');
+    escaped.push(b'"');
+    OsString::from_vec(escaped).into()
+}
+
+/// Same as [`argv`], for command lines run by `cmd.exe`, e.g. the default
+/// shell of Windows OpenSSH servers, in which the characters special to
+/// `cmd.exe`, including the quotes added by [`argv`], are escaped with `^`.
+pub fn cmd(s: &OsStr) -> Cow<'_, OsStr> {
+    let quoted = argv(s);
+    let special = |b: &u8| {
+        matches!(
+            b,
+            b'(' | b')' | b'%' | b'!' | b'^' | b'"' | b'<' | b'>' | b'&' | b'|'
+        )
+    };
+    if !quoted.as_bytes().iter().any(special) {
+        return quoted;
+    }
+
+    let mut escaped = Vec::with_capacity(quoted.len() + 4);
+    for b in quoted.as_bytes() {
+        if special(b) {
+            escaped.push(b'^');
+        }
+        escaped.push(*b);
+    }
+    OsString::from_vec(escaped).into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,7 +187,7 @@ mod tests {
 
     fn test_escape_from_bytes(input: &[u8], expected: &[u8]) {
         let input_os_str = OsStr::from_bytes(input);
-        let observed_os_str = escape(input_os_str);
+        let observed_os_str = posix(input_os_str);
         let expected_os_str = OsStr::from_bytes(expected);
         assert_eq!(observed_os_str, expected_os_str);
     }
@@ -88,4 +215,31 @@ mod tests {
             &[b'\'', 0x66, 0x6f, 0x80, 0x6f, b'\''],
         );
     }
+
+    #[test]
+    fn test_escape_dialects() {
+        let csh = |s: &str| super::csh(OsStr::new(s)).into_owned();
+        assert_eq!(csh("a-b"), "a-b");
+        assert_eq!(csh("it's"), r#"'it'\''s'"#);
+        assert_eq!(csh("a\nb!"), "'a\\\nb'\\!''");
+
+        let powershell = |s: &str| super::powershell(OsStr::new(s)).into_owned();
+        assert_eq!(powershell("a-b.txt"), "a-b.txt");
+        assert_eq!(powershell("a,b"), "'a,b'");
+        assert_eq!(powershell("it's $HOME"), "'it''s $HOME'");
+        assert_eq!(powershell("it\u{2019}s"), "'it\u{2019}\u{2019}s'");
+        assert_eq!(powershell(""), "''");
+
+        let argv = |s: &str| super::argv(OsStr::new(s)).into_owned();
+        assert_eq!(argv(r"C:\Program"), r"C:\Program");
+        assert_eq!(argv(r"C:\Program Files\"), r#""C:\Program Files\\""#);
+        assert_eq!(argv(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(argv(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(argv(""), r#""""#);
+
+        let cmd = |s: &str| super::cmd(OsStr::new(s)).into_owned();
+        assert_eq!(cmd("dir"), "dir");
+        assert_eq!(cmd("%PATH%"), "^%PATH^%");
+        assert_eq!(cmd(r#"a "b" | c"#), r#"^"a \^"b\^" ^| c^""#);
+    }
 }
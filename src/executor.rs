@@ -0,0 +1,138 @@
+use super::{Error, FileOps, ReconnectingSession, Session};
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Output;
+
+/// Future returned by the methods of [`RemoteExecutor`] and
+/// [`FileTransfer`].
+pub type ExecFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// Runs commands on a remote host, as implemented by [`Session`] and
+/// [`ReconnectingSession`].
+///
+/// This trait and [`FileTransfer`] are object safe, so that applications
+/// can store e.g. an `Arc<dyn RemoteExecutor>` and substitute fakes in
+/// their tests, or another transport, without making their code generic.
+///
+/// ```no_run
+/// use openssh::{Error, RemoteExecutor};
+///
+/// async fn kernel(host: &dyn RemoteExecutor) -> Result<String, Error> {
+///     let output = host.output("uname", &["-r"]).await?;
+///     Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+/// }
+/// ```
+pub trait RemoteExecutor: Send + Sync {
+    /// Run `program` with `args`, escaped as with [`Session::command`], and
+    /// collect its output as with
+    /// [`OwningCommand::output`](crate::OwningCommand::output).
+    fn output<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> ExecFuture<'a, Output>;
+
+    /// Run `command` with the remote shell as with [`Session::shell`], and
+    /// collect its output.
+    fn shell_output<'a>(&'a self, command: &'a str) -> ExecFuture<'a, Output>;
+
+    /// Check the connection to the remote host, as with [`Session::check`].
+    fn check(&self) -> ExecFuture<'_, ()>;
+}
+
+impl RemoteExecutor for Session {
+    fn output<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> ExecFuture<'a, Output> {
+        Box::pin(async move { self.command(program).args(args).output().await })
+    }
+
+    fn shell_output<'a>(&'a self, command: &'a str) -> ExecFuture<'a, Output> {
+        Box::pin(async move { self.shell(command).output().await })
+    }
+
+    fn check(&self) -> ExecFuture<'_, ()> {
+        Box::pin(Session::check(self))
+    }
+}
+
+/// Commands are retried after reconnecting, see [`ReconnectingSession::run`].
+impl RemoteExecutor for ReconnectingSession {
+    fn output<'a>(&'a self, program: &'a str, args: &'a [&'a str]) -> ExecFuture<'a, Output> {
+        Box::pin(
+            self.run(
+                move |session| async move { session.command(program).args(args).output().await },
+            ),
+        )
+    }
+
+    fn shell_output<'a>(&'a self, command: &'a str) -> ExecFuture<'a, Output> {
+        Box::pin(self.run(move |session| async move { session.shell(command).output().await }))
+    }
+
+    fn check(&self) -> ExecFuture<'_, ()> {
+        Box::pin(self.run(|session| async move { session.check().await }))
+    }
+}
+
+/// Transfers files to and from a remote host, as implemented by
+/// [`Session`] with [`Scp`](crate::Scp) and by [`FileOps`].
+///
+/// Remote paths are relative to the home directory of the remote user.
+pub trait FileTransfer: Send + Sync {
+    /// Read the content of the remote file `remote`.
+    fn read<'a>(&'a self, remote: &'a str) -> ExecFuture<'a, Vec<u8>>;
+
+    /// Create or truncate the remote file `remote` and write `content` to
+    /// it.
+    fn write<'a>(&'a self, remote: &'a str, content: &'a [u8]) -> ExecFuture<'a, ()>;
+
+    /// Copy the local file `local` to the remote file `remote`.
+    fn upload<'a>(&'a self, local: &'a Path, remote: &'a str) -> ExecFuture<'a, ()>;
+
+    /// Copy the remote file `remote` to the local file `local`.
+    fn download<'a>(&'a self, remote: &'a str, local: &'a Path) -> ExecFuture<'a, ()>;
+}
+
+/// Files are read and written with [`FileOps`], and copied with
+/// [`Scp`](crate::Scp).
+impl FileTransfer for Session {
+    fn read<'a>(&'a self, remote: &'a str) -> ExecFuture<'a, Vec<u8>> {
+        Box::pin(async move { self.file_ops().read(remote).await })
+    }
+
+    fn write<'a>(&'a self, remote: &'a str, content: &'a [u8]) -> ExecFuture<'a, ()> {
+        Box::pin(async move { self.file_ops().write(remote, content).await })
+    }
+
+    fn upload<'a>(&'a self, local: &'a Path, remote: &'a str) -> ExecFuture<'a, ()> {
+        Box::pin(async move { self.scp().send(local, remote).await })
+    }
+
+    fn download<'a>(&'a self, remote: &'a str, local: &'a Path) -> ExecFuture<'a, ()> {
+        Box::pin(async move { self.scp().recv(remote, local).await })
+    }
+}
+
+/// Files are copied through memory, so this is only suited to small files.
+impl FileTransfer for FileOps<'_> {
+    fn read<'a>(&'a self, remote: &'a str) -> ExecFuture<'a, Vec<u8>> {
+        Box::pin(FileOps::read(self, remote))
+    }
+
+    fn write<'a>(&'a self, remote: &'a str, content: &'a [u8]) -> ExecFuture<'a, ()> {
+        Box::pin(FileOps::write(self, remote, content))
+    }
+
+    fn upload<'a>(&'a self, local: &'a Path, remote: &'a str) -> ExecFuture<'a, ()> {
+        Box::pin(async move {
+            let content = tokio::fs::read(local).await.map_err(Error::LocalIo)?;
+            FileOps::write(self, remote, content).await
+        })
+    }
+
+    fn download<'a>(&'a self, remote: &'a str, local: &'a Path) -> ExecFuture<'a, ()> {
+        Box::pin(async move {
+            let content = FileOps::read(self, remote).await?;
+            tokio::fs::write(local, content)
+                .await
+                .map_err(Error::LocalIo)
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use super::escape;
+use super::transfer_error::TransferFailure;
+use super::{Error, OwningCommand, Session, Stdio};
+
+use std::io;
+use std::path::Path;
+use std::process::Output;
+
+use tokio::io::AsyncWriteExt;
+
+/// Turn the stderr of a failed command into an error, recognizing the
+/// common errno messages so that callers can match on the error kind and
+/// [`Error::transfer_error`].
+pub(crate) fn remote_error(stderr: &[u8]) -> Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    Error::Remote(TransferFailure::io_error(stderr.trim().to_owned()))
+}
+
+/// File operations implemented with POSIX commands run on the remote host,
+/// as returned by [`Session::file_ops`].
+///
+/// This is meant for servers where both the sftp subsystem and `scp` are
+/// disabled by policy. The methods mirror those of `Fs` from
+/// [`openssh-sftp-client`] so that callers can switch between the two,
+/// except that contents are returned as `Vec<u8>`.
+///
+/// Every operation runs a separate command, so this is much slower than
+/// sftp for many small operations. Failures of the remote commands are
+/// reported as [`Error::Remote`], with [`io::ErrorKind::NotFound`] or
+/// [`io::ErrorKind::PermissionDenied`], and [`Error::transfer_error`], when
+/// recognized from their output.
+///
+/// [`openssh-sftp-client`]: https://crates.io/crates/openssh-sftp-client
+#[derive(Debug, Clone, Copy)]
+pub struct FileOps<'s> {
+    session: &'s Session,
+}
+
+impl<'s> FileOps<'s> {
+    pub(crate) fn new(session: &'s Session) -> Self {
+        Self { session }
+    }
+
+    /// Run `sh -c script` with `path` as `$1`.
+    fn script(&self, script: &str, path: &Path) -> OwningCommand<&'s Session> {
+        let mut cmd = self.session.command("sh");
+        cmd.arg("-c").arg(script).arg("sh");
+        cmd.raw_arg(&*escape::posix(path.as_os_str()));
+        cmd
+    }
+
+    async fn run(
+        mut cmd: OwningCommand<&'s Session>,
+        stdin: Option<&[u8]>,
+    ) -> Result<Output, Error> {
+        let stdin_cfg = if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        let mut child = cmd
+            .stdin(stdin_cfg)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .await?;
+
+        if let Some(content) = stdin {
+            let mut child_stdin = child.stdin().take().unwrap();
+            child_stdin
+                .write_all(content)
+                .await
+                .map_err(Error::ChildIo)?;
+            // Close stdin so that the remote command sees the end of it.
+            drop(child_stdin);
+        }
+
+        let output = child.wait_with_output().await?;
+        if output.status.success() {
+            Ok(output)
+        } else {
+            Err(remote_error(&output.stderr))
+        }
+    }
+
+    /// Return `true` if `path` exists on the remote host, following
+    /// symlinks.
+    pub async fn exists(&self, path: impl AsRef<Path>) -> Result<bool, Error> {
+        let mut cmd = self.session.command("test");
+        cmd.arg("-e")
+            .raw_arg(&*escape::posix(path.as_ref().as_os_str()));
+        let output = cmd.stderr(Stdio::piped()).output().await?;
+
+        // `test` exits with 1 if the file does not exist, and more on error.
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(remote_error(&output.stderr)),
+        }
+    }
+
+    /// Read the content of the remote file `path`.
+    pub async fn read(&self, path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+        let mut cmd = self.session.command("cat");
+        cmd.arg("--")
+            .raw_arg(&*escape::posix(path.as_ref().as_os_str()));
+        Ok(Self::run(cmd, None).await?.stdout)
+    }
+
+    /// Create or truncate the remote file `path` and write `content` to it.
+    pub async fn write(
+        &self,
+        path: impl AsRef<Path>,
+        content: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let cmd = self.script("cat > \"$1\"", path.as_ref());
+        Self::run(cmd, Some(content.as_ref())).await.map(|_| ())
+    }
+
+    /// Append `content` to the remote file `path`, creating it if needed.
+    pub async fn append(
+        &self,
+        path: impl AsRef<Path>,
+        content: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        let cmd = self.script("cat >> \"$1\"", path.as_ref());
+        Self::run(cmd, Some(content.as_ref())).await.map(|_| ())
+    }
+
+    /// Set the permissions of the remote file `path` to `mode`, e.g.
+    /// `0o644`.
+    pub async fn chmod(&self, path: impl AsRef<Path>, mode: u32) -> Result<(), Error> {
+        let mut cmd = self.session.command("chmod");
+        cmd.arg(format!("{:o}", mode & 0o7777))
+            .arg("--")
+            .raw_arg(&*escape::posix(path.as_ref().as_os_str()));
+        Self::run(cmd, None).await.map(|_| ())
+    }
+
+    /// Change the owner of the remote file `path` to the user `uid` and
+    /// the group `gid`, e.g. to fix the ownership of uploaded files, which
+    /// usually requires root.
+    ///
+    /// Use [`user_id`](Self::user_id) to find the `uid` of a user by name.
+    pub async fn chown(&self, path: impl AsRef<Path>, uid: u32, gid: u32) -> Result<(), Error> {
+        let mut cmd = self.session.command("chown");
+        cmd.arg(format!("{}:{}", uid, gid))
+            .arg("--")
+            .raw_arg(&*escape::posix(path.as_ref().as_os_str()));
+        Self::run(cmd, None).await.map(|_| ())
+    }
+
+    /// Return the id of the remote user named `name`, with `id -u`.
+    pub async fn user_id(&self, name: &str) -> Result<u32, Error> {
+        let mut cmd = self.session.command("id");
+        cmd.arg("-u").arg("--").arg(name);
+        let output = Self::run(cmd, None).await?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout.trim().parse().map_err(|_| {
+            Error::Remote(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected output of id: {}", stdout.trim()),
+            ))
+        })
+    }
+}
@@ -0,0 +1,277 @@
+//! Aggregation of the results of a command run across many hosts, e.g.
+//! with [`broadcast`](crate::broadcast).
+//!
+//! ```no_run
+//! # async fn f(sessions: Vec<(String, openssh::Session)>) {
+//! use openssh::fleet::Report;
+//!
+//! let mut report = Report::new();
+//! for (host, session) in &sessions {
+//!     report.add(host, &session.command("uname").arg("-r").output().await);
+//! }
+//! println!("{}", report);
+//! # }
+//! ```
+
+use super::Error;
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::process::Output;
+
+/// Hosts on which the command exited with the same status and printed the
+/// same stdout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputGroup {
+    digest: u64,
+    status: Option<i32>,
+    stdout: String,
+    hosts: Vec<String>,
+}
+
+impl OutputGroup {
+    /// Hash of the stdout, which is only meant to be compared with the
+    /// other groups of the same report.
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    /// The exit code, or `None` if the command was killed by a signal.
+    pub fn status(&self) -> Option<i32> {
+        self.status
+    }
+
+    /// The stdout, decoded as UTF-8 with invalid bytes replaced.
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    /// The hosts, in the order they were added.
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+}
+
+/// Hosts on which the command could not be run, because of the same kind
+/// of [`Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FailureGroup {
+    kind: String,
+    hosts: Vec<(String, String)>,
+}
+
+impl FailureGroup {
+    /// The kind of error, e.g. `failed to connect to the remote host`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The hosts, in the order they were added, along with the full error
+    /// of each of them.
+    pub fn hosts(&self) -> &[(String, String)] {
+        &self.hosts
+    }
+}
+
+/// The error along with its sources, e.g.
+/// `failed to connect to the remote host: Connection refused`.
+fn error_chain(err: &Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+    chain
+}
+
+/// Results of a command across many hosts, grouped by identical output and
+/// by kind of failure.
+///
+/// With the `serde` feature enabled, the report can be serialized, and it
+/// implements [`Display`](fmt::Display) for humans, with the largest
+/// groups first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report {
+    outputs: Vec<OutputGroup>,
+    failures: Vec<FailureGroup>,
+}
+
+impl Report {
+    /// Create an empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the `result` of the command on `host`.
+    pub fn add(&mut self, host: impl Into<String>, result: &Result<Output, Error>) -> &mut Self {
+        let host = host.into();
+
+        match result {
+            Ok(output) => {
+                let mut hasher = DefaultHasher::new();
+                output.stdout.hash(&mut hasher);
+                let digest = hasher.finish();
+                let status = output.status.code();
+
+                match self
+                    .outputs
+                    .iter_mut()
+                    .find(|group| group.digest == digest && group.status == status)
+                {
+                    Some(group) => group.hosts.push(host),
+                    None => self.outputs.push(OutputGroup {
+                        digest,
+                        status,
+                        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                        hosts: vec![host],
+                    }),
+                }
+            }
+            Err(err) => {
+                let kind = err.to_string();
+                let entry = (host, error_chain(err));
+
+                match self.failures.iter_mut().find(|group| group.kind == kind) {
+                    Some(group) => group.hosts.push(entry),
+                    None => self.failures.push(FailureGroup {
+                        kind,
+                        hosts: vec![entry],
+                    }),
+                }
+            }
+        }
+        self
+    }
+
+    /// The groups of hosts on which the command ran, in the order they
+    /// were first seen.
+    pub fn outputs(&self) -> &[OutputGroup] {
+        &self.outputs
+    }
+
+    /// The groups of hosts on which the command could not be run, in the
+    /// order they were first seen.
+    pub fn failures(&self) -> &[FailureGroup] {
+        &self.failures
+    }
+
+    /// Number of hosts added.
+    pub fn len(&self) -> usize {
+        let outputs: usize = self.outputs.iter().map(|group| group.hosts.len()).sum();
+        let failures: usize = self.failures.iter().map(|group| group.hosts.len()).sum();
+        outputs + failures
+    }
+
+    /// Whether no host is added.
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty() && self.failures.is_empty()
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        "host"
+    } else {
+        "hosts"
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut outputs: Vec<&OutputGroup> = self.outputs.iter().collect();
+        outputs.sort_by_key(|group| std::cmp::Reverse(group.hosts.len()));
+
+        for group in outputs {
+            let n = group.hosts.len();
+            match group.status {
+                Some(code) => writeln!(f, "{} {} exited with {}:", n, plural(n), code)?,
+                None => writeln!(f, "{} {} killed by a signal:", n, plural(n))?,
+            }
+            writeln!(f, "  {}", group.hosts.join(", "))?;
+            for line in group.stdout.lines() {
+                writeln!(f, "  | {}", line)?;
+            }
+        }
+
+        let mut failures: Vec<&FailureGroup> = self.failures.iter().collect();
+        failures.sort_by_key(|group| std::cmp::Reverse(group.hosts.len()));
+
+        for group in failures {
+            let n = group.hosts.len();
+            writeln!(f, "{} {} failed: {}", n, plural(n), group.kind)?;
+            for (host, err) in &group.hosts {
+                writeln!(f, "  {}: {}", host, err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::Error;
+
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    fn output(code: i32, stdout: &str) -> Result<Output, Error> {
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into(),
+            stderr: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn report() {
+        let refused = || {
+            Err(Error::Connect(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Connection refused",
+            )))
+        };
+
+        let mut report = Report::new();
+        report
+            .add("a", &output(0, "5.10\n"))
+            .add("b", &output(0, "6.1\n"))
+            .add("c", &refused())
+            .add("d", &output(0, "6.1\n"))
+            .add("e", &output(1, "6.1\n"))
+            .add("f", &Err(Error::Disconnected))
+            .add("g", &refused());
+
+        assert_eq!(report.len(), 7);
+        assert_eq!(report.outputs().len(), 3);
+        assert_eq!(report.outputs()[1].hosts(), ["b", "d"]);
+        assert_eq!(report.outputs()[1].stdout(), "6.1\n");
+        assert_eq!(report.outputs()[2].status(), Some(1));
+        assert_eq!(report.failures().len(), 2);
+        assert_eq!(
+            report.failures()[0].hosts()[1],
+            (
+                "g".to_owned(),
+                "failed to connect to the remote host: Connection refused".to_owned()
+            )
+        );
+
+        assert_eq!(
+            report.to_string(),
+            "2 hosts exited with 0:\n  b, d\n  | 6.1\n\
+             1 host exited with 0:\n  a\n  | 5.10\n\
+             1 host exited with 1:\n  e\n  | 6.1\n\
+             2 hosts failed: failed to connect to the remote host\n\
+             \x20 c: failed to connect to the remote host: Connection refused\n\
+             \x20 g: failed to connect to the remote host: Connection refused\n\
+             1 host failed: the connection was terminated\n  f: the connection was terminated\n"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! A disposable sshd to run integration tests against, without any
+//! external setup.
+//!
+//! ```no_run
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use openssh::harness::TestServer;
+//!
+//! let server = TestServer::start().await?;
+//! let session = server.builder().connect(server.destination()).await?;
+//!
+//! let output = session.command("echo").arg("foo").output().await?;
+//! assert_eq!(output.stdout, b"foo\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{KnownHosts, SessionBuilder};
+
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tempfile::{Builder, TempDir};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::time::{self, Instant};
+
+/// How long [`TestServer::start`] waits for sshd to accept connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Directories searched for `sshd` after `PATH`, since `sbin` is often
+/// missing from it for regular users.
+const SBIN_DIRS: [&str; 3] = ["/usr/sbin", "/usr/local/sbin", "/sbin"];
+
+/// Absolute path of `sshd`, which refuses to start otherwise.
+fn find_sshd() -> io::Result<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(SBIN_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join("sshd"))
+        .find(|sshd| sshd.is_absolute() && sshd.is_file())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "sshd is not installed"))
+}
+
+/// A port that is free right now, to listen on.
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+async fn keygen(path: &Path) -> io::Result<String> {
+    let status = Command::new("ssh-keygen")
+        .args([
+            "-q",
+            "-t",
+            "ed25519",
+            "-N",
+            "",
+            "-C",
+            "openssh-rs-test",
+            "-f",
+        ])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("ssh-keygen failed: {}", status),
+        ));
+    }
+    fs::read_to_string(path.with_extension("pub"))
+}
+
+/// An sshd running as the current user on a random port of `127.0.0.1`,
+/// accepting a freshly generated key.
+///
+/// Since sshd does not switch users unless it runs as root, sessions log
+/// in as the current user.
+///
+/// The server is killed and its files are removed on drop.
+#[derive(Debug)]
+pub struct TestServer {
+    sshd: Child,
+    port: u16,
+    dir: TempDir,
+}
+
+impl TestServer {
+    /// Generate the keys and configuration of a new sshd in a temporary
+    /// directory, then start it and wait for it to accept connections.
+    ///
+    /// `sshd` and `ssh-keygen` must be installed locally.
+    pub async fn start() -> io::Result<Self> {
+        let sshd = find_sshd()?;
+        let dir = Builder::new().prefix(".openssh-rs-sshd").tempdir()?;
+
+        let host_key = keygen(&dir.path().join("host_key")).await?;
+        let client_key = keygen(&dir.path().join("client_key")).await?;
+        fs::write(dir.path().join("authorized_keys"), client_key)?;
+        fs::write(dir.path().join("ssh_config"), "")?;
+
+        let port = free_port()?;
+        // Only the key type and the key itself, without the comment.
+        let host_key: Vec<&str> = host_key.split_whitespace().take(2).collect();
+        fs::write(
+            dir.path().join("known_hosts"),
+            format!("[127.0.0.1]:{} {}\n", port, host_key.join(" ")),
+        )?;
+
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let config = format!(
+            "Port {port}\n\
+             ListenAddress 127.0.0.1\n\
+             HostKey \"{host_key}\"\n\
+             AuthorizedKeysFile \"{authorized_keys}\"\n\
+             PidFile none\n\
+             StrictModes no\n\
+             PasswordAuthentication no\n\
+             AllowTcpForwarding yes\n\
+             AllowStreamLocalForwarding yes\n\
+             Subsystem sftp internal-sftp\n",
+            port = port,
+            host_key = path("host_key"),
+            authorized_keys = path("authorized_keys"),
+        );
+        fs::write(dir.path().join("sshd_config"), config)?;
+
+        let log = fs::File::create(dir.path().join("sshd.log"))?;
+        let sshd = Command::new(sshd)
+            .arg("-D")
+            .arg("-e")
+            .arg("-f")
+            .arg(dir.path().join("sshd_config"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let mut server = Self { sshd, port, dir };
+        server.wait_ready().await?;
+        Ok(server)
+    }
+
+    /// Wait until sshd sends its banner.
+    async fn wait_ready(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+
+        loop {
+            if let Some(status) = self.sshd.try_wait()? {
+                let log = fs::read_to_string(self.dir.path().join("sshd.log")).unwrap_or_default();
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("sshd exited with {}: {}", status, log.trim_end()),
+                ));
+            }
+
+            if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", self.port)).await {
+                let mut banner = [0; 4];
+                if stream.read_exact(&mut banner).await.is_ok() && &banner == b"SSH-" {
+                    return Ok(());
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "sshd did not start in time",
+                ));
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// The port sshd listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The destination to pass to [`SessionBuilder::connect`] along with
+    /// [`builder`](Self::builder).
+    pub fn destination(&self) -> &str {
+        "127.0.0.1"
+    }
+
+    /// The private key accepted by sshd.
+    pub fn keyfile(&self) -> PathBuf {
+        self.dir.path().join("client_key")
+    }
+
+    /// Return a builder set up to connect to this server, which checks its
+    /// host key strictly and ignores the local ssh config.
+    pub fn builder(&self) -> SessionBuilder {
+        let mut builder = SessionBuilder::default();
+        builder
+            .port(self.port)
+            .keyfile(self.keyfile())
+            .config_file(self.dir.path().join("ssh_config"))
+            .user_known_hosts_file(self.dir.path().join("known_hosts"))
+            .known_hosts_check(KnownHosts::Strict);
+        builder
+    }
+}
@@ -0,0 +1,136 @@
+use super::MasterEvent;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Parse the address in `Authenticated to host ([ip]:port) using "method".`,
+/// where ssh always puts the ip in brackets.
+fn parse_authenticated_addr(message: &str) -> Option<SocketAddr> {
+    let (_, rest) = message.split_once(" ([")?;
+    let (ip, rest) = rest.split_once("]:")?;
+    let (port, _) = rest.split_once(')')?;
+    Some(SocketAddr::new(ip.parse().ok()?, port.parse().ok()?))
+}
+
+/// Metadata about the connection of a [`Session`](crate::Session), as
+/// returned by [`Session::info`](crate::Session::info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    user: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    remote_addr: Option<SocketAddr>,
+    server_version: Option<String>,
+    control_socket: PathBuf,
+}
+
+impl SessionInfo {
+    pub(crate) fn new(control_socket: PathBuf) -> Self {
+        Self {
+            user: None,
+            host: None,
+            port: None,
+            remote_addr: None,
+            server_version: None,
+            control_socket,
+        }
+    }
+
+    /// Fill in the user, host and port from the output of `ssh -G`.
+    pub(crate) fn apply_config(&mut self, config: &str) {
+        for line in config.lines() {
+            let (key, value) = match line.split_once(' ') {
+                Some(pair) => pair,
+                None => continue,
+            };
+
+            match key {
+                "user" => self.user = Some(value.to_owned()),
+                "hostname" => self.host = Some(value.to_owned()),
+                "port" => self.port = value.parse().ok(),
+                _ => (),
+            }
+        }
+    }
+
+    /// Fill in the remote address and the version of the server from the
+    /// log of the master.
+    pub(crate) fn apply_log(&mut self, log: &str) {
+        for line in log.lines() {
+            let event = MasterEvent::parse(line);
+            let message = event.message();
+
+            if let Some(version) = message.split(", remote software version ").nth(1) {
+                self.server_version = Some(version.to_owned());
+            } else if message.starts_with("Authenticated to ") {
+                self.remote_addr = parse_authenticated_addr(message);
+            }
+        }
+    }
+
+    /// The user logged in as, as resolved from the ssh config.
+    ///
+    /// Only known for sessions created by a
+    /// [`SessionBuilder`](crate::SessionBuilder), as for the host and port.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// The host connected to, after applying `HostName` from the ssh config.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The port connected to, as resolved from the ssh config.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The address of the server, which is only logged with a
+    /// [`master_verbosity`](crate::SessionBuilder::master_verbosity) of at
+    /// least 1.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The software version sent by the server in its banner, e.g.
+    /// `OpenSSH_9.2p1 Debian-2+deb12u6`, which is only logged with a
+    /// [`master_verbosity`](crate::SessionBuilder::master_verbosity) of at
+    /// least 1.
+    pub fn server_version(&self) -> Option<&str> {
+        self.server_version.as_deref()
+    }
+
+    /// The path of the control socket of the master.
+    pub fn control_socket(&self) -> &Path {
+        &self.control_socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionInfo;
+
+    use std::path::PathBuf;
+
+    #[test]
+    fn apply() {
+        let mut info = SessionInfo::new(PathBuf::from("/tmp/master"));
+        info.apply_config("user test-user\nhostname 127.0.0.1\nport 2222\nbatchmode yes\n");
+        info.apply_log(
+            "debug1: Connecting to 127.0.0.1 [127.0.0.1] port 2222.\n\
+             debug1: Remote protocol version 2.0, remote software version OpenSSH_9.2p1 Debian-2\n\
+             debug1: Authenticated to 127.0.0.1 ([127.0.0.1]:2222) using \"publickey\".\n",
+        );
+
+        assert_eq!(info.user(), Some("test-user"));
+        assert_eq!(info.host(), Some("127.0.0.1"));
+        assert_eq!(info.port(), Some(2222));
+        assert_eq!(info.remote_addr(), Some("127.0.0.1:2222".parse().unwrap()));
+        assert_eq!(info.server_version(), Some("OpenSSH_9.2p1 Debian-2"));
+
+        let mut info = SessionInfo::new(PathBuf::from("/tmp/master"));
+        info.apply_log("debug1: Authenticated to host ([::1]:22) using \"publickey\".\n");
+        assert_eq!(info.remote_addr(), Some("[::1]:22".parse().unwrap()));
+    }
+}
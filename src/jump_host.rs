@@ -0,0 +1,217 @@
+use super::escape;
+use super::KnownHosts;
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// A jump host with its own settings, added with
+/// [`SessionBuilder::jump_host`](crate::SessionBuilder::jump_host).
+///
+/// Unlike the hosts given to
+/// [`SessionBuilder::jump_hosts`](crate::SessionBuilder::jump_hosts), the
+/// settings of each hop are passed on the command line of the ssh
+/// connecting to it, so multi-hop topologies do not need `~/.ssh/config`.
+///
+/// ```rust
+/// use openssh::{JumpHost, KnownHosts, SessionBuilder};
+///
+/// let mut builder = SessionBuilder::default();
+/// builder
+///     .jump_host(
+///         JumpHost::new("bastion.example.com")
+///             .user("ops")
+///             .keyfile("/etc/keys/bastion")
+///             .known_hosts_check(KnownHosts::Strict),
+///     )
+///     .jump_host(JumpHost::new("10.0.0.1").port(2222));
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JumpHost {
+    destination: String,
+    user: Option<String>,
+    port: Option<u16>,
+    keyfile: Option<PathBuf>,
+    known_hosts_check: Option<KnownHosts>,
+}
+
+impl JumpHost {
+    /// Create a hop to `destination`, either `[user@]host[:port]` as with
+    /// `ssh -J` or `ssh://[user@]host[:port]`.
+    pub fn new(destination: impl Into<String>) -> Self {
+        Self {
+            destination: destination.into(),
+            user: None,
+            port: None,
+            keyfile: None,
+            known_hosts_check: None,
+        }
+    }
+
+    /// Set the user to log in as on this hop.
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Set the port to connect to on this hop.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Authenticate to this hop with only the given keyfile.
+    pub fn keyfile(mut self, keyfile: impl AsRef<Path>) -> Self {
+        self.keyfile = Some(keyfile.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set how the key of this hop is checked, see
+    /// [`SessionBuilder::known_hosts_check`](crate::SessionBuilder::known_hosts_check).
+    ///
+    /// By default, the setting from the ssh config is used.
+    pub fn known_hosts_check(mut self, known_hosts_check: KnownHosts) -> Self {
+        self.known_hosts_check = Some(known_hosts_check);
+        self
+    }
+
+    pub(crate) fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// Whether the hop has no settings of its own, so that it can be
+    /// passed to `ssh -J`.
+    pub(crate) fn is_plain(&self) -> bool {
+        self.user.is_none()
+            && self.port.is_none()
+            && self.keyfile.is_none()
+            && self.known_hosts_check.is_none()
+    }
+
+    /// The command connecting to this hop, with `proxy` as its own
+    /// `ProxyCommand`, and forwarding stdio to the next destination.
+    fn command(&self, proxy: Option<&[u8]>, config_file: Option<&Path>) -> Vec<u8> {
+        let mut args: Vec<OsString> = vec!["ssh".into(), "-o".into(), "BatchMode=yes".into()];
+
+        if let Some(config_file) = config_file {
+            args.push("-F".into());
+            args.push(config_file.into());
+        }
+        if let Some(known_hosts_check) = &self.known_hosts_check {
+            args.push("-o".into());
+            args.push(known_hosts_check.as_option().into());
+        }
+        if let Some(user) = &self.user {
+            args.push("-l".into());
+            args.push(user.into());
+        }
+        if let Some(port) = self.port {
+            args.push("-p".into());
+            args.push(port.to_string().into());
+        }
+        if let Some(keyfile) = &self.keyfile {
+            args.push("-o".into());
+            args.push("IdentitiesOnly=yes".into());
+            args.push("-i".into());
+            args.push(keyfile.into());
+        }
+        if let Some(proxy) = proxy {
+            // The tokens of the inner command are expanded by the ssh
+            // running it, not by the one running this command.
+            let mut option = b"ProxyCommand=".to_vec();
+            option.extend(proxy.iter().flat_map(|&b| {
+                let n = if b == b'%' { 2 } else { 1 };
+                std::iter::repeat(b).take(n)
+            }));
+            args.push("-o".into());
+            args.push(OsString::from_vec(option));
+        }
+        args.push("-W".into());
+        args.push("[%h]:%p".into());
+
+        // `ssh` only accepts a port in the destination with the URI form.
+        let destination = &self.destination;
+        if destination.starts_with("ssh://") || !destination.contains(':') {
+            args.push(destination.into());
+        } else {
+            args.push(format!("ssh://{}", destination).into());
+        }
+
+        let mut command = Vec::new();
+        for arg in &args {
+            if !command.is_empty() {
+                command.push(b' ');
+            }
+            command.extend_from_slice(escape::posix(arg).as_bytes());
+        }
+        command
+    }
+}
+
+/// Build the `ProxyCommand` of the master connecting through `hops`, the
+/// first of which is reached with `proxy_command` if any.
+pub(crate) fn proxy_command(
+    hops: &[JumpHost],
+    proxy_command: Option<&str>,
+    config_file: Option<&Path>,
+) -> Option<OsString> {
+    let mut proxy = proxy_command.map(|command| command.as_bytes().to_vec());
+    for hop in hops {
+        proxy = Some(hop.command(proxy.as_deref(), config_file));
+    }
+    proxy.map(OsString::from_vec)
+}
+
+/// Join plain `hops` for `ssh -J`.
+pub(crate) fn proxy_jump(hops: &[JumpHost]) -> OsString {
+    let mut jump = OsString::new();
+    for hop in hops {
+        if !jump.is_empty() {
+            jump.push(",");
+        }
+        jump.push(OsStr::new(hop.destination()));
+    }
+    jump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{proxy_command, proxy_jump, JumpHost};
+    use crate::KnownHosts;
+
+    use std::path::Path;
+
+    #[test]
+    fn jump() {
+        let hops = [JumpHost::new("a"), JumpHost::new("user@b:2222")];
+        assert_eq!(proxy_jump(&hops), "a,user@b:2222");
+        assert_eq!(proxy_command(&[], None, None), None);
+    }
+
+    #[test]
+    fn command() {
+        let hops = [
+            JumpHost::new("a")
+                .user("ops")
+                .keyfile("/keys/a key")
+                .known_hosts_check(KnownHosts::Strict),
+            JumpHost::new("user@b:2222"),
+        ];
+
+        assert_eq!(
+            proxy_command(&hops[..1], Some("nc -X 5 -x proxy:1080 %h %p"), None).unwrap(),
+            "ssh -o BatchMode=yes -o StrictHostKeyChecking=yes -l ops \
+             -o IdentitiesOnly=yes -i '/keys/a key' \
+             -o 'ProxyCommand=nc -X 5 -x proxy:1080 %%h %%p' -W '[%h]:%p' a"
+        );
+
+        assert_eq!(
+            proxy_command(&hops, None, Some(Path::new("/cfg"))).unwrap(),
+            "ssh -o BatchMode=yes -F /cfg \
+             -o 'ProxyCommand=ssh -o BatchMode=yes -F /cfg -o StrictHostKeyChecking=yes -l ops \
+             -o IdentitiesOnly=yes -i '\\''/keys/a key'\\'' -W '\\''[%%h]:%%p'\\'' a' \
+             -W '[%h]:%p' 'ssh://user@b:2222'"
+        );
+    }
+}
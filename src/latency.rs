@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+/// Round-trip times measured by [`Session::measure_latency`](crate::Session::measure_latency).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latency {
+    /// The samples, sorted.
+    sorted: Vec<Duration>,
+    mean: Duration,
+    jitter: Duration,
+}
+
+impl Latency {
+    /// Compute the statistics of `samples`, in the order they were taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is empty.
+    pub(crate) fn new(samples: Vec<Duration>) -> Self {
+        assert!(!samples.is_empty(), "at least one sample is required");
+
+        let n = samples.len() as u32;
+        let mean = samples.iter().sum::<Duration>() / n;
+        // Mean difference between consecutive samples, as in RFC 3550.
+        let jitter = if samples.len() < 2 {
+            Duration::ZERO
+        } else {
+            let diffs: Duration = samples
+                .windows(2)
+                .map(|pair| {
+                    pair[0]
+                        .checked_sub(pair[1])
+                        .unwrap_or_else(|| pair[1] - pair[0])
+                })
+                .sum();
+            diffs / (n - 1)
+        };
+
+        let mut sorted = samples;
+        sorted.sort_unstable();
+        Self {
+            sorted,
+            mean,
+            jitter,
+        }
+    }
+
+    /// Number of samples.
+    pub fn samples(&self) -> usize {
+        self.sorted.len()
+    }
+
+    /// The shortest round trip.
+    pub fn min(&self) -> Duration {
+        self.sorted[0]
+    }
+
+    /// The longest round trip.
+    pub fn max(&self) -> Duration {
+        self.sorted[self.sorted.len() - 1]
+    }
+
+    /// The mean round trip.
+    pub fn mean(&self) -> Duration {
+        self.mean
+    }
+
+    /// The mean variation between consecutive round trips.
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    /// The round trip below which `percentile` percent of the samples lie,
+    /// using the nearest-rank method, e.g. `percentile(50.0)` is the median.
+    ///
+    /// `percentile` is clamped to `0.0..=100.0`.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let percentile = percentile.clamp(0.0, 100.0);
+        let rank = (percentile / 100.0 * self.sorted.len() as f64).ceil() as usize;
+        self.sorted[rank.saturating_sub(1)]
+    }
+
+    /// Scale `timeout`, chosen for a round trip of `baseline`, to the
+    /// observed latency, e.g. to avoid spurious timeouts over satellite or
+    /// VPN links.
+    ///
+    /// The 95th percentile of the samples is used, so that occasional slow
+    /// round trips are accounted for. `timeout` is never shortened.
+    pub fn scale_timeout(&self, timeout: Duration, baseline: Duration) -> Duration {
+        let observed = self.percentile(95.0);
+        if observed <= baseline || baseline.is_zero() {
+            return timeout;
+        }
+        timeout.mul_f64(observed.as_secs_f64() / baseline.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Latency;
+
+    use std::time::Duration;
+
+    #[test]
+    fn latency() {
+        let ms = Duration::from_millis;
+        let latency = Latency::new(vec![ms(30), ms(10), ms(20), ms(40)]);
+
+        assert_eq!(latency.samples(), 4);
+        assert_eq!(latency.min(), ms(10));
+        assert_eq!(latency.max(), ms(40));
+        assert_eq!(latency.mean(), ms(25));
+        // |30 - 10| + |10 - 20| + |20 - 40|
+        assert_eq!(latency.jitter(), ms(50) / 3);
+        assert_eq!(latency.percentile(50.0), ms(20));
+        assert_eq!(latency.percentile(95.0), ms(40));
+        assert_eq!(latency.percentile(0.0), ms(10));
+
+        assert_eq!(latency.scale_timeout(ms(1000), ms(20)), ms(2000));
+        assert_eq!(latency.scale_timeout(ms(1000), ms(100)), ms(1000));
+
+        let latency = Latency::new(vec![ms(5)]);
+        assert_eq!(latency.jitter(), Duration::ZERO);
+    }
+}
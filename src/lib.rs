@@ -163,17 +163,22 @@ mod stdio;
 pub use stdio::{ChildStderr, ChildStdin, ChildStdout, Stdio};
 
 mod session;
-pub use session::Session;
+pub use session::{ConnectionHealth, Ownership, Session};
 
 mod builder;
-pub use builder::{ControlPersist, KnownHosts, SessionBuilder};
+pub use builder::{
+    ConnectionRecipe, ControlPersist, IoPriority, KnownHosts, RemoteCommandMode, RemoteShell,
+    SessionBuilder,
+};
 
 mod command;
-pub use command::{OverSsh, OwningCommand};
+pub use command::{CwdStrategy, EnvStrategy, OverSsh, OwningCommand, PtyOptions, TranslateOpts};
 /// Convenience [`OwningCommand`] alias when working with a session reference.
 pub type Command<'s> = OwningCommand<&'s Session>;
 
-mod escape;
+pub mod escape;
+
+mod trace;
 
 mod child;
 pub use child::Child;
@@ -181,7 +186,105 @@ pub use child::Child;
 pub type RemoteChild<'a> = Child<&'a Session>;
 
 mod error;
-pub use error::Error;
+pub use error::{Error, Result};
+
+mod backend;
+pub use backend::{available_backends, Backend};
+
+mod connect_error;
+pub use connect_error::SshConnectError;
+
+mod transfer_error;
+pub use transfer_error::TransferError;
+
+mod master_log;
+pub use master_log::{LogLevel, MasterEvent, MasterEventKind, MasterEvents, MasterLogReader};
+
+mod info;
+pub use info::SessionInfo;
+
+mod latency;
+pub use latency::Latency;
+
+mod jump_host;
+pub use jump_host::JumpHost;
+
+mod config_writer;
+pub use config_writer::{ConfigWriter, HostConfig};
+
+mod reconnect;
+pub use reconnect::{ReconnectingSession, RetryPolicy};
+
+mod pool;
+pub use pool::SessionPool;
+
+mod executor;
+pub use executor::{ExecFuture, FileTransfer, RemoteExecutor};
+
+mod broadcast;
+pub use broadcast::{broadcast, Broadcast};
+
+pub mod fleet;
+
+mod budget;
+pub use budget::{ConcurrencyBudget, Priority};
+
+mod metrics_sink;
+#[cfg(feature = "metrics")]
+pub use metrics_sink::MetricsFacade;
+pub use metrics_sink::MetricsSink;
+
+mod shell;
+pub use shell::{InteractiveShell, ShellSignal};
+
+mod file_ops;
+pub use file_ops::FileOps;
+
+mod direct_streamlocal;
+pub use direct_streamlocal::DirectStreamlocal;
+
+mod direct_tcpip;
+pub use direct_tcpip::DirectTcpip;
+
+mod remote_lock;
+pub use remote_lock::RemoteLock;
+
+mod compat;
+pub use compat::{Compat, Userland};
+
+mod schedule;
+pub use schedule::{JobStatus, ScheduledJob, Scheduler, When};
+
+mod plan;
+pub use plan::{Plan, PlanError, StepFuture};
+
+mod scp;
+pub use scp::Scp;
+
+#[cfg(feature = "encoding")]
+mod encoding;
+#[cfg(feature = "encoding")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encoding")))]
+pub use encoding::DecodeErrors;
+#[cfg(feature = "encoding")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encoding")))]
+pub use encoding_rs;
+
+#[cfg(feature = "harness")]
+#[cfg_attr(docsrs, doc(cfg(feature = "harness")))]
+pub mod harness;
+
+mod sample;
+pub use sample::{Sample, SampledOutput};
+
+mod capture;
+pub use capture::{CaptureOptions, CapturedOutput, CapturedStream};
+
+mod output_stream;
+pub use output_stream::{OutputChunk, OutputStream};
+
+mod buffer_pool;
+pub use buffer_pool::BufferPool;
 
 #[cfg(feature = "process-mux")]
 pub(crate) mod process_impl;
@@ -196,7 +299,27 @@ pub mod changelog;
 mod port_forwarding;
 pub use port_forwarding::*;
 
+mod preflight;
+pub use preflight::preflight;
+
 /// Types to create and interact with the Remote Process
 pub mod process {
     pub use super::{ChildStderr, ChildStdin, ChildStdout, Command, RemoteChild, Stdio};
 }
+
+/// The most commonly used types and traits, to be glob imported:
+///
+/// ```rust
+/// use openssh::prelude::*;
+///
+/// async fn uptime(session: &Session) -> Result<String> {
+///     let output = session.command("uptime").output().await?;
+///     Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+/// }
+/// ```
+pub mod prelude {
+    pub use super::{
+        Child, Command, Error, KnownHosts, OverSsh, OwningCommand, RemoteChild, Result, Session,
+        SessionBuilder, Stdio,
+    };
+}
@@ -0,0 +1,456 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// How often the master log is checked for new lines once the end of it
+/// has been reached.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Remove the lines logged at the debug levels, so that errors can be
+/// recognized when [`SessionBuilder::master_verbosity`] is set.
+///
+/// [`SessionBuilder::master_verbosity`]: crate::SessionBuilder::master_verbosity
+pub(crate) fn strip_debug_lines(log: &str) -> String {
+    log.lines().filter(|line| !line.starts_with("debug")).fold(
+        String::new(),
+        |mut stripped, line| {
+            stripped.push_str(line);
+            stripped.push('\n');
+            stripped
+        },
+    )
+}
+
+/// Level of a line of the master log.
+///
+/// ssh only prefixes debug lines with their level, so errors are reported
+/// as [`LogLevel::Info`] too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// Regular message, logged without `-v`.
+    Info,
+    /// Logged with `-v` or more.
+    Debug1,
+    /// Logged with `-vv` or more.
+    Debug2,
+    /// Logged with `-vvv`.
+    Debug3,
+}
+
+/// What a line of the master log is about, as classified by
+/// [`MasterEvent::kind`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MasterEventKind {
+    /// An authentication method is being tried.
+    AuthAttempt {
+        /// Name of the method, e.g. `publickey`.
+        method: String,
+    },
+
+    /// Authentication succeeded.
+    Authenticated {
+        /// Name of the method that succeeded, e.g. `publickey`.
+        method: String,
+    },
+
+    /// A channel is opened, e.g. for a session or a forwarded connection.
+    ChannelOpen {
+        /// Id of the channel on the local side.
+        id: u32,
+        /// Type of the channel, e.g. `client-session`.
+        channel_type: String,
+    },
+
+    /// A port forward is confirmed.
+    ForwardConfirmed,
+
+    /// The host key of the server is received.
+    HostKey {
+        /// Type of the key, e.g. `ssh-ed25519`.
+        key_type: String,
+        /// Fingerprint of the key, e.g. `SHA256:...`.
+        fingerprint: String,
+    },
+
+    /// The cipher of one direction of the connection is negotiated.
+    CipherNegotiated {
+        /// Direction of the connection, either `client->server` or
+        /// `server->client`.
+        direction: String,
+        /// Name of the cipher, e.g. `chacha20-poly1305@openssh.com`.
+        cipher: String,
+        /// Name of the MAC, or `<implicit>` for AEAD ciphers.
+        mac: String,
+    },
+
+    /// A warning from ssh, e.g. about a changed host key.
+    Warning {
+        /// The warning, without the surrounding decoration.
+        message: String,
+    },
+
+    /// The connection to the server is terminated.
+    Disconnect {
+        /// The reason given by ssh.
+        reason: String,
+    },
+
+    /// Any other line.
+    Other,
+}
+
+/// A line of the master log, classified into a structured event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterEvent {
+    level: LogLevel,
+    kind: MasterEventKind,
+    message: String,
+}
+
+impl MasterEvent {
+    /// Classify a line of the master log.
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim_end();
+
+        let (level, message) = [
+            ("debug1: ", LogLevel::Debug1),
+            ("debug2: ", LogLevel::Debug2),
+            ("debug3: ", LogLevel::Debug3),
+        ]
+        .iter()
+        .find_map(|(prefix, level)| line.strip_prefix(prefix).map(|msg| (*level, msg)))
+        .unwrap_or((LogLevel::Info, line));
+
+        Self {
+            level,
+            kind: classify(message),
+            message: message.to_owned(),
+        }
+    }
+
+    /// Level of the line.
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    /// What the line is about.
+    pub fn kind(&self) -> &MasterEventKind {
+        &self.kind
+    }
+
+    /// The line, without the level prefix.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+fn classify(message: &str) -> MasterEventKind {
+    use MasterEventKind::*;
+
+    if let Some(method) = message.strip_prefix("Next authentication method: ") {
+        return AuthAttempt {
+            method: method.to_owned(),
+        };
+    }
+
+    if message.starts_with("Authenticated to ") {
+        if let Some(method) = message
+            .rsplit_once(" using \"")
+            .and_then(|(_, rest)| rest.split_once('"'))
+            .map(|(method, _)| method)
+        {
+            return Authenticated {
+                method: method.to_owned(),
+            };
+        }
+    }
+
+    // e.g. `channel 1: new session [client-session] (inactive timeout: 0)`
+    // or `channel 1: new [client-session]` for older versions.
+    if let Some(rest) = message.strip_prefix("channel ") {
+        if let Some((id, rest)) = rest.split_once(": new ") {
+            let channel_type = rest
+                .split_once('[')
+                .and_then(|(_, rest)| rest.split_once(']'))
+                .map(|(channel_type, _)| channel_type);
+
+            if let (Ok(id), Some(channel_type)) = (id.parse(), channel_type) {
+                return ChannelOpen {
+                    id,
+                    channel_type: channel_type.to_owned(),
+                };
+            }
+        }
+    }
+
+    if message.starts_with("remote forward success for: ")
+        || message.starts_with("Local connections to ")
+        || message.starts_with("Allocated port ")
+    {
+        return ForwardConfirmed;
+    }
+
+    if let Some((key_type, fingerprint)) = message
+        .strip_prefix("Server host key: ")
+        .and_then(|rest| rest.split_once(' '))
+    {
+        return HostKey {
+            key_type: key_type.to_owned(),
+            fingerprint: fingerprint.to_owned(),
+        };
+    }
+
+    // e.g. `kex: server->client cipher: aes128-ctr MAC: umac-64@openssh.com compression: none`
+    if let Some((direction, rest)) = message
+        .strip_prefix("kex: ")
+        .and_then(|rest| rest.split_once(" cipher: "))
+    {
+        let mut words = rest.split(' ');
+        if let (Some(cipher), Some("MAC:"), Some(mac)) = (words.next(), words.next(), words.next())
+        {
+            return CipherNegotiated {
+                direction: direction.to_owned(),
+                cipher: cipher.to_owned(),
+                mac: mac.to_owned(),
+            };
+        }
+    }
+
+    // Important warnings are framed with `@`, e.g.
+    // `@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @`.
+    let unframed = message.trim_matches(['@', ' ']);
+    if unframed.starts_with("WARNING: ") || unframed.starts_with("Warning: ") {
+        return Warning {
+            message: unframed.to_owned(),
+        };
+    }
+
+    if message.starts_with("Received disconnect from ")
+        || message.starts_with("Disconnected from ")
+        || message.starts_with("Timeout, server ")
+        || (message.starts_with("Connection to ") && message.contains(" closed"))
+        || message.starts_with("Connection closed by ")
+        || message.starts_with("Connection reset by ")
+    {
+        return Disconnect {
+            reason: message.to_owned(),
+        };
+    }
+
+    Other
+}
+
+/// [`Stream`] of the events of the master log, returned by
+/// [`Session::master_events`](crate::Session::master_events).
+///
+/// It follows the log as it grows, like `tail -f`, and ends once the
+/// control socket is removed, i.e. when the master exits.
+#[derive(Debug)]
+pub struct MasterEvents {
+    reader: BufReader<File>,
+    ctl: Box<Path>,
+    line: Vec<u8>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl MasterEvents {
+    pub(crate) fn new(log: &Path, ctl: &Path) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(log)?),
+            ctl: ctl.into(),
+            line: Vec::new(),
+            sleep: None,
+        })
+    }
+}
+
+impl Stream for MasterEvents {
+    type Item = io::Result<MasterEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(sleep) = &mut this.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+            }
+
+            // The log is a regular file, so reading never blocks for long.
+            if let Err(err) = this.reader.read_until(b'\n', &mut this.line) {
+                return Poll::Ready(Some(Err(err)));
+            }
+
+            if this.line.last() == Some(&b'\n') {
+                let event = MasterEvent::parse(&String::from_utf8_lossy(&this.line));
+                this.line.clear();
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            // Reached the end of the log, possibly in the middle of a line
+            // which is kept until it is complete.
+            if !this.ctl.exists() {
+                return Poll::Ready(None);
+            }
+            this.sleep = Some(Box::pin(sleep(POLL_INTERVAL)));
+        }
+    }
+}
+
+/// [`AsyncRead`] of the raw master log, returned by
+/// [`Session::master_log_reader`](crate::Session::master_log_reader).
+///
+/// Like [`MasterEvents`], it follows the log as it grows and reaches its
+/// end once the master exits.
+#[derive(Debug)]
+pub struct MasterLogReader {
+    file: File,
+    ctl: Box<Path>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl MasterLogReader {
+    pub(crate) fn new(log: &Path, ctl: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(log)?,
+            ctl: ctl.into(),
+            sleep: None,
+        })
+    }
+}
+
+impl AsyncRead for MasterLogReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(sleep) = &mut this.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+            }
+
+            let n = this.file.read(buf.initialize_unfilled())?;
+            buf.advance(n);
+            if n != 0 || buf.remaining() == 0 || !this.ctl.exists() {
+                return Poll::Ready(Ok(()));
+            }
+            this.sleep = Some(Box::pin(sleep(POLL_INTERVAL)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogLevel, MasterEvent, MasterEventKind::*};
+
+    #[test]
+    fn parse() {
+        let cases = [
+            (
+                "debug1: Next authentication method: publickey\n",
+                LogLevel::Debug1,
+                AuthAttempt {
+                    method: "publickey".into(),
+                },
+            ),
+            (
+                "Authenticated to 127.0.0.1 ([127.0.0.1]:2222) using \"publickey\".",
+                LogLevel::Info,
+                Authenticated {
+                    method: "publickey".into(),
+                },
+            ),
+            (
+                "debug1: channel 1: new session [client-session] (inactive timeout: 0)",
+                LogLevel::Debug1,
+                ChannelOpen {
+                    id: 1,
+                    channel_type: "client-session".into(),
+                },
+            ),
+            (
+                "debug2: channel 0: new [client-session]",
+                LogLevel::Debug2,
+                ChannelOpen {
+                    id: 0,
+                    channel_type: "client-session".into(),
+                },
+            ),
+            (
+                "debug1: remote forward success for: listen 8080, connect 127.0.0.1:80",
+                LogLevel::Debug1,
+                ForwardConfirmed,
+            ),
+            (
+                "Received disconnect from 127.0.0.1 port 2222:11: bye",
+                LogLevel::Info,
+                Disconnect {
+                    reason: "Received disconnect from 127.0.0.1 port 2222:11: bye".into(),
+                },
+            ),
+            (
+                "Timeout, server 127.0.0.1 not responding.",
+                LogLevel::Info,
+                Disconnect {
+                    reason: "Timeout, server 127.0.0.1 not responding.".into(),
+                },
+            ),
+            (
+                "debug1: Server host key: ssh-ed25519 SHA256:uOPp1Ad1lJ3v0lIfkq6cGDnmJ0pKSKsVvsMaIDt0Wdo",
+                LogLevel::Debug1,
+                HostKey {
+                    key_type: "ssh-ed25519".into(),
+                    fingerprint: "SHA256:uOPp1Ad1lJ3v0lIfkq6cGDnmJ0pKSKsVvsMaIDt0Wdo".into(),
+                },
+            ),
+            (
+                "debug1: kex: server->client cipher: chacha20-poly1305@openssh.com MAC: <implicit> compression: none",
+                LogLevel::Debug1,
+                CipherNegotiated {
+                    direction: "server->client".into(),
+                    cipher: "chacha20-poly1305@openssh.com".into(),
+                    mac: "<implicit>".into(),
+                },
+            ),
+            (
+                "@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @",
+                LogLevel::Info,
+                Warning {
+                    message: "WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!".into(),
+                },
+            ),
+            (
+                "Warning: Permanently added '[127.0.0.1]:2222' (ED25519) to the list of known hosts.",
+                LogLevel::Info,
+                Warning {
+                    message: "Warning: Permanently added '[127.0.0.1]:2222' (ED25519) to the list of known hosts.".into(),
+                },
+            ),
+            ("debug3: send packet: type 5", LogLevel::Debug3, Other),
+        ];
+
+        for (line, level, kind) in cases {
+            let event = MasterEvent::parse(line);
+            assert_eq!(event.level(), level, "{line}");
+            assert_eq!(event.kind(), &kind, "{line}");
+            assert!(!event.message().starts_with("debug"));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receives the metrics of the sessions it is installed on with
+/// [`SessionBuilder::metrics_sink`](crate::SessionBuilder::metrics_sink) or
+/// [`Session::set_metrics_sink`](crate::Session::set_metrics_sink).
+///
+/// Installing a sink per session, e.g. labelled with its destination,
+/// gives per-host metrics without wrapping every call site. All the
+/// methods do nothing by default, and are called from the tasks performing
+/// the operations, so they should return quickly.
+///
+/// Sftp requests are sent by `openssh-sftp-client`, so they are only
+/// accounted for as the bytes over the stdin and stdout of the subsystem.
+///
+/// With the `metrics` feature, [`MetricsFacade`] records them through the
+/// [`metrics`](https://docs.rs/metrics) facade.
+pub trait MetricsSink: Send + Sync {
+    /// A remote command or subsystem was spawned.
+    fn command_spawned(&self) {}
+
+    /// A remote command exited, `elapsed` after it was spawned.
+    fn command_exited(&self, elapsed: Duration) {
+        let _ = elapsed;
+    }
+
+    /// `n` bytes were written to the stdin of a remote command.
+    fn stdin_bytes(&self, n: u64) {
+        let _ = n;
+    }
+
+    /// `n` bytes were read from the stdout of a remote command.
+    fn stdout_bytes(&self, n: u64) {
+        let _ = n;
+    }
+
+    /// A [`ReconnectingSession`](crate::ReconnectingSession) replaced its
+    /// session after the connection was lost.
+    fn reconnected(&self) {}
+}
+
+/// The sink of a session.
+#[derive(Clone)]
+pub(crate) struct Metrics(pub(crate) Arc<dyn MetricsSink>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+/// Reports the bytes going through a stdio stream of a remote command.
+pub(crate) struct Meter {
+    metrics: Metrics,
+    record: fn(&dyn MetricsSink, u64),
+}
+
+impl Meter {
+    pub(crate) fn stdin(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            record: |sink, n| sink.stdin_bytes(n),
+        }
+    }
+
+    pub(crate) fn stdout(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            record: |sink, n| sink.stdout_bytes(n),
+        }
+    }
+
+    pub(crate) fn record(&self, n: usize) {
+        if n > 0 {
+            (self.record)(&*self.metrics.0, n as u64);
+        }
+    }
+}
+
+impl fmt::Debug for Meter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Meter").finish_non_exhaustive()
+    }
+}
+
+/// A [`MetricsSink`] recording through the [`metrics`] facade, into the
+/// recorder installed by the application:
+///
+///  - `openssh_commands_spawned_total`: counter of the commands spawned.
+///  - `openssh_command_duration_seconds`: histogram of the time from
+///    spawning the commands to their exit.
+///  - `openssh_stdin_bytes_total` and `openssh_stdout_bytes_total`:
+///    counters of the bytes over the stdin and stdout of the commands.
+///  - `openssh_reconnects_total`: counter of the reconnections.
+///
+/// ```
+/// use openssh::{MetricsFacade, SessionBuilder};
+/// use std::sync::Arc;
+///
+/// let host = "example.com";
+/// let mut builder = SessionBuilder::default();
+/// builder.metrics_sink(Arc::new(MetricsFacade::new().label("host", host)));
+/// ```
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+#[derive(Debug, Clone, Default)]
+pub struct MetricsFacade {
+    labels: Vec<metrics::Label>,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsFacade {
+    /// Create a sink recording the metrics without labels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Label all the metrics with `key` set to `value`, e.g. `host` set to
+    /// the destination of the sessions.
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels
+            .push(metrics::Label::new(key.into(), value.into()));
+        self
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsSink for MetricsFacade {
+    fn command_spawned(&self) {
+        metrics::counter!("openssh_commands_spawned_total", self.labels.iter()).increment(1);
+    }
+
+    fn command_exited(&self, elapsed: Duration) {
+        metrics::histogram!("openssh_command_duration_seconds", self.labels.iter()).record(elapsed);
+    }
+
+    fn stdin_bytes(&self, n: u64) {
+        metrics::counter!("openssh_stdin_bytes_total", self.labels.iter()).increment(n);
+    }
+
+    fn stdout_bytes(&self, n: u64) {
+        metrics::counter!("openssh_stdout_bytes_total", self.labels.iter()).increment(n);
+    }
+
+    fn reconnected(&self) {
+        metrics::counter!("openssh_reconnects_total", self.labels.iter()).increment(1);
+    }
+}
@@ -1,20 +1,96 @@
 use super::Error;
 
+use std::fmt;
+use std::future::Future;
 use std::io;
+use std::mem;
 use std::os::unix::process::ExitStatusExt;
+use std::pin::Pin;
 use std::process::ExitStatus;
+use std::ptr;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 
 use openssh_mux_client::{EstablishedSession, SessionStatus};
 
+type WaitFuture = Pin<Box<dyn Future<Output = Result<ExitStatus, Error>> + Send>>;
+
+enum State {
+    Running(EstablishedSession),
+    /// `wait` has been polled by `try_wait`, but not completed.
+    Waiting(WaitFuture),
+    Exited(ExitStatus),
+    Failed,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Running(established_session) => {
+                f.debug_tuple("Running").field(established_session).finish()
+            }
+            State::Waiting(_) => f.write_str("Waiting"),
+            State::Exited(status) => f.debug_tuple("Exited").field(status).finish(),
+            State::Failed => f.write_str("Failed"),
+        }
+    }
+}
+
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| (), |_| (), |_| ());
+    const RAW: RawWaker = RawWaker::new(ptr::null(), &VTABLE);
+
+    // Safety: the vtable does nothing with the data pointer.
+    unsafe { Waker::from_raw(RAW) }
+}
+
+fn already_failed() -> Error {
+    Error::Remote(io::Error::new(
+        io::ErrorKind::Other,
+        "the remote child has already failed",
+    ))
+}
+
+async fn wait_session(mut established_session: EstablishedSession) -> Result<ExitStatus, Error> {
+    let session_status = loop {
+        match established_session
+            .wait()
+            .await
+            .map_err(|(err, _established_session)| err)?
+        {
+            // The remote process still runs, just without a tty.
+            SessionStatus::TtyAllocFail(session) => established_session = session,
+            session_status => break session_status,
+        }
+    };
+
+    match session_status {
+        SessionStatus::TtyAllocFail(_) => unreachable!("handled above"),
+        SessionStatus::Exited { exit_value } => {
+            if let Some(val) = exit_value {
+                if val == 127 {
+                    Err(Error::Remote(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "remote command not found",
+                    )))
+                } else {
+                    Ok(ExitStatusExt::from_raw((val as i32) << 8))
+                }
+            } else {
+                Err(Error::RemoteProcessTerminated)
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct RemoteChild {
-    established_session: EstablishedSession,
+    state: State,
 }
 
 impl RemoteChild {
     pub(crate) fn new(established_session: EstablishedSession) -> Self {
         Self {
-            established_session,
+            state: State::Running(established_session),
         }
     }
 
@@ -29,31 +105,45 @@ impl RemoteChild {
         Ok(())
     }
 
-    pub(crate) async fn wait(self) -> Result<ExitStatus, Error> {
-        let session_status = self
-            .established_session
-            .wait()
-            .await
-            .map_err(|(err, _established_session)| err)?;
+    pub(crate) fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        let future = match &mut self.state {
+            State::Running(_) => {
+                let established_session = match mem::replace(&mut self.state, State::Failed) {
+                    State::Running(established_session) => established_session,
+                    _ => unreachable!(),
+                };
+                self.state = State::Waiting(Box::pin(wait_session(established_session)));
 
-        match session_status {
-            SessionStatus::TtyAllocFail(_established_session) => {
-                unreachable!("native_mux_impl never allocates a tty")
-            }
-            SessionStatus::Exited { exit_value } => {
-                if let Some(val) = exit_value {
-                    if val == 127 {
-                        Err(Error::Remote(io::Error::new(
-                            io::ErrorKind::NotFound,
-                            "remote command not found",
-                        )))
-                    } else {
-                        Ok(ExitStatusExt::from_raw((val as i32) << 8))
-                    }
-                } else {
-                    Err(Error::RemoteProcessTerminated)
+                match &mut self.state {
+                    State::Waiting(future) => future,
+                    _ => unreachable!(),
                 }
             }
+            State::Waiting(future) => future,
+            State::Exited(status) => return Ok(Some(*status)),
+            State::Failed => return Err(already_failed()),
+        };
+
+        let waker = noop_waker();
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Pending => Ok(None),
+            Poll::Ready(Ok(status)) => {
+                self.state = State::Exited(status);
+                Ok(Some(status))
+            }
+            Poll::Ready(Err(err)) => {
+                self.state = State::Failed;
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) async fn wait(self) -> Result<ExitStatus, Error> {
+        match self.state {
+            State::Running(established_session) => wait_session(established_session).await,
+            State::Waiting(future) => future.await,
+            State::Exited(status) => Ok(status),
+            State::Failed => Err(already_failed()),
         }
     }
 }
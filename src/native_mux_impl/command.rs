@@ -4,7 +4,9 @@ use super::{ChildStderr, ChildStdin, ChildStdout, Stdio};
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
 use openssh_mux_client::{Connection, NonZeroByteSlice, Session};
@@ -14,6 +16,13 @@ pub(crate) struct Command {
     cmd: Vec<u8>,
     ctl: Box<Path>,
     subsystem: bool,
+    setsid: bool,
+    /// Environment variables, which the mux client does not support sending.
+    has_env: bool,
+    /// Whether to request a pty, with the value of `TERM` to send.
+    pty: Option<Option<Vec<u8>>>,
+    forward_agent: bool,
+    forward_x11: bool,
 
     stdin_v: Stdio,
     stdout_v: Stdio,
@@ -26,6 +35,11 @@ impl Command {
             cmd,
             ctl,
             subsystem,
+            setsid: false,
+            has_env: false,
+            pty: None,
+            forward_agent: false,
+            forward_x11: false,
 
             stdin_v: Stdio::inherit(),
             stdout_v: Stdio::inherit(),
@@ -38,6 +52,26 @@ impl Command {
         self.cmd.extend_from_slice(arg.as_ref().as_bytes());
     }
 
+    pub(crate) fn setsid(&mut self, setsid: bool) {
+        self.setsid = setsid;
+    }
+
+    pub(crate) fn env(&mut self, _key: &OsStr, _val: &OsStr) {
+        self.has_env = true;
+    }
+
+    pub(crate) fn request_pty(&mut self, term: Option<&str>) {
+        self.pty = Some(term.map(|term| term.as_bytes().to_vec()));
+    }
+
+    pub(crate) fn forward_agent(&mut self, forward_agent: bool) {
+        self.forward_agent = forward_agent;
+    }
+
+    pub(crate) fn forward_x11(&mut self, forward_x11: bool) {
+        self.forward_x11 = forward_x11;
+    }
+
     pub(crate) fn stdin<T: Into<Stdio>>(&mut self, cfg: T) {
         self.stdin_v = cfg.into();
     }
@@ -50,6 +84,27 @@ impl Command {
         self.stderr_v = cfg.into();
     }
 
+    /// Length of the remote command, as sent to the server.
+    pub(crate) fn remote_command_len(&self) -> usize {
+        self.cmd.len()
+    }
+
+    /// The remote command, as sent to the server.
+    pub(crate) fn remote_command(&self) -> Vec<u8> {
+        self.cmd.clone()
+    }
+
+    /// Replace the remote command with `cmd` and return the original one.
+    ///
+    /// Return `None` for subsystems, whose name cannot be replaced.
+    pub(crate) fn replace_command(&mut self, cmd: &[u8]) -> Option<Vec<u8>> {
+        if self.subsystem {
+            return None;
+        }
+
+        Some(mem::replace(&mut self.cmd, cmd.to_vec()))
+    }
+
     pub(crate) async fn spawn(
         &mut self,
     ) -> Result<
@@ -61,26 +116,38 @@ impl Command {
         ),
         Error,
     > {
+        if self.has_env {
+            return Err(Error::CommandHasEnv);
+        }
+
         let (stdin, child_stdin) = self.stdin_v.to_stdin()?;
         let (stdout, child_stdout) = self.stdout_v.to_stdout()?;
         let (stderr, child_stderr) = self.stderr_v.to_stderr()?;
 
-        let stdios = [
-            stdin.as_raw_fd_or_null_fd()?,
-            stdout.as_raw_fd_or_null_fd()?,
-            stderr.as_raw_fd_or_null_fd()?,
-        ];
-
-        let cmd = NonZeroByteSlice::new(&self.cmd).ok_or(Error::InvalidCommand)?;
+        let stdios = [stdin.as_raw_fd(), stdout.as_raw_fd(), stderr.as_raw_fd()];
 
-        #[cfg(feature = "tracing")]
-        tracing::debug!(cmd = String::from_utf8_lossy(cmd.into_inner()).as_ref());
+        let cmd = if self.setsid && !self.subsystem {
+            let mut cmd = b"setsid -w ".to_vec();
+            cmd.extend_from_slice(&self.cmd);
+            Cow::Owned(cmd)
+        } else {
+            Cow::Borrowed(&*self.cmd)
+        };
+        let cmd = NonZeroByteSlice::new(&cmd).ok_or(Error::InvalidCommand)?;
 
-        let session = Session::builder()
+        let mut session = Session::builder()
             .cmd(Cow::Borrowed(cmd))
             .subsystem(self.subsystem)
+            .tty(self.pty.is_some())
+            .agent(self.forward_agent)
+            .x11_forwarding(self.forward_x11)
             .build();
 
+        if let Some(Some(term)) = &self.pty {
+            let term = NonZeroByteSlice::new(term).ok_or(Error::InvalidCommand)?;
+            session.term = Cow::Borrowed(term);
+        }
+
         let established_session = Connection::connect(&self.ctl)
             .await?
             .open_new_session(&session, &stdios)
@@ -1,6 +1,7 @@
 use super::{Command, Error};
 
 use std::ffi::OsStr;
+use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
@@ -12,35 +13,60 @@ pub(crate) struct Session {
     /// TempDir will automatically removes the temporary dir on drop
     tempdir: Option<TempDir>,
     ctl: Box<Path>,
+    master_log: Option<Box<Path>>,
 }
 
 impl Session {
     pub(crate) fn new(dir: TempDir) -> Self {
+        let log = dir.path().join("log").into_boxed_path();
         let ctl = dir.path().join("master").into_boxed_path();
 
         Self {
             tempdir: Some(dir),
             ctl,
+            master_log: Some(log),
         }
     }
 
-    pub(crate) fn resume(ctl: Box<Path>, _master_log: Option<Box<Path>>) -> Self {
-        Self { tempdir: None, ctl }
+    pub(crate) fn resume(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
+        Self {
+            tempdir: None,
+            ctl,
+            master_log,
+        }
     }
 
     pub(crate) async fn check(&self) -> Result<(), Error> {
-        Connection::connect(&self.ctl)
+        self.master_pid().await.map(|_| ())
+    }
+
+    pub(crate) async fn master_pid(&self) -> Result<u32, Error> {
+        let pid = Connection::connect(&self.ctl)
             .await?
             .send_alive_check()
             .await?;
 
-        Ok(())
+        Ok(pid.get())
     }
 
     pub(crate) fn ctl(&self) -> &Path {
         &self.ctl
     }
 
+    pub(crate) fn state_dir(&self) -> Option<&Path> {
+        self.tempdir.as_ref().map(TempDir::path)
+    }
+
+    pub(crate) fn master_log(&self) -> Option<&Path> {
+        self.master_log.as_deref()
+    }
+
+    /// Return a handle to the same master that does not own it, for
+    /// checking it from a background task.
+    pub(crate) fn watcher(&self) -> Self {
+        Self::resume(self.ctl.clone(), self.master_log.clone())
+    }
+
     pub(crate) fn raw_command<S: AsRef<OsStr>>(&self, program: S) -> Command {
         Command::new(self.ctl.clone(), program.as_ref().as_bytes().into(), false)
     }
@@ -85,7 +111,7 @@ impl Session {
         Ok(())
     }
 
-    async fn close_impl(&self) -> Result<(), Error> {
+    pub(crate) async fn stop_accepting(&self) -> Result<(), Error> {
         Connection::connect(&self.ctl)
             .await?
             .request_stop_listening()
@@ -98,19 +124,29 @@ impl Session {
         // Take self.tempdir so that drop would do nothing
         let tempdir = self.tempdir.take();
 
-        self.close_impl().await?;
+        self.stop_accepting().await?;
+
+        Ok(tempdir)
+    }
+
+    pub(crate) async fn terminate(mut self) -> Result<Option<TempDir>, Error> {
+        // The terminate request of the multiplex protocol is not supported
+        // by openssh_mux_client, so stop the master the way ssh does on
+        // SIGTERM instead.
+        let pid = self.master_pid().await?;
+        let tempdir = self.tempdir.take();
+
+        // Safety: kill is safe to call with any arguments.
+        if unsafe { libc::kill(pid as _, libc::SIGTERM) } == -1 {
+            return Err(Error::Master(io::Error::last_os_error()));
+        }
 
         Ok(tempdir)
     }
 
     pub(crate) fn detach(mut self) -> (Box<Path>, Option<Box<Path>>) {
-        (
-            self.ctl.clone(),
-            self.tempdir.take().map(TempDir::into_path).map(|mut path| {
-                path.push("log");
-                path.into_boxed_path()
-            }),
-        )
+        self.tempdir.take().map(TempDir::into_path);
+        (self.ctl.clone(), self.master_log.take())
     }
 }
 
@@ -3,7 +3,7 @@ use crate::{stdio::StdioImpl, Error, Stdio};
 use std::{
     fs::{File, OpenOptions},
     io,
-    os::unix::io::{AsRawFd, OwnedFd, RawFd},
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
 };
 
 use libc::{c_int, fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
@@ -15,7 +15,7 @@ fn create_pipe() -> Result<(PipeReader, PipeWriter), Error> {
 }
 
 /// Open "/dev/null" with RW.
-fn get_null_fd() -> Result<RawFd, Error> {
+fn get_null_fd() -> Result<BorrowedFd<'static>, Error> {
     static NULL_FD: OnceCell<File> = OnceCell::new();
     let res = NULL_FD.get_or_try_init(|| {
         OpenOptions::new()
@@ -25,13 +25,32 @@ fn get_null_fd() -> Result<RawFd, Error> {
             .map_err(Error::ChildIo)
     });
 
-    res.map(AsRawFd::as_raw_fd)
+    res.map(AsFd::as_fd)
 }
 
-pub(crate) enum Fd {
+/// Borrow the stdin of this process.
+fn stdin_fd() -> BorrowedFd<'static> {
+    static STDIN: OnceCell<io::Stdin> = OnceCell::new();
+    STDIN.get_or_init(io::stdin).as_fd()
+}
+
+/// Borrow the stdout of this process.
+fn stdout_fd() -> BorrowedFd<'static> {
+    static STDOUT: OnceCell<io::Stdout> = OnceCell::new();
+    STDOUT.get_or_init(io::stdout).as_fd()
+}
+
+/// Borrow the stderr of this process.
+fn stderr_fd() -> BorrowedFd<'static> {
+    static STDERR: OnceCell<io::Stderr> = OnceCell::new();
+    STDERR.get_or_init(io::stderr).as_fd()
+}
+
+/// Fd passed to the remote child, which is either a pipe created for it
+/// and closed once it is passed, or borrowed from the [`Stdio`].
+pub(crate) enum Fd<'a> {
     Owned(OwnedFd),
-    Borrowed(RawFd),
-    Null,
+    Borrowed(BorrowedFd<'a>),
 }
 
 fn cvt(ret: c_int) -> io::Result<c_int> {
@@ -49,23 +68,20 @@ fn set_blocking_inner(fd: RawFd) -> io::Result<()> {
     Ok(())
 }
 
-fn set_blocking(fd: RawFd) -> Result<(), Error> {
-    set_blocking_inner(fd).map_err(Error::ChildIo)
+fn set_blocking(fd: BorrowedFd<'_>) -> Result<(), Error> {
+    set_blocking_inner(fd.as_raw_fd()).map_err(Error::ChildIo)
 }
 
-impl Fd {
-    pub(crate) fn as_raw_fd_or_null_fd(&self) -> Result<RawFd, Error> {
-        use Fd::*;
-
+impl AsRawFd for Fd<'_> {
+    fn as_raw_fd(&self) -> RawFd {
         match self {
-            Owned(owned_fd) => Ok(owned_fd.as_raw_fd()),
-            Borrowed(rawfd) => Ok(*rawfd),
-            Null => get_null_fd(),
+            Fd::Owned(owned_fd) => owned_fd.as_raw_fd(),
+            Fd::Borrowed(borrowed_fd) => borrowed_fd.as_raw_fd(),
         }
     }
 }
 
-impl TryFrom<PipeReader> for Fd {
+impl TryFrom<PipeReader> for Fd<'_> {
     type Error = Error;
 
     fn try_from(pipe_reader: PipeReader) -> Result<Self, Error> {
@@ -76,7 +92,7 @@ impl TryFrom<PipeReader> for Fd {
     }
 }
 
-impl TryFrom<PipeWriter> for Fd {
+impl TryFrom<PipeWriter> for Fd<'_> {
     type Error = Error;
 
     fn try_from(pipe_writer: PipeWriter) -> Result<Self, Error> {
@@ -88,47 +104,45 @@ impl TryFrom<PipeWriter> for Fd {
 }
 
 impl Stdio {
-    pub(crate) fn to_stdin(&self) -> Result<(Fd, Option<ChildStdin>), Error> {
+    pub(crate) fn to_stdin(&self) -> Result<(Fd<'_>, Option<ChildStdin>), Error> {
         match &self.0 {
-            StdioImpl::Inherit => Ok((Fd::Borrowed(io::stdin().as_raw_fd()), None)),
-            StdioImpl::Null => Ok((Fd::Null, None)),
+            StdioImpl::Inherit => Ok((Fd::Borrowed(stdin_fd()), None)),
+            StdioImpl::Null => Ok((Fd::Borrowed(get_null_fd()?), None)),
             StdioImpl::Pipe => {
                 let (read, write) = create_pipe()?;
                 Ok((read.try_into()?, Some(write)))
             }
             StdioImpl::Fd(fd) => {
-                let raw_fd = fd.as_raw_fd();
-                set_blocking(raw_fd)?;
-                Ok((Fd::Borrowed(raw_fd), None))
+                set_blocking(fd.as_fd())?;
+                Ok((Fd::Borrowed(fd.as_fd()), None))
             }
         }
     }
 
     fn to_output(
         &self,
-        get_inherit_rawfd: fn() -> RawFd,
-    ) -> Result<(Fd, Option<PipeReader>), Error> {
+        inherit_fd: fn() -> BorrowedFd<'static>,
+    ) -> Result<(Fd<'_>, Option<PipeReader>), Error> {
         match &self.0 {
-            StdioImpl::Inherit => Ok((Fd::Borrowed(get_inherit_rawfd()), None)),
-            StdioImpl::Null => Ok((Fd::Null, None)),
+            StdioImpl::Inherit => Ok((Fd::Borrowed(inherit_fd()), None)),
+            StdioImpl::Null => Ok((Fd::Borrowed(get_null_fd()?), None)),
             StdioImpl::Pipe => {
                 let (read, write) = create_pipe()?;
                 Ok((write.try_into()?, Some(read)))
             }
             StdioImpl::Fd(fd) => {
-                let raw_fd = fd.as_raw_fd();
-                set_blocking(raw_fd)?;
-                Ok((Fd::Borrowed(raw_fd), None))
+                set_blocking(fd.as_fd())?;
+                Ok((Fd::Borrowed(fd.as_fd()), None))
             }
         }
     }
 
-    pub(crate) fn to_stdout(&self) -> Result<(Fd, Option<PipeReader>), Error> {
-        self.to_output(|| io::stdout().as_raw_fd())
+    pub(crate) fn to_stdout(&self) -> Result<(Fd<'_>, Option<PipeReader>), Error> {
+        self.to_output(stdout_fd)
     }
 
-    pub(crate) fn to_stderr(&self) -> Result<(Fd, Option<PipeReader>), Error> {
-        self.to_output(|| io::stderr().as_raw_fd())
+    pub(crate) fn to_stderr(&self) -> Result<(Fd<'_>, Option<PipeReader>), Error> {
+        self.to_output(stderr_fd)
     }
 }
 
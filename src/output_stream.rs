@@ -0,0 +1,219 @@
+use super::{Child, ChildStderr, ChildStdout, Error};
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Data written by a remote process, tagged with the stream it was
+/// written to, as yielded by [`OutputStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChunk {
+    /// Data written to stdout.
+    Stdout(Vec<u8>),
+    /// Data written to stderr.
+    Stderr(Vec<u8>),
+}
+
+impl OutputChunk {
+    /// The data, regardless of the stream it was written to.
+    pub fn data(&self) -> &[u8] {
+        match self {
+            OutputChunk::Stdout(data) | OutputChunk::Stderr(data) => data,
+        }
+    }
+}
+
+/// One of the pipes read by an [`OutputStream`].
+#[derive(Debug)]
+struct Pipe<R> {
+    /// `None` once EOF is reached.
+    reader: Option<R>,
+    /// The incomplete last line, when line buffered.
+    line: Vec<u8>,
+    tag: fn(Vec<u8>) -> OutputChunk,
+}
+
+impl<R: AsyncRead + Unpin> Pipe<R> {
+    fn new(reader: Option<R>, tag: fn(Vec<u8>) -> OutputChunk) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            tag,
+        }
+    }
+
+    /// Read once from the pipe and queue the chunks read into `pending`.
+    ///
+    /// Returns `Poll::Pending` if nothing can be read, including once EOF
+    /// is reached.
+    fn poll_chunks(
+        &mut self,
+        cx: &mut Context<'_>,
+        line_buffered: bool,
+        pending: &mut VecDeque<OutputChunk>,
+    ) -> Poll<io::Result<()>> {
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => return Poll::Pending,
+        };
+
+        let mut buf = [0; 8192];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        match Pin::new(reader).poll_read(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(err)) => {
+                self.reader = None;
+                return Poll::Ready(Err(err));
+            }
+            Poll::Pending => return Poll::Pending,
+        }
+        let data = read_buf.filled();
+
+        if data.is_empty() {
+            self.reader = None;
+            if !self.line.is_empty() {
+                pending.push_back((self.tag)(std::mem::take(&mut self.line)));
+            }
+        } else if line_buffered {
+            self.line.extend_from_slice(data);
+            while let Some(end) = self.line.iter().position(|&b| b == b'\n') {
+                let rest = self.line.split_off(end + 1);
+                pending.push_back((self.tag)(std::mem::replace(&mut self.line, rest)));
+            }
+        } else {
+            pending.push_back((self.tag)(data.to_vec()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// [`Stream`] of the stdout and stderr of a remote process, returned by
+/// [`OwningCommand::stream`](crate::OwningCommand::stream).
+///
+/// Chunks are yielded in the order they are read from either pipe, which
+/// may differ from the order the remote process wrote them in, since both
+/// are forwarded over separate channels. The stream ends once both pipes
+/// reach EOF, after which [`wait`](Self::wait) returns the exit status.
+///
+/// ```no_run
+/// # async fn f(session: &openssh::Session) -> Result<(), openssh::Error> {
+/// use futures_core::Stream;
+/// use openssh::OutputChunk;
+/// use std::future::Future;
+/// use std::pin::Pin;
+/// use std::task::{Context, Poll};
+///
+/// // Same as `StreamExt::next` of `futures-util`.
+/// struct Next<'a, S>(&'a mut S);
+///
+/// impl<S: Stream + Unpin> Future for Next<'_, S> {
+///     type Output = Option<S::Item>;
+///
+///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+///         Pin::new(&mut *self.0).poll_next(cx)
+///     }
+/// }
+///
+/// let mut stream = session
+///     .command("make")
+///     .stream()
+///     .await?
+///     .line_buffered(true);
+///
+/// while let Some(chunk) = Next(&mut stream).await {
+///     match chunk? {
+///         OutputChunk::Stdout(line) => print!("{}", String::from_utf8_lossy(&line)),
+///         OutputChunk::Stderr(line) => eprint!("{}", String::from_utf8_lossy(&line)),
+///     }
+/// }
+/// let status = stream.wait().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct OutputStream<S> {
+    child: Child<S>,
+    stdout: Pipe<ChildStdout>,
+    stderr: Pipe<ChildStderr>,
+    line_buffered: bool,
+    /// Alternates to read both pipes fairly.
+    stderr_first: bool,
+    pending: VecDeque<OutputChunk>,
+}
+
+impl<S> OutputStream<S> {
+    pub(crate) fn new(mut child: Child<S>) -> Self {
+        let stdout = child.stdout().take();
+        let stderr = child.stderr().take();
+
+        Self {
+            child,
+            stdout: Pipe::new(stdout, OutputChunk::Stdout),
+            stderr: Pipe::new(stderr, OutputChunk::Stderr),
+            line_buffered: false,
+            stderr_first: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Yield one chunk per line, including its `\n`, instead of the data as
+    /// it is read. The last line may lack a `\n`.
+    ///
+    /// Defaults to `false`.
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        self.line_buffered = line_buffered;
+        self
+    }
+
+    /// Wait for the remote process to exit, returning its exit status.
+    ///
+    /// Any output not yet read from the stream is discarded.
+    pub async fn wait(self) -> Result<ExitStatus, Error> {
+        self.child.wait().await
+    }
+}
+
+impl<S: Unpin> Stream for OutputStream<S> {
+    type Item = Result<OutputChunk, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(chunk) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            if this.stdout.reader.is_none() && this.stderr.reader.is_none() {
+                return Poll::Ready(None);
+            }
+
+            this.stderr_first = !this.stderr_first;
+            let line_buffered = this.line_buffered;
+            let pending = &mut this.pending;
+
+            let mut polled = if this.stderr_first {
+                this.stderr.poll_chunks(cx, line_buffered, pending)
+            } else {
+                this.stdout.poll_chunks(cx, line_buffered, pending)
+            };
+            if polled.is_pending() {
+                polled = if this.stderr_first {
+                    this.stdout.poll_chunks(cx, line_buffered, pending)
+                } else {
+                    this.stderr.poll_chunks(cx, line_buffered, pending)
+                };
+            }
+
+            match polled {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(Error::ChildIo(err)))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
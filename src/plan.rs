@@ -0,0 +1,297 @@
+use super::file_ops::remote_error;
+use super::{Error, Session, Stdio};
+
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+/// Future returned by the custom steps and rollbacks of a [`Plan`].
+pub type StepFuture<'s> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 's>>;
+
+type CustomAction = Box<dyn for<'s> Fn(&'s Session) -> StepFuture<'s> + Send + Sync>;
+
+enum Action {
+    Run(Vec<String>),
+    Upload { local: PathBuf, remote: String },
+    Rename { from: String, to: String },
+    Custom(CustomAction),
+}
+
+/// Run `argv` on the remote host, failing if it exits with a non-zero
+/// status.
+async fn run(session: &Session, argv: &[String]) -> Result<(), Error> {
+    let mut cmd = session.command(&argv[0]);
+    cmd.args(&argv[1..]);
+    let output = cmd.stderr(Stdio::piped()).output().await?;
+
+    if !output.status.success() {
+        return Err(remote_error(&output.stderr));
+    }
+    Ok(())
+}
+
+impl Action {
+    async fn execute(&self, session: &Session) -> Result<(), Error> {
+        match self {
+            Action::Run(argv) => run(session, argv).await,
+            Action::Upload { local, remote } => session.scp().send(local, remote).await,
+            Action::Rename { from, to } => {
+                let argv = ["mv", "-f", "--", from, to].map(String::from);
+                run(session, &argv).await
+            }
+            Action::Custom(action) => action(session).await,
+        }
+    }
+}
+
+struct Step {
+    name: String,
+    action: Action,
+    rollback: Option<Action>,
+}
+
+/// A sequence of steps changing a remote host, each with an optional
+/// rollback, run by [`Session::execute_plan`].
+///
+/// The steps run in order and stop at the first failure, after which the
+/// rollbacks of the steps that completed run in reverse order, so that
+/// deployments either fully apply or are undone as far as possible.
+///
+/// Custom steps can run anything with the session, e.g. sftp operations
+/// with [`openssh-sftp-client`].
+///
+/// ```no_run
+/// # async fn f(session: &openssh::Session) -> Result<(), openssh::PlanError> {
+/// use openssh::Plan;
+///
+/// let mut plan = Plan::new();
+/// plan.upload("target/release/app", "/srv/app.new")
+///     .rollback_run("rm", ["-f", "/srv/app.new"])
+///     .run("cp", ["/srv/app", "/srv/app.old"])
+///     .rename("/srv/app.new", "/srv/app")
+///     .rollback_run("mv", ["-f", "/srv/app.old", "/srv/app"])
+///     .step("restart app", |session| {
+///         Box::pin(async move {
+///             session
+///                 .command("systemctl")
+///                 .args(["restart", "app"])
+///                 .status()
+///                 .await
+///                 .map(|_| ())
+///         })
+///     });
+///
+/// session.execute_plan(&plan).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`openssh-sftp-client`]: https://crates.io/crates/openssh-sftp-client
+#[derive(Default)]
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+impl fmt::Debug for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.steps.iter().map(|step| &step.name))
+            .finish()
+    }
+}
+
+impl Plan {
+    /// Create an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, name: String, action: Action) -> &mut Self {
+        self.steps.push(Step {
+            name,
+            action,
+            rollback: None,
+        });
+        self
+    }
+
+    fn argv<I, A>(program: &str, args: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<str>,
+    {
+        let mut argv = vec![program.to_owned()];
+        argv.extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        argv
+    }
+
+    /// Add a step running `program` with `args`, escaped as with
+    /// [`Session::command`], which fails if the program exits with a
+    /// non-zero status.
+    pub fn run<I, A>(&mut self, program: &str, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<str>,
+    {
+        self.push(
+            format!("run {}", program),
+            Action::Run(Self::argv(program, args)),
+        )
+    }
+
+    /// Add a step copying the local file `local` to `remote` with
+    /// [`Scp::send`](crate::Scp::send).
+    pub fn upload(&mut self, local: impl AsRef<Path>, remote: impl Into<String>) -> &mut Self {
+        let local = local.as_ref().to_path_buf();
+        let remote = remote.into();
+        self.push(
+            format!("upload {} to {}", local.display(), remote),
+            Action::Upload { local, remote },
+        )
+    }
+
+    /// Add a step renaming the remote file `from` to `to` with `mv -f`.
+    pub fn rename(&mut self, from: impl Into<String>, to: impl Into<String>) -> &mut Self {
+        let from = from.into();
+        let to = to.into();
+        self.push(
+            format!("rename {} to {}", from, to),
+            Action::Rename { from, to },
+        )
+    }
+
+    /// Add a step named `name` running `action`.
+    pub fn step<F>(&mut self, name: impl Into<String>, action: F) -> &mut Self
+    where
+        F: for<'s> Fn(&'s Session) -> StepFuture<'s> + Send + Sync + 'static,
+    {
+        self.push(name.into(), Action::Custom(Box::new(action)))
+    }
+
+    fn set_rollback(&mut self, rollback: Action) -> &mut Self {
+        let step = self
+            .steps
+            .last_mut()
+            .expect("a rollback must follow the step it undoes");
+        step.rollback = Some(rollback);
+        self
+    }
+
+    /// Undo the last step added by running `program` with `args`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step is added yet.
+    pub fn rollback_run<I, A>(&mut self, program: &str, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<str>,
+    {
+        self.set_rollback(Action::Run(Self::argv(program, args)))
+    }
+
+    /// Undo the last step added by running `rollback`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step is added yet.
+    pub fn rollback<F>(&mut self, rollback: F) -> &mut Self
+    where
+        F: for<'s> Fn(&'s Session) -> StepFuture<'s> + Send + Sync + 'static,
+    {
+        self.set_rollback(Action::Custom(Box::new(rollback)))
+    }
+
+    /// Number of steps.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Whether the plan has no step.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub(crate) async fn execute(&self, session: &Session) -> Result<(), PlanError> {
+        for (index, step) in self.steps.iter().enumerate() {
+            let error = match step.action.execute(session).await {
+                Ok(()) => continue,
+                Err(error) => error,
+            };
+
+            let mut rollback_errors = Vec::new();
+            for done in self.steps[..index].iter().rev() {
+                if let Some(rollback) = &done.rollback {
+                    if let Err(err) = rollback.execute(session).await {
+                        rollback_errors.push((done.name.clone(), err));
+                    }
+                }
+            }
+
+            return Err(PlanError {
+                step: step.name.clone(),
+                index,
+                error,
+                rollback_errors,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Failure of a step of a [`Plan`], as returned by
+/// [`Session::execute_plan`] once the rollbacks have run.
+#[derive(Debug)]
+pub struct PlanError {
+    step: String,
+    index: usize,
+    error: Error,
+    rollback_errors: Vec<(String, Error)>,
+}
+
+impl PlanError {
+    /// Name of the step that failed, e.g. `run systemctl`.
+    pub fn step(&self) -> &str {
+        &self.step
+    }
+
+    /// Index of the step that failed in the plan.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The error of the step.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// The errors of the rollbacks that failed, with the names of their
+    /// steps, in the order the rollbacks ran.
+    ///
+    /// If this is empty, all the completed steps were rolled back.
+    pub fn rollback_errors(&self) -> &[(String, Error)] {
+        &self.rollback_errors
+    }
+
+    /// Return the error of the step.
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} ({}) failed", self.index, self.step)?;
+        if !self.rollback_errors.is_empty() {
+            write!(f, ", and {} rollbacks failed", self.rollback_errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PlanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -0,0 +1,253 @@
+use super::{Error, OwningCommand, Session, SessionBuilder};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// A session of a [`SessionPool`], along with when it was last handed out.
+#[derive(Debug)]
+struct Pooled {
+    session: Arc<Session>,
+    last_used: Instant,
+}
+
+impl Pooled {
+    /// Return the number of commands, or other handles, using the session.
+    fn users(&self) -> usize {
+        Arc::strong_count(&self.session) - 1
+    }
+}
+
+/// The sessions to a host.
+type Host = Arc<Mutex<Vec<Pooled>>>;
+
+/// Manages the sessions to a set of hosts, connected with the settings of
+/// a [`SessionBuilder`] when a command is first run on them.
+///
+/// Up to [`max_sessions_per_host`](Self::max_sessions_per_host) sessions
+/// are opened to each host, a new one being connected once all the others
+/// run [`max_commands_per_session`](Self::max_commands_per_session)
+/// commands, e.g. to work around the `MaxSessions` limit of sshd, which
+/// defaults to 10 channels per connection.
+///
+/// A session is checked with [`Session::check`] before being reused once
+/// no command runs on it, and is replaced if the connection was lost. It
+/// is closed once it has not been used for
+/// [`max_idle`](Self::max_idle).
+///
+/// Sessions are connected with
+/// [`SessionBuilder::connect_default`], i.e. with the `process-mux` backend
+/// if it is enabled, and with `native-mux` otherwise.
+///
+/// ```no_run
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), openssh::Error> {
+/// use openssh::{SessionBuilder, SessionPool};
+/// use std::time::Duration;
+///
+/// let mut pool = SessionPool::new(SessionBuilder::default());
+/// pool.max_sessions_per_host(2)
+///     .max_idle(Some(Duration::from_secs(300)));
+///
+/// for host in ["web1.example.com", "web2.example.com"] {
+///     let status = pool.command(host, "true").await?.status().await?;
+///     assert!(status.success());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SessionPool {
+    builder: SessionBuilder,
+    max_sessions_per_host: usize,
+    max_commands_per_session: usize,
+    max_idle: Option<Duration>,
+    hosts: std::sync::Mutex<HashMap<String, Host>>,
+}
+
+impl SessionPool {
+    /// Create a pool connecting to the hosts with `builder`, with at most
+    /// one session per host by default.
+    pub fn new(builder: SessionBuilder) -> Self {
+        Self {
+            builder,
+            max_sessions_per_host: 1,
+            max_commands_per_session: 10,
+            max_idle: None,
+            hosts: Default::default(),
+        }
+    }
+
+    /// Set the maximum number of sessions opened to each host.
+    ///
+    /// Once reached, commands are spread over the existing sessions
+    /// instead. Defaults to 1.
+    pub fn max_sessions_per_host(&mut self, max: usize) -> &mut Self {
+        self.max_sessions_per_host = max.max(1);
+        self
+    }
+
+    /// Set the number of commands running on a session beyond which
+    /// another session is opened to the host, up to
+    /// [`max_sessions_per_host`](Self::max_sessions_per_host).
+    ///
+    /// Defaults to 10, the default `MaxSessions` of sshd.
+    pub fn max_commands_per_session(&mut self, max: usize) -> &mut Self {
+        self.max_commands_per_session = max.max(1);
+        self
+    }
+
+    /// Close the sessions which have not been handed out for `max_idle`
+    /// and on which no command runs anymore, or never if `None`, the
+    /// default.
+    ///
+    /// Idle sessions of a host are closed when it is used again, or by
+    /// [`evict_idle`](Self::evict_idle).
+    pub fn max_idle(&mut self, max_idle: Option<Duration>) -> &mut Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Return a new command running `program` on `host`, connecting to it
+    /// if needed, as with [`Session::command`].
+    pub async fn command<'a, P: Into<Cow<'a, str>>>(
+        &self,
+        host: &str,
+        program: P,
+    ) -> Result<OwningCommand<Arc<Session>>, Error> {
+        Ok(Session::to_command(self.session(host).await?, program))
+    }
+
+    /// Return the least used session to `host`, connecting to it if all
+    /// the sessions are busy, or none is open yet.
+    ///
+    /// The session counts as used as long as the returned handle is alive.
+    pub async fn session(&self, host: &str) -> Result<Arc<Session>, Error> {
+        let host_sessions = self.host(host);
+        let mut sessions = host_sessions.lock().await;
+        self.evict(&mut sessions).await;
+
+        loop {
+            let index = sessions
+                .iter()
+                .enumerate()
+                .filter(|(_, pooled)| pooled.users() < self.max_commands_per_session)
+                .min_by_key(|(_, pooled)| pooled.users())
+                .map(|(index, _)| index);
+            let index = match index {
+                Some(index) => index,
+                None => break,
+            };
+
+            // Sessions in use are known to be alive.
+            if sessions[index].users() == 0 && sessions[index].session.check().await.is_err() {
+                sessions.swap_remove(index);
+                continue;
+            }
+
+            let pooled = &mut sessions[index];
+            pooled.last_used = Instant::now();
+            return Ok(pooled.session.clone());
+        }
+
+        if sessions.len() >= self.max_sessions_per_host {
+            let pooled = sessions
+                .iter_mut()
+                .min_by_key(|pooled| pooled.users())
+                .expect("at least one session is allowed per host");
+            pooled.last_used = Instant::now();
+            return Ok(pooled.session.clone());
+        }
+
+        let session = Arc::new(self.connect(host).await?);
+        sessions.push(Pooled {
+            session: session.clone(),
+            last_used: Instant::now(),
+        });
+        Ok(session)
+    }
+
+    /// Close the sessions of all the hosts which have been idle for
+    /// [`max_idle`](Self::max_idle).
+    pub async fn evict_idle(&self) {
+        let hosts: Vec<Host> = self
+            .hosts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect();
+
+        for host in hosts {
+            self.evict(&mut *host.lock().await).await;
+        }
+    }
+
+    /// Close all the sessions which are not in use, the others being
+    /// disconnected once the last handle to them is dropped.
+    ///
+    /// Returns the first error encountered while closing them.
+    pub async fn close(self) -> Result<(), Error> {
+        let hosts = self
+            .hosts
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut res = Ok(());
+        for (_, host) in hosts {
+            for pooled in host.lock().await.drain(..) {
+                if let Ok(session) = Arc::try_unwrap(pooled.session) {
+                    let closed = session.close().await;
+                    if res.is_ok() {
+                        res = closed;
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn host(&self, host: &str) -> Host {
+        self.hosts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(host.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    async fn evict(&self, sessions: &mut Vec<Pooled>) {
+        let max_idle = match self.max_idle {
+            Some(max_idle) => max_idle,
+            None => return,
+        };
+
+        let mut index = 0;
+        while index < sessions.len() {
+            let pooled = &sessions[index];
+            if pooled.users() > 0 || pooled.last_used.elapsed() < max_idle {
+                index += 1;
+                continue;
+            }
+
+            // The session is discarded either way, so failing to close it
+            // cleanly is ignored.
+            if let Ok(session) = Arc::try_unwrap(sessions.swap_remove(index).session) {
+                let _ = session.close().await;
+            }
+        }
+    }
+
+    #[cfg(any(feature = "process-mux", feature = "native-mux"))]
+    async fn connect(&self, host: &str) -> Result<Session, Error> {
+        self.builder.connect_default(host).await
+    }
+
+    #[cfg(not(any(feature = "process-mux", feature = "native-mux")))]
+    async fn connect(&self, _host: &str) -> Result<Session, Error> {
+        unreachable!("Neither feature process-mux nor native-mux is enabled")
+    }
+}
@@ -1,5 +1,6 @@
 #[cfg(feature = "native-mux")]
 use super::native_mux_impl;
+use super::{Error, Session};
 
 #[cfg(feature = "process-mux")]
 use std::ffi::OsStr;
@@ -116,6 +117,20 @@ impl Socket<'_> {
         }
     }
 
+    /// Copy the borrowed host or path, if any.
+    pub(crate) fn into_owned(self) -> Socket<'static> {
+        match self {
+            #[cfg(unix)]
+            Socket::UnixSocket { path } => Socket::UnixSocket {
+                path: Cow::Owned(path.into_owned()),
+            },
+            Socket::TcpSocket { host, port } => Socket::TcpSocket {
+                host: Cow::Owned(host.into_owned()),
+                port,
+            },
+        }
+    }
+
     #[cfg(feature = "process-mux")]
     pub(crate) fn as_os_str(&self) -> Cow<'_, OsStr> {
         match self {
@@ -153,3 +168,109 @@ impl<'a> fmt::Display for Socket<'a> {
         }
     }
 }
+
+/// A port forwarding opened by [`Session::request_port_forward`], which is
+/// closed once dropped.
+///
+/// Since closing it is asynchronous, dropping the guard spawns a task on
+/// the current tokio runtime to close it, ignoring any error, and does
+/// nothing outside of a runtime. Use [`close`](Self::close) to wait for it
+/// and be alerted to errors.
+#[derive(Debug)]
+#[must_use = "the port forwarding is closed as soon as the guard is dropped"]
+pub struct ForwardGuard {
+    /// `None` once closed or leaked.
+    session: Option<Box<Session>>,
+    forward_type: ForwardType,
+    listen_socket: Socket<'static>,
+    connect_socket: Socket<'static>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl ForwardGuard {
+    pub(crate) fn new(
+        session: Session,
+        forward_type: ForwardType,
+        listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+        local_addr: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            session: Some(Box::new(session)),
+            forward_type,
+            listen_socket,
+            connect_socket,
+            local_addr,
+        }
+    }
+
+    /// The type of the forwarding.
+    pub fn forward_type(&self) -> ForwardType {
+        self.forward_type
+    }
+
+    /// The socket listened on, with the port picked by the OS if port 0
+    /// was requested for a local forwarding.
+    pub fn listen_socket(&self) -> &Socket<'static> {
+        &self.listen_socket
+    }
+
+    /// The socket connected to.
+    pub fn connect_socket(&self) -> &Socket<'static> {
+        &self.connect_socket
+    }
+
+    /// The local address listened on, for local forwardings on a tcp socket
+    /// whose host is an ip address or whose port is 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Close the forwarding, as with [`Session::close_port_forward`].
+    pub async fn close(mut self) -> Result<(), Error> {
+        match self.session.take() {
+            Some(session) => {
+                session
+                    .close_port_forward(
+                        self.forward_type,
+                        self.listen_socket.clone(),
+                        self.connect_socket.clone(),
+                    )
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the forwarding open until it is closed with
+    /// [`Session::close_port_forward`] or the session is closed.
+    pub fn leak(mut self) {
+        self.session = None;
+    }
+}
+
+impl Drop for ForwardGuard {
+    fn drop(&mut self) {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return,
+        };
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+
+        let forward_type = self.forward_type;
+        let listen_socket = self.listen_socket.clone();
+        let connect_socket = self.connect_socket.clone();
+        runtime.spawn(async move {
+            let _res = session
+                .close_port_forward(forward_type, listen_socket, connect_socket)
+                .await;
+            #[cfg(feature = "tracing")]
+            if let Err(err) = _res {
+                tracing::error!("Closing port forwarding failed: {}", err);
+            }
+        });
+    }
+}
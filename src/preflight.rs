@@ -0,0 +1,145 @@
+use super::{Error, SessionBuilder};
+
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+
+/// Maximum length of the identification string, including the trailing CR LF,
+/// as specified in [RFC 4253](https://www.rfc-editor.org/rfc/rfc4253#section-4.2).
+const MAX_BANNER_LEN: u64 = 255;
+
+/// Maximum number of bytes read while looking for the identification string,
+/// since the server may send other lines of data before it.
+const MAX_PREAMBLE_LEN: u64 = 8 * 1024;
+
+/// Extract host and port from `destination`, accepting the same
+/// `ssh://[user@]host[:port]` and `[user@]host` forms as
+/// [`SessionBuilder::connect`].
+fn parse_destination(destination: &str) -> (&str, u16) {
+    let builder = SessionBuilder::default();
+    let (builder, mut host) = builder.resolve(destination);
+
+    if let Some(at) = host.rfind('@') {
+        host = &host[(at + 1)..];
+    }
+    if host.starts_with('[') && host.ends_with(']') {
+        host = &host[1..(host.len() - 1)];
+    }
+
+    let port = builder
+        .get_port()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(22);
+
+    (host, port)
+}
+
+/// Read lines from `reader` until the ssh identification string is found
+/// and return it, without the trailing line terminator.
+async fn read_banner<R: AsyncRead + Unpin>(reader: R) -> io::Result<String> {
+    let mut reader = BufReader::new(reader.take(MAX_PREAMBLE_LEN));
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let n = (&mut reader)
+            .take(MAX_BANNER_LEN)
+            .read_until(b'\n', &mut line)
+            .await?;
+
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before the ssh banner was received",
+            ));
+        }
+
+        if line.starts_with(b"SSH-") {
+            if line.last() != Some(&b'\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "ssh banner is too long",
+                ));
+            }
+
+            let banner = line.strip_suffix(b"\n").unwrap_or(&line);
+            let banner = banner.strip_suffix(b"\r").unwrap_or(banner);
+
+            return String::from_utf8(banner.to_vec())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+    }
+}
+
+/// Check whether an ssh server is listening at `destination`, without
+/// authenticating or launching a master connection.
+///
+/// This opens a raw TCP connection to the host and reads the identification
+/// string the server sends, e.g. `SSH-2.0-OpenSSH_9.6`, which is returned.
+/// It is a fast way to filter out unreachable hosts before paying the cost of
+/// [`Session::connect`](crate::Session::connect).
+///
+/// `destination` accepts the `ssh://[user@]host[:port]` and `[user@]host`
+/// forms, with the port defaulting to 22. Note that, unlike
+/// [`Session::connect`](crate::Session::connect), the ssh configuration
+/// (e.g. `Host` aliases, `ProxyJump`) is not consulted.
+///
+/// If the whole check takes longer than `timeout`, [`Error::Connect`] is
+/// returned with an error of kind [`io::ErrorKind::TimedOut`].
+pub async fn preflight(destination: &str, timeout: Duration) -> Result<String, Error> {
+    let (host, port) = parse_destination(destination);
+
+    let check = async {
+        let stream = TcpStream::connect((host, port)).await?;
+        read_banner(stream).await
+    };
+
+    match time::timeout(timeout, check).await {
+        Ok(res) => res.map_err(Error::Connect),
+        Err(_) => Err(Error::Connect(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out waiting for the ssh banner",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_destination, read_banner};
+
+    #[test]
+    fn destination() {
+        assert_eq!(parse_destination("opensshtest"), ("opensshtest", 22));
+        assert_eq!(
+            parse_destination("test-user@opensshtest"),
+            ("opensshtest", 22)
+        );
+        assert_eq!(
+            parse_destination("ssh://test-user@127.0.0.1:2222"),
+            ("127.0.0.1", 2222)
+        );
+        assert_eq!(parse_destination("ssh://opensshtest"), ("opensshtest", 22));
+        assert_eq!(parse_destination("ssh://[::1]:2222"), ("::1", 2222));
+    }
+
+    #[tokio::test]
+    async fn banner() {
+        let banner = read_banner(&b"SSH-2.0-OpenSSH_9.6\r\n"[..]).await.unwrap();
+        assert_eq!(banner, "SSH-2.0-OpenSSH_9.6");
+
+        let banner = read_banner(&b"hello\r\nthere\nSSH-2.0-x\n"[..])
+            .await
+            .unwrap();
+        assert_eq!(banner, "SSH-2.0-x");
+
+        let err = read_banner(&b"hello\r\n"[..]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let long = format!("SSH-2.0-{}\r\n", "x".repeat(300));
+        let err = read_banner(long.as_bytes()).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
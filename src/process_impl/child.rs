@@ -5,6 +5,17 @@ use std::process::ExitStatus;
 
 use tokio::process;
 
+fn exit_status(w: ExitStatus) -> Result<ExitStatus, Error> {
+    match w.code() {
+        Some(255) => Err(Error::RemoteProcessTerminated),
+        Some(127) => Err(Error::Remote(io::Error::new(
+            io::ErrorKind::NotFound,
+            "remote command not found",
+        ))),
+        _ => Ok(w),
+    }
+}
+
 // Disconnects the ssh session at drop, but does not kill the remote process.
 #[derive(Debug)]
 pub(crate) struct RemoteChild {
@@ -24,17 +35,17 @@ impl RemoteChild {
         Ok(())
     }
 
+    pub(crate) fn try_wait(&mut self) -> Result<Option<ExitStatus>, Error> {
+        match self.channel.try_wait() {
+            Err(e) => Err(Error::Remote(e)),
+            Ok(w) => w.map(exit_status).transpose(),
+        }
+    }
+
     pub(crate) async fn wait(mut self) -> Result<ExitStatus, Error> {
         match self.channel.wait().await {
             Err(e) => Err(Error::Remote(e)),
-            Ok(w) => match w.code() {
-                Some(255) => Err(Error::RemoteProcessTerminated),
-                Some(127) => Err(Error::Remote(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "remote command not found",
-                ))),
-                _ => Ok(w),
-            },
+            Ok(w) => exit_status(w),
         }
     }
 }
@@ -1,42 +1,142 @@
 use super::Error;
 use super::RemoteChild;
 use super::{ChildStderr, ChildStdin, ChildStdout};
+use crate::{stdio::StdioImpl, Stdio};
 
-use std::ffi::OsStr;
-use std::process::Stdio;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::process;
 
-use tokio::process;
+fn to_process_stdio(stdio: &Stdio) -> Result<process::Stdio, Error> {
+    match &stdio.0 {
+        StdioImpl::Null => Ok(process::Stdio::null()),
+        StdioImpl::Pipe => Ok(process::Stdio::piped()),
+        StdioImpl::Inherit => Ok(process::Stdio::inherit()),
+        StdioImpl::Fd(fd) => fd.try_clone().map(Into::into).map_err(Error::ChildIo),
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Command {
-    builder: process::Command,
+    /// Arguments passed to `ssh` before the destination.
+    ssh_args: Vec<OsString>,
+    /// The remote command, passed to `ssh` after `--`.
+    cmd: Vec<OsString>,
+    subsystem: bool,
+    setsid: bool,
+    /// Argument of `-o SetEnv`, if any.
+    set_env: Option<Vec<u8>>,
+    /// Whether to request a pty, with the value of `TERM` to send.
+    pty: Option<Option<OsString>>,
+    /// Whether to pass `-A` or `-a`, if any.
+    forward_agent: Option<bool>,
+    /// Whether to pass `-X` or `-x`, if any.
+    forward_x11: Option<bool>,
+
+    stdin_v: Stdio,
+    stdout_v: Stdio,
+    stderr_v: Stdio,
 }
 
 impl Command {
-    pub(crate) fn new(mut builder: process::Command) -> Self {
-        // Disconnects the ssh session at `RemoteChild::drop`, but does
-        // not kill the remote process.
-        builder.kill_on_drop(true);
+    pub(crate) fn new(ssh_args: Vec<OsString>, program: &OsStr, subsystem: bool) -> Self {
+        Self {
+            ssh_args,
+            cmd: vec![program.to_os_string()],
+            subsystem,
+            setsid: false,
+            set_env: None,
+            pty: None,
+            forward_agent: None,
+            forward_x11: None,
 
-        Self { builder }
+            stdin_v: Stdio::inherit(),
+            stdout_v: Stdio::inherit(),
+            stderr_v: Stdio::inherit(),
+        }
     }
 }
 
 impl Command {
     pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
-        self.builder.arg(arg);
+        self.cmd.push(arg.as_ref().to_os_string());
+    }
+
+    pub(crate) fn setsid(&mut self, setsid: bool) {
+        self.setsid = setsid;
+    }
+
+    pub(crate) fn env(&mut self, key: &OsStr, val: &OsStr) {
+        // ssh only uses the first `SetEnv` it encounters, so all variables
+        // are passed together as quoted arguments of a single option.
+        let set_env = self.set_env.get_or_insert_with(|| b"SetEnv=".to_vec());
+        if set_env.len() > b"SetEnv=".len() {
+            set_env.push(b' ');
+        }
+
+        set_env.push(b'"');
+        for &byte in key.as_bytes().iter().chain(b"=").chain(val.as_bytes()) {
+            if byte == b'"' || byte == b'\\' {
+                set_env.push(b'\\');
+            }
+            set_env.push(byte);
+        }
+        set_env.push(b'"');
+    }
+
+    pub(crate) fn request_pty(&mut self, term: Option<&str>) {
+        self.pty = Some(term.map(OsString::from));
+    }
+
+    pub(crate) fn forward_agent(&mut self, forward_agent: bool) {
+        self.forward_agent = Some(forward_agent);
+    }
+
+    pub(crate) fn forward_x11(&mut self, forward_x11: bool) {
+        self.forward_x11 = Some(forward_x11);
     }
 
     pub(crate) fn stdin<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdin(cfg);
+        self.stdin_v = cfg.into();
     }
 
     pub(crate) fn stdout<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdout(cfg);
+        self.stdout_v = cfg.into();
     }
 
     pub(crate) fn stderr<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stderr(cfg);
+        self.stderr_v = cfg.into();
+    }
+
+    /// Length of the remote command, as `ssh` sends it to the server.
+    pub(crate) fn remote_command_len(&self) -> usize {
+        // ssh joins the remote command and its arguments with spaces.
+        self.cmd.iter().map(|arg| arg.len() + 1).sum::<usize>() - 1
+    }
+
+    /// The remote command, as `ssh` sends it to the server.
+    pub(crate) fn remote_command(&self) -> Vec<u8> {
+        let mut joined = Vec::with_capacity(self.remote_command_len());
+        for (i, arg) in self.cmd.iter().enumerate() {
+            if i != 0 {
+                joined.push(b' ');
+            }
+            joined.extend_from_slice(arg.as_bytes());
+        }
+        joined
+    }
+
+    /// Replace the remote command with `cmd` and return the original one.
+    ///
+    /// Return `None` for subsystems, whose name cannot be replaced.
+    pub(crate) fn replace_command(&mut self, cmd: &[u8]) -> Option<Vec<u8>> {
+        if self.subsystem {
+            return None;
+        }
+
+        let original = self.remote_command();
+        self.cmd = vec![OsStr::from_bytes(cmd).to_os_string()];
+        Some(original)
     }
 
     pub(crate) async fn spawn(
@@ -50,10 +150,50 @@ impl Command {
         ),
         Error,
     > {
-        #[cfg(feature = "tracing")]
-        tracing::debug!(cmd = ?self.builder.as_std());
+        let mut builder = tokio::process::Command::new("ssh");
+        builder.args(&self.ssh_args);
+
+        if let Some(set_env) = &self.set_env {
+            builder.arg("-o").arg(OsStr::from_bytes(set_env));
+        }
+
+        if let Some(term) = &self.pty {
+            // Force allocation even though stdin may not be a terminal.
+            builder.arg("-tt");
+            if let Some(term) = term {
+                // Sent by ssh along with the pty request.
+                builder.env("TERM", term);
+            }
+        }
+
+        if let Some(forward_agent) = self.forward_agent {
+            builder.arg(if forward_agent { "-A" } else { "-a" });
+        }
+
+        if let Some(forward_x11) = self.forward_x11 {
+            builder.arg(if forward_x11 { "-X" } else { "-x" });
+        }
+
+        builder
+            // ssh does not care about the addr as long as we have passed
+            // `-S &*self.ctl`, see `Session::new_std_cmd`.
+            .arg("none")
+            .arg("--");
+
+        if self.setsid && !self.subsystem {
+            builder.arg("setsid").arg("-w");
+        }
+
+        builder
+            .args(&self.cmd)
+            .stdin(to_process_stdio(&self.stdin_v)?)
+            .stdout(to_process_stdio(&self.stdout_v)?)
+            .stderr(to_process_stdio(&self.stderr_v)?)
+            // Disconnects the ssh session at `RemoteChild::drop`, but does
+            // not kill the remote process.
+            .kill_on_drop(true);
 
-        let mut channel = self.builder.spawn().map_err(Error::Ssh)?;
+        let mut channel = builder.spawn().map_err(Error::Ssh)?;
 
         let child_stdin = channel.stdin.take();
         let child_stdout = channel.stdout.take();
@@ -1,6 +1,7 @@
 use super::{Command, Error, ForwardType, Socket};
+use crate::master_log::strip_debug_lines;
 
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -37,14 +38,22 @@ impl Session {
         }
     }
 
+    /// Arguments to pass to `ssh` before the destination.
+    fn ssh_args(&self, args: &[impl AsRef<OsStr>]) -> Vec<OsString> {
+        let mut ssh_args: Vec<OsString> = vec![
+            "-S".into(),
+            self.ctl.as_os_str().into(),
+            "-o".into(),
+            "BatchMode=yes".into(),
+        ];
+        ssh_args.extend(args.iter().map(|arg| arg.as_ref().to_os_string()));
+        ssh_args
+    }
+
     fn new_std_cmd(&self, args: &[impl AsRef<OsStr>]) -> std::process::Command {
         let mut cmd = std::process::Command::new("ssh");
         cmd.stdin(Stdio::null())
-            .arg("-S")
-            .arg(&*self.ctl)
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .args(args)
+            .args(self.ssh_args(args))
             // ssh does not care about the addr as long as we have passed
             // `-S &*self.ctl`.
             // It is tested on OpenSSH 8.2p1, 8.9p1, 9.0p1
@@ -53,10 +62,14 @@ impl Session {
     }
 
     fn new_cmd(&self, args: &[impl AsRef<OsStr>]) -> process::Command {
-        self.new_std_cmd(args).into()
+        let mut cmd: process::Command = self.new_std_cmd(args).into();
+        // Do not leave a cancelled control operation running concurrently
+        // with the next ones, e.g. `-O exit`.
+        cmd.kill_on_drop(true);
+        cmd
     }
 
-    pub(crate) async fn check(&self) -> Result<(), Error> {
+    async fn check_output(&self) -> Result<std::process::Output, Error> {
         let check = self
             .new_cmd(&["-O", "check"])
             .output()
@@ -70,24 +83,57 @@ impl Session {
                 Err(Error::Disconnected)
             }
         } else {
-            Ok(())
+            Ok(check)
         }
     }
 
+    pub(crate) async fn check(&self) -> Result<(), Error> {
+        self.check_output().await.map(|_| ())
+    }
+
+    pub(crate) async fn master_pid(&self) -> Result<u32, Error> {
+        let check = self.check_output().await?;
+
+        // ssh prints `Master running (pid=1234)`.
+        String::from_utf8_lossy(&check.stderr)
+            .split_once("(pid=")
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .and_then(|(pid, _)| pid.parse().ok())
+            .ok_or_else(|| {
+                Error::Master(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "failed to parse the pid of the master",
+                ))
+            })
+    }
+
     pub(crate) fn ctl(&self) -> &Path {
         &self.ctl
     }
 
+    pub(crate) fn state_dir(&self) -> Option<&Path> {
+        self.tempdir.as_ref().map(TempDir::path)
+    }
+
+    pub(crate) fn master_log(&self) -> Option<&Path> {
+        self.master_log.as_deref()
+    }
+
+    /// Return a handle to the same master that does not own it, for
+    /// checking it from a background task.
+    pub(crate) fn watcher(&self) -> Self {
+        Self::resume(self.ctl.clone(), self.master_log.clone())
+    }
+
     pub(crate) fn raw_command<S: AsRef<OsStr>>(&self, program: S) -> Command {
         // XXX: Should we do a self.check() here first?
 
         // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
         // succeed in establishing a _new_ connection if the master connection has failed.
 
-        let mut cmd = self.new_cmd(&["-T", "-p", "9"]);
-        cmd.arg("--").arg(program);
+        let args = self.ssh_args(&["-T", "-p", "9"]);
 
-        Command::new(cmd)
+        Command::new(args, program.as_ref(), false)
     }
 
     pub(crate) fn subsystem<S: AsRef<OsStr>>(&self, program: S) -> Command {
@@ -96,10 +142,9 @@ impl Session {
         // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
         // succeed in establishing a _new_ connection if the master connection has failed.
 
-        let mut cmd = self.new_cmd(&["-T", "-p", "9", "-s"]);
-        cmd.arg("--").arg(program);
+        let args = self.ssh_args(&["-T", "-p", "9", "-s"]);
 
-        Command::new(cmd)
+        Command::new(args, program.as_ref(), true)
     }
 
     pub(crate) async fn request_port_forward(
@@ -176,9 +221,10 @@ impl Session {
         }
     }
 
-    async fn close_impl(&self) -> Result<(), Error> {
+    /// Send the control command `op`, i.e. `exit` or `stop`, to the master.
+    async fn control(&self, op: &str) -> Result<(), Error> {
         let exit = self
-            .new_cmd(&["-O", "exit"])
+            .new_cmd(&["-O", op])
             .output()
             .await
             .map_err(Error::Ssh)?;
@@ -216,11 +262,19 @@ impl Session {
         // Take self.tempdir so that drop would do nothing
         let tempdir = self.tempdir.take();
 
-        self.close_impl().await?;
+        self.control("exit").await?;
 
         Ok(tempdir)
     }
 
+    pub(crate) async fn stop_accepting(&self) -> Result<(), Error> {
+        self.control("stop").await
+    }
+
+    pub(crate) async fn terminate(self) -> Result<Option<TempDir>, Error> {
+        self.close().await
+    }
+
     pub(crate) fn detach(mut self) -> (Box<Path>, Option<Box<Path>>) {
         self.tempdir.take().map(TempDir::into_path);
         (self.ctl.clone(), self.master_log.take())
@@ -228,7 +282,7 @@ impl Session {
 
     fn discover_master_error(&self) -> Option<Error> {
         let err = match fs::read_to_string(self.master_log.as_ref()?) {
-            Ok(err) => err,
+            Ok(err) => strip_debug_lines(&err),
             Err(e) => return Some(Error::Master(e)),
         };
         let mut stderr = err.trim();
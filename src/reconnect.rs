@@ -0,0 +1,194 @@
+use super::{Error, Session};
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time;
+
+/// How a [`ReconnectingSession`] retries after the connection is lost.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create the default policy, which reconnects up to 3 times with
+    /// a backoff starting at 500ms and capped at 30s.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of reconnections for a single operation.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Set the delay before the first reconnection, which doubles with
+    /// each subsequent one up to [`max_backoff`](RetryPolicy::max_backoff).
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the maximum delay between reconnections.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(1 << attempt.min(16))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// The current session, along with how many times it has been replaced,
+/// so that concurrent operations failing on the same session reconnect
+/// only once.
+#[derive(Debug)]
+struct Current {
+    generation: u64,
+    session: Arc<Session>,
+}
+
+/// A [`Session`] that re-launches the ssh master with its original settings
+/// once the connection is lost, for long-running programs controlling
+/// flaky hosts.
+///
+/// Operations performed through [`ReconnectingSession::run`] are retried
+/// on the new session when they fail because the connection to the master
+/// is broken, e.g. with [`Error::Disconnected`]. Since the remote side may
+/// or may not have performed the operation, only idempotent operations
+/// should be run this way.
+///
+/// Sessions created by other means than [`SessionBuilder`], e.g. with
+/// [`Session::resume`], cannot be reconnected. Neither can sessions
+/// connected with [`SessionBuilder::identity_from_memory`], since the key
+/// is not kept around to connect again.
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+/// [`SessionBuilder::identity_from_memory`]: crate::SessionBuilder::identity_from_memory
+#[derive(Debug)]
+pub struct ReconnectingSession {
+    current: Mutex<Current>,
+    policy: RetryPolicy,
+}
+
+impl ReconnectingSession {
+    /// Wrap `session`, which is reconnected according to `policy`.
+    ///
+    /// Returns `session` back if it was not created by
+    /// [`SessionBuilder`](crate::SessionBuilder), and thus has no
+    /// [recipe](Session::export_recipe) to reconnect with, or if it was
+    /// connected with
+    /// [`identity_from_memory`](crate::SessionBuilder::identity_from_memory).
+    pub fn new(session: Session, policy: RetryPolicy) -> Result<Self, Session> {
+        match session.export_recipe() {
+            Some(recipe) if !recipe.in_memory_identity() => (),
+            _ => return Err(session),
+        }
+
+        Ok(Self {
+            current: Mutex::new(Current {
+                generation: 0,
+                session: Arc::new(session),
+            }),
+            policy,
+        })
+    }
+
+    /// Return the current session.
+    ///
+    /// It is not replaced while being used, but is disconnected once
+    /// the last reference to it is dropped.
+    pub async fn session(&self) -> Arc<Session> {
+        self.current.lock().await.session.clone()
+    }
+
+    /// Run `op` with the current session, reconnecting and retrying it
+    /// when it fails because the connection to the master is broken.
+    ///
+    /// Gives up with the last error once
+    /// [`max_attempts`](RetryPolicy::max_attempts) reconnections failed
+    /// to make `op` succeed.
+    pub async fn run<F, Fut, T>(&self, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut(Arc<Session>) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let (mut generation, mut session) = {
+            let current = self.current.lock().await;
+            (current.generation, current.session.clone())
+        };
+        let mut attempt = 0;
+
+        loop {
+            let err = match op(session).await {
+                Err(err) if err.is_master_failure() && attempt < self.policy.max_attempts => err,
+                res => break res,
+            };
+
+            #[cfg(feature = "tracing")]
+            tracing::warn!("Reconnecting after: {}", err);
+            #[cfg(not(feature = "tracing"))]
+            let _ = err;
+
+            time::sleep(self.policy.backoff(attempt)).await;
+            attempt += 1;
+
+            match self.reconnect(generation).await {
+                Ok(current) => (generation, session) = current,
+                Err(err) if attempt < self.policy.max_attempts => {
+                    // Try again with the same session, which fails again
+                    // and triggers another reconnection.
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("Reconnecting failed: {}", err);
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = err;
+
+                    session = self.session().await;
+                }
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    /// Replace the session of `generation` with a new one, unless it has
+    /// already been replaced.
+    async fn reconnect(&self, generation: u64) -> Result<(u64, Arc<Session>), Error> {
+        let mut current = self.current.lock().await;
+
+        if current.generation == generation {
+            let recipe = current
+                .session
+                .export_recipe()
+                .expect("only sessions with a recipe are accepted");
+
+            let session = recipe.reconnect(current.session.backend()).await?;
+
+            if let Some(sink) = session.metrics_sink() {
+                sink.reconnected();
+            }
+            current.generation += 1;
+            current.session = Arc::new(session);
+        }
+
+        Ok((current.generation, current.session.clone()))
+    }
+}
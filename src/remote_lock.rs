@@ -0,0 +1,84 @@
+use super::file_ops::remote_error;
+use super::{Child, Error, Session, Stdio};
+
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// Lock the file for the name `$1` in the first writable directory of
+/// `/var/lock`, `$XDG_RUNTIME_DIR` and `/tmp`, print its path once locked,
+/// then hold the lock until stdin is closed.
+const LOCK: &str = r#"for dir in /var/lock "$XDG_RUNTIME_DIR" /tmp; do
+    [ -n "$dir" ] && [ -d "$dir" ] && [ -w "$dir" ] && break
+done
+path=$dir/openssh-rs-$1.lock
+exec 9>> "$path" || exit 1
+flock 9 || exit 1
+echo "$path"
+exec cat > /dev/null"#;
+
+/// A named lock held on the remote host, as returned by
+/// [`Session::remote_lock`].
+///
+/// The lock is an `flock` on a file held by a remote helper process, which
+/// releases it once its channel is closed: when the lock is
+/// [released](Self::release) or dropped, or if the connection is lost.
+#[derive(Debug)]
+pub struct RemoteLock<'s> {
+    helper: Child<&'s Session>,
+    path: String,
+}
+
+impl<'s> RemoteLock<'s> {
+    pub(crate) async fn acquire(session: &'s Session, name: &str) -> Result<RemoteLock<'s>, Error> {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+        {
+            return Err(Error::LocalIo(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid lock name {:?}", name),
+            )));
+        }
+
+        let mut helper = session
+            .command("sh")
+            .arg("-c")
+            .arg(LOCK)
+            .arg("sh")
+            .arg(name)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .await?;
+
+        let stdout = helper.stdout().take().expect("stdout is piped");
+        let mut path = String::new();
+        BufReader::new(stdout)
+            .read_line(&mut path)
+            .await
+            .map_err(Error::ChildIo)?;
+
+        if !path.ends_with('\n') {
+            // The helper exited before locking.
+            let output = helper.wait_with_output().await?;
+            return Err(remote_error(&output.stderr));
+        }
+        path.pop();
+
+        Ok(Self { helper, path })
+    }
+
+    /// The path of the locked file on the remote host.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Release the lock, waiting for the helper process to exit.
+    pub async fn release(self) -> Result<(), Error> {
+        self.helper.wait().await?;
+        Ok(())
+    }
+}
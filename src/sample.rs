@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::io;
+use std::process::ExitStatus;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The first and last bytes of an output stream, as captured by
+/// [`OwningCommand::capture_head_tail`](crate::OwningCommand::capture_head_tail).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// The first bytes of the stream.
+    pub head: Vec<u8>,
+    /// The last bytes of the stream, following `head` after `dropped` bytes.
+    pub tail: Vec<u8>,
+    /// Number of bytes between `head` and `tail` that were not retained.
+    pub dropped: u64,
+}
+
+/// The output of a finished remote process, of which only the first and
+/// last bytes are retained.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampledOutput {
+    /// The status (exit code) of the process.
+    pub status: ExitStatus,
+    /// The sampled data that the process wrote to stdout.
+    pub stdout: Sample,
+    /// The sampled data that the process wrote to stderr.
+    pub stderr: Sample,
+}
+
+/// Read `reader` to the end, retaining only the first `head_len` and the
+/// last `tail_len` bytes.
+pub(crate) async fn read_sample<R: AsyncRead + Unpin>(
+    mut reader: R,
+    head_len: usize,
+    tail_len: usize,
+) -> io::Result<Sample> {
+    let mut head = Vec::new();
+    let mut tail = VecDeque::new();
+    let mut dropped = 0;
+    let mut buf = [0; 8192];
+
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        let mut chunk = &buf[..n];
+        let to_head = (head_len - head.len()).min(chunk.len());
+        head.extend_from_slice(&chunk[..to_head]);
+        chunk = &chunk[to_head..];
+
+        tail.extend(chunk);
+        if tail.len() > tail_len {
+            let excess = tail.len() - tail_len;
+            tail.drain(..excess);
+            dropped += excess as u64;
+        }
+    }
+
+    Ok(Sample {
+        head,
+        tail: tail.into(),
+        dropped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_sample;
+
+    #[tokio::test]
+    async fn sample() {
+        let data: Vec<u8> = (0..20000u32).map(|i| i as u8).collect();
+
+        let sample = read_sample(&data[..], 100, 50).await.unwrap();
+        assert_eq!(sample.head, &data[..100]);
+        assert_eq!(sample.tail, &data[(data.len() - 50)..]);
+        assert_eq!(sample.dropped, 20000 - 150);
+
+        let sample = read_sample(&data[..120], 100, 50).await.unwrap();
+        assert_eq!(sample.head, &data[..100]);
+        assert_eq!(sample.tail, &data[100..120]);
+        assert_eq!(sample.dropped, 0);
+
+        let sample = read_sample(&data[..], 0, 0).await.unwrap();
+        assert!(sample.head.is_empty() && sample.tail.is_empty());
+        assert_eq!(sample.dropped, 20000);
+    }
+}
@@ -0,0 +1,296 @@
+use super::file_ops::remote_error;
+use super::{Error, Session, Stdio};
+
+use std::io;
+use std::time::Duration;
+
+/// Queue `$1` with the arguments of the scheduler chosen from `$2` and
+/// `$3`, and print the scheduler, the id of the job and its spool
+/// directory.
+const SCHEDULE: &str = r#"set -f
+spool=$(mktemp -d "${TMPDIR:-/tmp}/openssh-job.XXXXXX") || exit 1
+job="( $1 ) > '$spool/output' 2>&1; echo \$? > '$spool/status.tmp'; mv '$spool/status.tmp' '$spool/status'"
+if command -v at > /dev/null 2>&1; then
+    case $2 in
+        now) spec=now ;;
+        after) spec="now + $(( ($3 + 59) / 60 )) minutes" ;;
+        *) spec=$3 ;;
+    esac
+    out=$(printf '%s\n' "$job" | at $spec 2>&1) || { echo "$out" >&2; rm -rf "$spool"; exit 1; }
+    id=${out##*job }
+    echo "at ${id%% *} $spool"
+elif command -v systemd-run > /dev/null 2>&1; then
+    scope=--user; [ "$(id -u)" -eq 0 ] && scope=--system
+    unit=openssh-job-${spool##*.}
+    case $2 in
+        now) set -- ;;
+        after) set -- "--on-active=$3" ;;
+        *) set -- "--on-calendar=$3" ;;
+    esac
+    systemd-run $scope --quiet --collect --unit="$unit" "$@" sh -c "$job" >&2 || { rm -rf "$spool"; exit 1; }
+    echo "systemd-run $unit $spool"
+else
+    echo "neither at nor systemd-run is available" >&2
+    rm -rf "$spool"
+    exit 127
+fi"#;
+
+/// Print the exit code of the job with spool directory `$1`, or whether it
+/// is running or pending.
+const STATUS: &str = r#"if [ ! -d "$1" ]; then echo "$1: No such file or directory" >&2; exit 1
+elif [ -f "$1/status" ]; then cat "$1/status"
+elif [ -f "$1/output" ]; then echo running
+else echo pending; fi"#;
+
+/// Remove the job `$2` from scheduler `$1` and its spool directory `$3`.
+const CANCEL: &str = r#"case $1 in
+    at) atrm "$2" 2> /dev/null ;;
+    *) scope=--user; [ "$(id -u)" -eq 0 ] && scope=--system
+       systemctl $scope stop "$2.timer" "$2.service" 2> /dev/null ;;
+esac
+rm -rf -- "$3""#;
+
+/// Run `sh -c script sh args...`, returning its trimmed stdout.
+async fn run(session: &Session, script: &str, args: &[&str]) -> Result<String, Error> {
+    let mut cmd = session.command("sh");
+    cmd.arg("-c").arg(script).arg("sh").args(args);
+    let output = cmd.stderr(Stdio::piped()).output().await?;
+
+    if !output.status.success() {
+        return Err(remote_error(&output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// When to run a job queued with [`Session::schedule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum When {
+    /// As soon as possible.
+    Now,
+
+    /// After the given delay, which is rounded up to minutes with `at`.
+    After(Duration),
+
+    /// At the given time, as a timespec of `at`, e.g. `10:00 tomorrow`, or
+    /// a calendar event of `systemd-run --on-calendar`, e.g.
+    /// `2024-01-01 10:00`, depending on the [`Scheduler`] of the remote
+    /// host.
+    At(String),
+}
+
+impl When {
+    fn to_args(&self) -> (&'static str, String) {
+        match self {
+            When::Now => ("now", String::new()),
+            When::After(delay) => {
+                let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+                ("after", secs.to_string())
+            }
+            When::At(time) => ("at", time.clone()),
+        }
+    }
+}
+
+/// Scheduler running a [`ScheduledJob`] on the remote host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Scheduler {
+    /// `at`, which is preferred if installed.
+    At,
+    /// A transient timer of `systemd-run`, for the user manager unless
+    /// the remote user is root.
+    SystemdRun,
+}
+
+impl Scheduler {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scheduler::At => "at",
+            Scheduler::SystemdRun => "systemd-run",
+        }
+    }
+}
+
+/// Status of a [`ScheduledJob`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum JobStatus {
+    /// The job has not started yet.
+    Pending,
+    /// The job is running.
+    Running,
+    /// The job exited with the given code.
+    Exited(i32),
+}
+
+/// A command queued on the remote host by [`Session::schedule`].
+///
+/// The job runs independently from the connection, so it does not borrow
+/// the [`Session`]: its methods take the session to use, which can be a
+/// new one after reconnecting, with the job recreated from its parts with
+/// [`ScheduledJob::resume`] if needed.
+///
+/// The output of the job, stdout and stderr merged, and its exit code are
+/// kept in a spool directory on the remote host until the job is
+/// [cancelled](ScheduledJob::cancel).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledJob {
+    scheduler: Scheduler,
+    id: String,
+    spool: String,
+}
+
+impl ScheduledJob {
+    pub(crate) async fn schedule(
+        session: &Session,
+        command: &str,
+        when: When,
+    ) -> Result<Self, Error> {
+        let (mode, time) = when.to_args();
+        let mut cmd = session.command("sh");
+        cmd.arg("-c")
+            .arg(SCHEDULE)
+            .arg("sh")
+            .args([command, mode, &time]);
+        let output = cmd.stderr(Stdio::piped()).output().await?;
+
+        match output.status.code() {
+            Some(0) => Self::parse(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+                Error::Remote(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected output of the scheduler",
+                ))
+            }),
+            Some(127) => Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Unsupported,
+                String::from_utf8_lossy(&output.stderr).trim(),
+            ))),
+            _ => Err(remote_error(&output.stderr)),
+        }
+    }
+
+    fn parse(output: &str) -> Option<Self> {
+        let mut parts = output.trim_end_matches('\n').splitn(3, ' ');
+        let scheduler = match parts.next()? {
+            "at" => Scheduler::At,
+            "systemd-run" => Scheduler::SystemdRun,
+            _ => return None,
+        };
+        let id = parts.next().filter(|id| !id.is_empty())?;
+        let spool = parts.next().filter(|spool| spool.starts_with('/'))?;
+
+        Some(Self::resume(scheduler, id.into(), spool.into()))
+    }
+
+    /// Recreate a job from its [`scheduler`](Self::scheduler),
+    /// [`id`](Self::id) and [`spool`](Self::spool), e.g. to check it from
+    /// another process.
+    pub fn resume(scheduler: Scheduler, id: String, spool: String) -> Self {
+        Self {
+            scheduler,
+            id,
+            spool,
+        }
+    }
+
+    /// The scheduler running the job.
+    pub fn scheduler(&self) -> Scheduler {
+        self.scheduler
+    }
+
+    /// The id of the job for its scheduler: the job number for `at`, or
+    /// the name of the systemd unit.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The spool directory of the job on the remote host.
+    pub fn spool(&self) -> &str {
+        &self.spool
+    }
+
+    /// Check whether the job has run, through `session`.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] once the job is cancelled.
+    pub async fn status(&self, session: &Session) -> Result<JobStatus, Error> {
+        match run(session, STATUS, &[&self.spool]).await?.as_str() {
+            "pending" => Ok(JobStatus::Pending),
+            "running" => Ok(JobStatus::Running),
+            code => code.parse().map(JobStatus::Exited).map_err(|_| {
+                Error::Remote(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected status of the job: {}", code),
+                ))
+            }),
+        }
+    }
+
+    /// Fetch the output of the job so far, through `session`.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if the job has not started.
+    pub async fn fetch_output(&self, session: &Session) -> Result<Vec<u8>, Error> {
+        let mut cmd = session.command("cat");
+        cmd.arg("--").arg(format!("{}/output", self.spool));
+        let output = cmd.stderr(Stdio::piped()).output().await?;
+
+        if !output.status.success() {
+            return Err(remote_error(&output.stderr));
+        }
+        Ok(output.stdout)
+    }
+
+    /// Cancel the job through `session` if it is pending, or stop it if it
+    /// is running and the scheduler is `systemd-run`, then remove its
+    /// spool directory along with its output.
+    pub async fn cancel(self, session: &Session) -> Result<(), Error> {
+        let args = [self.scheduler.as_str(), &self.id, &self.spool];
+        run(session, CANCEL, &args).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScheduledJob, Scheduler, When};
+
+    use std::time::Duration;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            ScheduledJob::parse("at 12 /tmp/openssh-job.AbC123\n"),
+            Some(ScheduledJob::resume(
+                Scheduler::At,
+                "12".into(),
+                "/tmp/openssh-job.AbC123".into()
+            ))
+        );
+        assert_eq!(
+            ScheduledJob::parse(
+                "systemd-run openssh-job-AbC123 /var/tmp/my dir/openssh-job.AbC123"
+            ),
+            Some(ScheduledJob::resume(
+                Scheduler::SystemdRun,
+                "openssh-job-AbC123".into(),
+                "/var/tmp/my dir/openssh-job.AbC123".into()
+            ))
+        );
+
+        for invalid in ["", "at", "at  /tmp/x", "at 1 relative", "cron 1 /tmp/x"] {
+            assert_eq!(ScheduledJob::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn when() {
+        assert_eq!(When::Now.to_args(), ("now", String::new()));
+        assert_eq!(
+            When::After(Duration::from_millis(1500)).to_args(),
+            ("after", "2".into())
+        );
+        assert_eq!(
+            When::At("10:00 tomorrow".into()).to_args(),
+            ("at", "10:00 tomorrow".into())
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use super::transfer_error::TransferFailure;
+use super::{ChildStdin, ChildStdout, Error, RemoteChild, Session, Stdio};
+
+use std::fmt;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+const BUF_SIZE: usize = 32 * 1024;
+
+type Progress<'s> = Box<dyn FnMut(&Path, u64, u64) + Send + 's>;
+
+fn remote_error(kind: io::ErrorKind, msg: impl Into<String>) -> Error {
+    Error::Remote(io::Error::new(kind, msg.into()))
+}
+
+fn protocol_error() -> Error {
+    remote_error(
+        io::ErrorKind::InvalidData,
+        "invalid message from remote scp",
+    )
+}
+
+/// Copy files to and from the remote host with the scp protocol, as
+/// returned by [`Session::scp`].
+///
+/// The transfers are carried out by running `scp` in sink (`-t`) or source
+/// (`-f`) mode on the remote host over the existing connection, so they
+/// work with both backends and on servers that disable the sftp subsystem,
+/// but require `scp` to be installed on the remote host.
+///
+/// The errors reported by the remote `scp`, e.g. a denied permission or an
+/// exceeded quota, are recognized by [`Error::transfer_error`].
+///
+/// Remote paths are passed as-is to the remote `scp`, without expanding
+/// globs or `~`, and relative paths are relative to the home directory of
+/// the remote user.
+pub struct Scp<'s> {
+    session: &'s Session,
+    progress: Option<Progress<'s>>,
+}
+
+impl fmt::Debug for Scp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scp")
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s> Scp<'s> {
+    pub(crate) fn new(session: &'s Session) -> Self {
+        Self {
+            session,
+            progress: None,
+        }
+    }
+
+    /// Call `progress` with the local path, the number of bytes transferred
+    /// so far and the size of the file, before a file is transferred and
+    /// whenever a chunk of it has been.
+    pub fn on_progress<F>(&mut self, progress: F) -> &mut Self
+    where
+        F: FnMut(&Path, u64, u64) + Send + 's,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Copy the local file `local` to `remote`, which is either the path of
+    /// the file to create or overwrite, or an existing directory to copy it
+    /// into.
+    ///
+    /// The permissions of the local file are applied to the remote file if
+    /// it is created.
+    pub async fn send(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let mut transfer = Transfer::start(self.session, &["-t"], remote.as_ref()).await?;
+        transfer.read_ack().await?;
+        transfer
+            .send_file(local.as_ref(), &mut self.progress)
+            .await?;
+        transfer.finish().await
+    }
+
+    /// Copy the local directory `local` recursively to `remote`.
+    ///
+    /// As with `scp -r`, if `remote` is an existing directory then `local`
+    /// is copied into it, otherwise it is created as a copy of `local`.
+    /// Symlinks are followed and files that are neither regular files nor
+    /// directories are skipped.
+    pub async fn send_dir(
+        &mut self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let local = local.as_ref();
+        let mut transfer = Transfer::start(self.session, &["-r", "-t"], remote.as_ref()).await?;
+        transfer.read_ack().await?;
+
+        transfer.send_dir_header(local).await?;
+        let mut stack = vec![fs::read_dir(local).await.map_err(Error::LocalIo)?];
+        while let Some(dir) = stack.last_mut() {
+            let entry = match dir.next_entry().await.map_err(Error::LocalIo)? {
+                Some(entry) => entry,
+                None => {
+                    stack.pop();
+                    transfer.send_message("E\n").await?;
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            let metadata = fs::metadata(&path).await.map_err(Error::LocalIo)?;
+            if metadata.is_dir() {
+                transfer.send_dir_header(&path).await?;
+                stack.push(fs::read_dir(&path).await.map_err(Error::LocalIo)?);
+            } else if metadata.is_file() {
+                transfer.send_file(&path, &mut self.progress).await?;
+            }
+        }
+
+        transfer.finish().await
+    }
+
+    /// Copy the remote file `remote` to `local`, which is either the path of
+    /// the file to create or overwrite, or an existing directory to copy it
+    /// into.
+    ///
+    /// The permissions of the remote file are applied to the local file if
+    /// it is created.
+    pub async fn recv(
+        &mut self,
+        remote: impl AsRef<str>,
+        local: impl AsRef<Path>,
+    ) -> Result<(), Error> {
+        let local = local.as_ref();
+        let mut transfer = Transfer::start(self.session, &["-f"], remote.as_ref()).await?;
+        transfer.write(b"\0").await?;
+
+        let header = transfer.read_line().await?;
+        let (mode, len, name) = match header.as_bytes().first() {
+            Some(b'C') => parse_file_header(&header[1..]).ok_or_else(protocol_error)?,
+            Some(1) | Some(2) => {
+                return Err(Error::Remote(TransferFailure::io_error(
+                    header[1..].to_owned(),
+                )));
+            }
+            _ => return Err(protocol_error()),
+        };
+
+        let is_dir = fs::metadata(local)
+            .await
+            .map_or(false, |metadata| metadata.is_dir());
+        let path = if is_dir {
+            local.join(name)
+        } else {
+            local.to_path_buf()
+        };
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&path)
+            .await
+            .map_err(Error::LocalIo)?;
+        transfer.write(b"\0").await?;
+
+        report(&mut self.progress, &path, 0, len);
+        let mut buf = vec![0; BUF_SIZE];
+        let mut received = 0;
+        while received < len {
+            let max = buf.len().min((len - received) as usize);
+            let n = transfer
+                .stdout
+                .read(&mut buf[..max])
+                .await
+                .map_err(Error::ChildIo)?;
+            if n == 0 {
+                return Err(transfer.unexpected_eof());
+            }
+            file.write_all(&buf[..n]).await.map_err(Error::LocalIo)?;
+            received += n as u64;
+            report(&mut self.progress, &path, received, len);
+        }
+        file.flush().await.map_err(Error::LocalIo)?;
+
+        transfer.read_ack().await?;
+        transfer.write(b"\0").await?;
+        transfer.finish().await
+    }
+}
+
+fn report(progress: &mut Option<Progress<'_>>, path: &Path, transferred: u64, total: u64) {
+    if let Some(progress) = progress {
+        progress(path, transferred, total);
+    }
+}
+
+/// Parse `<mode> <len> <name>`, the rest of a `C` message.
+fn parse_file_header(header: &str) -> Option<(u32, u64, &str)> {
+    let mut parts = header.splitn(3, ' ');
+    let mode = u32::from_str_radix(parts.next()?, 8).ok()?;
+    let len = parts.next()?.parse().ok()?;
+    let name = parts.next()?;
+
+    if name.is_empty() || name == ".." || name.contains('/') {
+        return None;
+    }
+    Some((mode & 0o7777, len, name))
+}
+
+fn file_name(path: &Path) -> Result<&str, Error> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.contains('\n'))
+        .ok_or_else(|| {
+            Error::LocalIo(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file name cannot be sent with scp",
+            ))
+        })
+}
+
+/// A running remote `scp`.
+struct Transfer<'s> {
+    child: RemoteChild<'s>,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl<'s> Transfer<'s> {
+    async fn start(
+        session: &'s Session,
+        flags: &[&str],
+        remote: &str,
+    ) -> Result<Transfer<'s>, Error> {
+        let mut cmd = session.command("scp");
+        cmd.args(flags)
+            .arg("--")
+            .arg(remote)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        let mut child = cmd.spawn().await?;
+
+        let stdin = child.stdin().take().unwrap();
+        let stdout = child.stdout().take().unwrap();
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    fn unexpected_eof(&self) -> Error {
+        remote_error(
+            io::ErrorKind::UnexpectedEof,
+            "remote scp exited unexpectedly",
+        )
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.stdin.write_all(data).await.map_err(Error::ChildIo)
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        self.stdout
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(Error::ChildIo)?;
+
+        if line.pop() != Some(b'\n') {
+            return Err(self.unexpected_eof());
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Read the response to the last message, which is either a nul byte or
+    /// an error message.
+    async fn read_ack(&mut self) -> Result<(), Error> {
+        let mut byte = [0];
+        if self.stdout.read(&mut byte).await.map_err(Error::ChildIo)? == 0 {
+            return Err(self.unexpected_eof());
+        }
+
+        match byte[0] {
+            0 => Ok(()),
+            1 | 2 => {
+                let msg = self.read_line().await?;
+                Err(Error::Remote(TransferFailure::io_error(msg)))
+            }
+            _ => Err(protocol_error()),
+        }
+    }
+
+    async fn send_message(&mut self, msg: &str) -> Result<(), Error> {
+        self.write(msg.as_bytes()).await?;
+        self.read_ack().await
+    }
+
+    async fn send_dir_header(&mut self, path: &Path) -> Result<(), Error> {
+        let metadata = fs::metadata(path).await.map_err(Error::LocalIo)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        let msg = format!("D{:04o} 0 {}\n", mode, file_name(path)?);
+        self.send_message(&msg).await
+    }
+
+    async fn send_file(
+        &mut self,
+        path: &Path,
+        progress: &mut Option<Progress<'_>>,
+    ) -> Result<(), Error> {
+        let file = File::open(path).await.map_err(Error::LocalIo)?;
+        let metadata = file.metadata().await.map_err(Error::LocalIo)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+        let len = metadata.len();
+
+        let msg = format!("C{:04o} {} {}\n", mode, len, file_name(path)?);
+        self.send_message(&msg).await?;
+
+        report(progress, path, 0, len);
+        let mut file = file.take(len);
+        let mut buf = vec![0; BUF_SIZE];
+        let mut sent = 0;
+        while sent < len {
+            let n = file.read(&mut buf).await.map_err(Error::LocalIo)?;
+            if n == 0 {
+                return Err(Error::LocalIo(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file was truncated while being sent",
+                )));
+            }
+            self.write(&buf[..n]).await?;
+            sent += n as u64;
+            report(progress, path, sent, len);
+        }
+
+        self.send_message("\0").await
+    }
+
+    /// Close the input of the remote `scp` and wait for it to exit.
+    async fn finish(self) -> Result<(), Error> {
+        drop(self.stdin);
+        drop(self.stdout);
+
+        let status = self.child.wait().await?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(remote_error(
+                io::ErrorKind::Other,
+                format!("remote scp exited with {}", status),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_file_header;
+
+    #[test]
+    fn file_header() {
+        assert_eq!(
+            parse_file_header("0644 12 hello world.txt"),
+            Some((0o644, 12, "hello world.txt"))
+        );
+        assert_eq!(parse_file_header("4755 0 x"), Some((0o4755, 0, "x")));
+
+        for invalid in [
+            "0644 12",
+            "0644 x name",
+            "9 12 name",
+            "0644 12 ..",
+            "0644 12 a/b",
+        ] {
+            assert_eq!(parse_file_header(invalid), None, "{invalid}");
+        }
+    }
+}
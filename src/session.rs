@@ -1,4 +1,12 @@
-use super::{Error, ForwardType, KnownHosts, OwningCommand, SessionBuilder, Socket};
+use super::child::AbortOnDrop;
+use super::metrics_sink::Metrics;
+use super::trace::{Op, Target};
+use super::{
+    Backend, Compat, ConcurrencyBudget, ConnectionRecipe, DirectStreamlocal, DirectTcpip, Error,
+    FileOps, ForwardGuard, ForwardType, InteractiveShell, KnownHosts, Latency, MasterEvents,
+    MasterLogReader, MetricsSink, OwningCommand, Plan, PlanError, RemoteCommandMode, RemoteLock,
+    RemoteShell, ScheduledJob, Scp, SessionBuilder, SessionInfo, Socket, Stdio, When,
+};
 
 #[cfg(feature = "process-mux")]
 use super::process_impl;
@@ -10,8 +18,14 @@ use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::ops::Deref;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use std::{fs, io};
 
 use tempfile::TempDir;
+use tokio::sync::{watch, RwLock};
+use tokio::time::{self, Instant};
 
 #[derive(Debug)]
 pub(crate) enum SessionImp {
@@ -49,11 +63,164 @@ macro_rules! delegate {
 /// When the `Session` is dropped, the connection to the remote host is severed, and any errors
 /// silently ignored. To disconnect and be alerted to errors, use [`close`](Session::close).
 #[derive(Debug)]
-pub struct Session(SessionImp);
+pub struct Session {
+    imp: SessionImp,
+    recipe: Option<Box<ConnectionRecipe>>,
+    health: Arc<Health>,
+    budget: Option<ConcurrencyBudget>,
+    commands: Box<CommandSettings>,
+    keepalive: Option<AbortOnDrop>,
+}
+
+/// Settings applied to the commands of a [`Session`], boxed to keep it
+/// small.
+#[derive(Debug)]
+struct CommandSettings {
+    remote_command_mode: RemoteCommandMode,
+    remote_shell: RemoteShell,
+    metrics: Option<Metrics>,
+}
+
+/// Whether [`Session::close`] shuts the ssh multiplex master down, as
+/// returned by [`Session::ownership`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Ownership {
+    /// The master is shut down on close.
+    Owned,
+
+    /// The master is managed outside of this session, which was created
+    /// with [`Session::attach`] or [`Session::attach_mux`], and is left
+    /// running on close. Use [`Session::terminate`] to shut it down.
+    Detached,
+}
+
+/// Health of the connection to the ssh multiplex master, as published by
+/// [`Session::subscribe_health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionHealth {
+    /// No operation has completed yet.
+    Unknown,
+
+    /// The last operation succeeded.
+    Healthy,
+
+    /// The last operation failed in a way that indicates that the
+    /// connection is broken, see [`Session::is_degraded`].
+    Degraded {
+        /// The error, as returned by [`Session::last_error`].
+        error: String,
+    },
+}
+
+/// Last-known health of the ssh multiplex master, updated with the results
+/// of the operations performed through a [`Session`] and its commands.
+#[derive(Debug)]
+pub(crate) struct Health {
+    degraded: AtomicBool,
+    last_error: Mutex<Option<String>>,
+    sender: watch::Sender<ConnectionHealth>,
+    /// Set once [`Session::stop_accepting`] starts.
+    closing: AtomicBool,
+    /// Held for reading by control operations in flight, and for writing
+    /// by [`Session::stop_accepting`] to wait for them to settle.
+    control: RwLock<()>,
+    /// Number of channels open, for [`Session::wait_idle`].
+    channels: watch::Sender<usize>,
+    /// Set for [`Ownership::Detached`] sessions.
+    detached: AtomicBool,
+}
+
+/// Counts a channel as open for [`Session::wait_idle`] until dropped.
+#[derive(Debug)]
+pub(crate) struct ChannelGuard(Arc<Health>);
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        self.0.channels.send_modify(|channels| *channels -= 1);
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            degraded: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+            sender: watch::channel(ConnectionHealth::Unknown).0,
+            closing: AtomicBool::new(false),
+            control: RwLock::new(()),
+            channels: watch::channel(0).0,
+            detached: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Health {
+    pub(crate) fn open_channel(self: &Arc<Self>) -> ChannelGuard {
+        self.channels.send_modify(|channels| *channels += 1);
+        ChannelGuard(self.clone())
+    }
+
+    pub(crate) fn record<T>(&self, res: &Result<T, Error>) {
+        match res {
+            Ok(_) => {
+                self.degraded.store(false, Ordering::Relaxed);
+                self.sender.send_if_modified(|health| {
+                    let modified = *health != ConnectionHealth::Healthy;
+                    *health = ConnectionHealth::Healthy;
+                    modified
+                });
+            }
+            Err(err) => self.record_err(err),
+        }
+    }
 
-// TODO: UserKnownHostsFile for custom known host fingerprint.
+    /// Fail with [`Error::SessionClosing`] once the session has started
+    /// draining.
+    pub(crate) fn ensure_open(&self) -> Result<(), Error> {
+        if self.closing.load(Ordering::Acquire) {
+            Err(Error::SessionClosing)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn record_err(&self, err: &Error) {
+        if !err.is_master_failure() {
+            return;
+        }
+
+        let msg = match std::error::Error::source(err) {
+            Some(source) => format!("{}: {}", err, source),
+            None => err.to_string(),
+        };
+        *self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(msg.clone());
+        self.degraded.store(true, Ordering::Relaxed);
+        self.sender
+            .send_replace(ConnectionHealth::Degraded { error: msg });
+    }
+}
 
 impl Session {
+    fn from_imp(imp: SessionImp) -> Self {
+        Self {
+            imp,
+            recipe: None,
+            health: Arc::default(),
+            budget: None,
+            commands: Box::new(CommandSettings {
+                remote_command_mode: RemoteCommandMode::Command,
+                remote_shell: RemoteShell::Posix,
+                metrics: None,
+            }),
+            keepalive: None,
+        }
+    }
+
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
     ///
     /// By using the built-in [`SessionBuilder`] in openssh, or a custom SessionBuilder,
@@ -97,7 +264,7 @@ impl Session {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub fn new_process_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
     }
 
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
@@ -142,7 +309,7 @@ impl Session {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub fn new_native_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
             tempdir,
         )))
     }
@@ -161,7 +328,7 @@ impl Session {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub fn resume(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::resume(
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::resume(
             ctl, master_log,
         )))
     }
@@ -171,11 +338,56 @@ impl Session {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub fn resume_mux(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
             ctl, master_log,
         )))
     }
 
+    /// Attach to an ssh multiplex master started outside of this crate,
+    /// e.g. with `ssh -M -S ctl`, listening on the control socket `ctl`.
+    ///
+    /// Unlike [`Session::resume`], the master is [checked](Session::check)
+    /// to be alive first, and the session is [`Ownership::Detached`]:
+    /// [`close`](Session::close) leaves the master running, while
+    /// [`terminate`](Session::terminate) still shuts it down.
+    ///
+    /// This connects to the ssh multiplex master using process mux impl.
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub async fn attach(ctl: impl AsRef<Path>) -> Result<Self, Error> {
+        Backend::ProcessMux.ensure_available().await?;
+        Self::resume(ctl.as_ref().into(), None)
+            .into_attached()
+            .await
+    }
+
+    /// Same as [`Session::attach`] except that it connects to the ssh
+    /// multiplex master using native mux impl, whose check also verifies
+    /// that the master speaks a supported version of the mux protocol.
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub async fn attach_mux(ctl: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::resume_mux(ctl.as_ref().into(), None)
+            .into_attached()
+            .await
+    }
+
+    async fn into_attached(self) -> Result<Self, Error> {
+        self.check().await?;
+        self.health.detached.store(true, Ordering::Relaxed);
+        Ok(self)
+    }
+
+    /// Whether [`close`](Session::close) shuts the ssh multiplex master
+    /// down.
+    pub fn ownership(&self) -> Ownership {
+        if self.health.detached.load(Ordering::Relaxed) {
+            Ownership::Detached
+        } else {
+            Ownership::Owned
+        }
+    }
+
     /// Connect to the host at the given `host` over SSH using process impl, which will
     /// spawn a new ssh process for each `Child` created.
     ///
@@ -221,18 +433,321 @@ impl Session {
             .await
     }
 
+    /// Wait for all the channels opened through this session, i.e. its
+    /// remote children, to be closed, for up to `timeout`, e.g. before
+    /// suspending the machine or rotating credentials.
+    ///
+    /// Once idle, the master is [checked](Self::check) to be alive. Returns
+    /// `false` if channels are still open after `timeout`.
+    ///
+    /// Only the channels of this session are tracked, not those of other
+    /// clients of the same master, and a [`Child`](crate::Child) counts as
+    /// open until it is waited for or dropped.
+    #[cfg(not(windows))]
+    #[cfg_attr(docsrs, doc(cfg(not(windows))))]
+    pub async fn wait_idle(&self, timeout: Duration) -> Result<bool, Error> {
+        let mut channels = self.health.channels.subscribe();
+        let idle = time::timeout(timeout, channels.wait_for(|channels| *channels == 0)).await;
+        if idle.is_err() {
+            return Ok(false);
+        }
+
+        self.check().await?;
+        Ok(true)
+    }
+
     /// Check the status of the underlying SSH connection.
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub async fn check(&self) -> Result<(), Error> {
-        delegate!(&self.0, imp, { imp.check().await })
+        let res = delegate!(&self.imp, imp, { imp.check().await });
+        self.health.record(&res);
+        res
+    }
+
+    /// Measure the round-trip time to the remote host by running `true`
+    /// on it `samples` times in a row.
+    ///
+    /// Commands are timed rather than the alive checks of
+    /// [`check`](Self::check), which are answered by the local multiplex
+    /// master without reaching the remote host. Each sample thus includes
+    /// the time to open a channel and to start a process remotely.
+    ///
+    /// The result can be used to scale timeouts with
+    /// [`Latency::scale_timeout`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is 0.
+    pub async fn measure_latency(&self, samples: usize) -> Result<Latency, Error> {
+        assert!(samples > 0, "at least one sample is required");
+
+        let mut durations = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let start = Instant::now();
+            self.command("true")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await?;
+            durations.push(start.elapsed());
+        }
+        Ok(Latency::new(durations))
+    }
+
+    /// Notify the ssh multiplex master that the size of a terminal changed,
+    /// so that it forwards the new window size of the ptys requested with
+    /// [`OwningCommand::request_pty`].
+    ///
+    /// The master reads the size from the terminals passed as stdin of the
+    /// commands, which must thus be resized first, e.g. with the
+    /// `TIOCSWINSZ` ioctl.
+    ///
+    /// This sends `SIGWINCH` to the master, which must thus run on this host
+    /// under the same user.
+    pub async fn notify_window_change(&self) -> Result<(), Error> {
+        let res: Result<u32, Error> = delegate!(&self.imp, imp, { imp.master_pid().await });
+        self.health.record(&res);
+
+        let pid = res?;
+        // Safety: kill is safe to call with any arguments.
+        if unsafe { libc::kill(pid as _, libc::SIGWINCH) } == -1 {
+            return Err(Error::Master(io::Error::last_os_error()));
+        }
+        Ok(())
     }
 
     /// Get the SSH connection's control socket path.
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub fn control_socket(&self) -> &Path {
-        delegate!(&self.0, imp, { imp.ctl() })
+        delegate!(&self.imp, imp, { imp.ctl() })
+    }
+
+    /// Get the path of the temporary directory created for this session,
+    /// which contains the control socket (`master`) and the log of the
+    /// ssh multiplex master (`log`).
+    ///
+    /// Returns `None` if the session does not own such a directory, e.g.
+    /// if it is created by [`Session::resume`] or [`Session::resume_mux`].
+    pub fn state_dir(&self) -> Option<&Path> {
+        delegate!(&self.imp, imp, { imp.state_dir() })
+    }
+
+    /// Get the path of the log of the ssh multiplex master, if known.
+    pub fn master_log(&self) -> Option<&Path> {
+        delegate!(&self.imp, imp, { imp.master_log() })
+    }
+
+    /// Follow the [log of the ssh multiplex master](Session::master_log),
+    /// as a [`Stream`](futures_core::Stream) of [`MasterEvent`](crate::MasterEvent)s.
+    ///
+    /// The stream starts from the beginning of the log and ends once the
+    /// master exits. Most events are only logged by ssh at the debug levels,
+    /// see [`SessionBuilder::master_verbosity`](crate::SessionBuilder::master_verbosity).
+    ///
+    /// Fails with [`Error::Master`] if the log is unknown or cannot be opened.
+    pub fn master_events(&self) -> Result<MasterEvents, Error> {
+        MasterEvents::new(self.known_master_log()?, self.control_socket()).map_err(Error::Master)
+    }
+
+    /// Follow the raw [log of the ssh multiplex master](Session::master_log),
+    /// as an [`AsyncRead`](tokio::io::AsyncRead).
+    ///
+    /// As with [`master_events`](Self::master_events), the reader starts
+    /// from the beginning of the log and reaches its end once the master
+    /// exits.
+    ///
+    /// Fails with [`Error::Master`] if the log is unknown or cannot be opened.
+    pub fn master_log_reader(&self) -> Result<MasterLogReader, Error> {
+        MasterLogReader::new(self.known_master_log()?, self.control_socket()).map_err(Error::Master)
+    }
+
+    /// Return metadata about the connection: the user, host and port as
+    /// resolved from the ssh config, the address and software version of
+    /// the server as logged by the master, and the control socket.
+    ///
+    /// This runs `ssh -G` locally to evaluate the ssh config and reads the
+    /// [log of the master](Self::master_log), without running any remote
+    /// command. The user, host and port are only known for sessions
+    /// created by a [`SessionBuilder`], and the address and version of the
+    /// server only with a
+    /// [`master_verbosity`](SessionBuilder::master_verbosity) of at least 1.
+    pub async fn info(&self) -> Result<SessionInfo, Error> {
+        let mut info = SessionInfo::new(self.control_socket().to_path_buf());
+
+        if let Some(recipe) = self.recipe.as_deref() {
+            info.apply_config(&recipe.resolved_config().await?);
+        }
+        if let Some(log) = self.master_log() {
+            // The log is missing once the master exits.
+            if let Ok(log) = tokio::fs::read_to_string(log).await {
+                info.apply_log(&log);
+            }
+        }
+
+        Ok(info)
+    }
+
+    fn known_master_log(&self) -> Result<&Path, Error> {
+        self.master_log().ok_or_else(|| {
+            Error::Master(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the path of the master log is unknown",
+            ))
+        })
+    }
+
+    pub(crate) fn with_recipe(mut self, recipe: ConnectionRecipe) -> Self {
+        self.recipe = Some(Box::new(recipe));
+        self
+    }
+
+    /// The destination recorded in the spans of the operations, which is
+    /// only known for sessions created by a [`SessionBuilder`].
+    fn target(&self) -> Target {
+        Target::new(self.recipe.as_deref().map(ConnectionRecipe::destination))
+    }
+
+    /// Subscribe to the [health](ConnectionHealth) of the connection, which
+    /// is updated with the results of the operations performed through this
+    /// session and its commands.
+    ///
+    /// Combine it with [`SessionBuilder::keepalive_interval`] to have it
+    /// updated periodically even when the session is idle.
+    pub fn subscribe_health(&self) -> watch::Receiver<ConnectionHealth> {
+        self.health.sender.subscribe()
+    }
+
+    /// Spawn a task that [checks](Session::check) the connection every
+    /// `interval`, until the session is dropped.
+    pub(crate) fn with_keepalive(mut self, interval: Duration) -> Self {
+        let imp = self.watcher_imp();
+        let health = self.health.clone();
+
+        let keepalive = tokio::spawn(async move {
+            let mut ticker = time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let res: Result<(), Error> = delegate!(&imp, imp, { imp.check().await });
+                health.record(&res);
+            }
+        });
+        self.keepalive = Some(AbortOnDrop(keepalive));
+        self
+    }
+
+    /// Return a handle to the master of this session, which does not close
+    /// it on drop.
+    fn watcher_imp(&self) -> SessionImp {
+        // Not using `delegate!`, which would not compile without any backend.
+        match self.imp {
+            #[cfg(feature = "process-mux")]
+            SessionImp::ProcessImpl(ref imp) => SessionImp::ProcessImpl(imp.watcher()),
+
+            #[cfg(feature = "native-mux")]
+            SessionImp::NativeMuxImpl(ref imp) => SessionImp::NativeMuxImpl(imp.watcher()),
+        }
+    }
+
+    /// Return the [`Backend`] of this session.
+    pub(crate) fn backend(&self) -> Backend {
+        // Not using `delegate!`, which would not compile without any backend.
+        match self.imp {
+            #[cfg(feature = "process-mux")]
+            SessionImp::ProcessImpl(_) => Backend::ProcessMux,
+
+            #[cfg(feature = "native-mux")]
+            SessionImp::NativeMuxImpl(_) => Backend::NativeMux,
+        }
+    }
+
+    /// Attach this session to `budget`, limiting the number of its commands
+    /// running simultaneously along with those of the other sessions
+    /// attached to it, or detach it with `None`.
+    ///
+    /// Only the commands spawned afterwards are affected.
+    pub fn set_concurrency_budget(&mut self, budget: Option<ConcurrencyBudget>) {
+        self.budget = budget;
+    }
+
+    /// Report the metrics of this session to `sink`, see [`MetricsSink`],
+    /// or stop reporting them with `None`.
+    ///
+    /// Only the commands spawned afterwards are affected.
+    pub fn set_metrics_sink(&mut self, sink: Option<Arc<dyn MetricsSink>>) {
+        self.commands.metrics = sink.map(Metrics);
+    }
+
+    pub(crate) fn metrics_sink(&self) -> Option<&dyn MetricsSink> {
+        self.commands.metrics.as_ref().map(|metrics| &*metrics.0)
+    }
+
+    /// Set how the commands of this session are sent to the server, see
+    /// [`SessionBuilder::remote_command_mode`].
+    ///
+    /// Only the commands constructed afterwards are affected.
+    pub fn set_remote_command_mode(&mut self, mode: RemoteCommandMode) {
+        self.commands.remote_command_mode = mode;
+    }
+
+    /// Set the shell interpreting the commands of this session on the
+    /// remote host, see [`SessionBuilder::remote_shell`].
+    ///
+    /// Only the commands constructed afterwards are affected.
+    pub fn set_remote_shell(&mut self, shell: RemoteShell) {
+        self.commands.remote_shell = shell;
+    }
+
+    /// Return `true` if the last operation performed through this session
+    /// or its commands failed in a way that indicates that the connection
+    /// to the ssh multiplex master is broken, e.g. [`Error::Disconnected`].
+    ///
+    /// It is reset by the next successful operation, and allows cheaply
+    /// deciding whether to recycle a session without issuing a
+    /// [`check`](Session::check) each time.
+    pub fn is_degraded(&self) -> bool {
+        self.health.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Return the description of the last error which marked this session
+    /// as [degraded](Session::is_degraded), if any.
+    ///
+    /// Unlike [`is_degraded`](Session::is_degraded), it is not reset by
+    /// successful operations.
+    pub fn last_error(&self) -> Option<String> {
+        self.health
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Return the settings this session was established with, which can be
+    /// passed to [`SessionBuilder::from_recipe`] to connect again, possibly
+    /// from another process, with identical settings.
+    ///
+    /// Returns `None` if the session is not created by one of the `connect`
+    /// functions, e.g. by [`Session::resume`] or [`Session::resume_mux`].
+    pub fn export_recipe(&self) -> Option<&ConnectionRecipe> {
+        self.recipe.as_deref()
+    }
+
+    /// Return a builder with the settings this session was established
+    /// with, including the user and port of an `ssh://` destination, to
+    /// connect to another host just like this one.
+    ///
+    /// The settings tied to the destination of this session,
+    /// [`SessionBuilder::resolve_to`] and
+    /// [`SessionBuilder::control_socket_path`], are reset.
+    ///
+    /// Returns `None` if the session is not created by one of the `connect`
+    /// functions, as with [`export_recipe`](Self::export_recipe).
+    pub fn builder_snapshot(&self) -> Option<SessionBuilder> {
+        self.recipe
+            .as_deref()
+            .map(ConnectionRecipe::sibling_builder)
     }
 
     /// Constructs a new [`OwningCommand`] for launching the program at path `program` on the remote
@@ -332,7 +847,9 @@ impl Session {
         P: Into<Cow<'a, str>>,
         S: Deref<Target = Session> + Clone,
     {
-        Self::to_raw_command(session, &*shell_escape::unix::escape(program.into()))
+        let program = program.into();
+        let shell = session.commands.remote_shell;
+        Self::to_raw_command(session, &*shell.escape(OsStr::new(&*program)))
     }
 
     /// Version of [`raw_command`](Self::raw_command) which stores an
@@ -344,10 +861,87 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, {
+        let session_impl = delegate!(&session.imp, imp, {
             imp.raw_command(program.as_ref()).into()
         });
-        OwningCommand::new(session, session_impl)
+        let health = session.health.clone();
+        let budget = session.budget.clone();
+        let metrics = session.commands.metrics.clone();
+        let mode = session.commands.remote_command_mode.clone();
+        let shell = session.commands.remote_shell;
+        let target = session.target();
+        OwningCommand::new(session, session_impl, health, budget)
+            .with_remote_command_mode(mode)
+            .with_remote_shell(shell)
+            .with_target(target)
+            .with_metrics(metrics)
+    }
+
+    /// Queue the shell command `command` to run on the remote host `when`
+    /// requested, with `at` or else `systemd-run`, so that it runs even if
+    /// the connection is closed, see [`ScheduledJob`].
+    ///
+    /// As with [`shell`](Self::shell), `command` is interpreted by `sh`.
+    /// Fails with [`Error::Remote`] of kind [`io::ErrorKind::Unsupported`]
+    /// if neither scheduler is installed.
+    pub async fn schedule(&self, command: &str, when: When) -> Result<ScheduledJob, Error> {
+        ScheduledJob::schedule(self, command, when).await
+    }
+
+    /// Detect the userland of the remote host and return helpers for common
+    /// operations using the flags it supports, see [`Compat`].
+    ///
+    /// This runs a probe command every time it is called, so the returned
+    /// [`Compat`] should be kept around.
+    pub async fn compat(&self) -> Result<Compat<'_>, Error> {
+        Compat::new(self).await
+    }
+
+    /// Run the steps of `plan` in order, stopping at the first failure,
+    /// after which the rollbacks of the completed steps run in reverse
+    /// order, see [`Plan`].
+    pub async fn execute_plan(&self, plan: &Plan) -> Result<(), PlanError> {
+        plan.execute(self).await
+    }
+
+    /// Acquire the lock `name` on the remote host, waiting for other holders
+    /// to release it, e.g. to keep several controllers from changing the
+    /// same host at once, see [`RemoteLock`].
+    ///
+    /// The lock is an `flock` on `openssh-rs-{name}.lock` in `/var/lock`,
+    /// or else in `$XDG_RUNTIME_DIR` or `/tmp` if it is not writable, so it
+    /// is shared by the sessions logged in as the same user, and requires
+    /// `flock` on the remote host. Cancelling the future gives up waiting.
+    ///
+    /// Fails with [`Error::LocalIo`] of kind
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput) if `name` is empty
+    /// or contains other characters than ASCII letters, digits, `.`, `_`
+    /// and `-`.
+    pub async fn remote_lock(&self, name: &str) -> Result<RemoteLock<'_>, Error> {
+        RemoteLock::acquire(self, name).await
+    }
+
+    /// Read and write remote files with plain POSIX commands, for servers
+    /// where both sftp and scp are disabled, see [`FileOps`].
+    pub fn file_ops(&self) -> FileOps<'_> {
+        FileOps::new(self)
+    }
+
+    /// Copy files to and from the remote host with the scp protocol.
+    ///
+    /// Unlike sftp, this does not depend on the sftp subsystem being enabled
+    /// on the server, see [`Scp`] for details.
+    pub fn scp(&self) -> Scp<'_> {
+        Scp::new(self)
+    }
+
+    /// Start the login shell of the remote user on a pseudo-terminal, to
+    /// interact with it through the returned [`InteractiveShell`].
+    ///
+    /// The terminal is initially sized 0x0, which most programs treat as
+    /// 80x24; use [`InteractiveShell::resize`] to set the actual size.
+    pub async fn shell_interactive(&self) -> Result<InteractiveShell<'_>, Error> {
+        InteractiveShell::spawn(self).await
     }
 
     /// Constructs a new [`OwningCommand`] for launching subsystem `program` on the remote
@@ -414,8 +1008,16 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, { imp.subsystem(program.as_ref()).into() });
-        OwningCommand::new(session, session_impl)
+        let session_impl = delegate!(&session.imp, imp, {
+            imp.subsystem(program.as_ref()).into()
+        });
+        let health = session.health.clone();
+        let budget = session.budget.clone();
+        let metrics = session.commands.metrics.clone();
+        let target = session.target();
+        OwningCommand::new(session, session_impl, health, budget)
+            .with_target(target)
+            .with_metrics(metrics)
     }
 
     /// Constructs a new [`OwningCommand`] that runs the provided shell command on the remote host.
@@ -463,6 +1065,35 @@ impl Session {
         cmd
     }
 
+    /// Open a tcp connection from the remote host to `host:port`, tunneled
+    /// through the ssh connection, as with `ssh -W`.
+    ///
+    /// This lets clients of e.g. http or databases talk to services only
+    /// reachable from the remote host, without listening on a local socket.
+    ///
+    /// Whether the remote host manages to connect is only known once the
+    /// tunnel is used, see [`DirectTcpip`].
+    pub fn open_direct_tcpip(&self, host: &str, port: u16) -> Result<DirectTcpip, Error> {
+        self.health.ensure_open()?;
+        let target = format!("[{}]:{}", host, port);
+        DirectTcpip::open(delegate!(&self.imp, imp, { imp.ctl() }), target.as_ref())
+    }
+
+    /// Open a connection to the unix socket at `path` on the remote host,
+    /// tunneled through the ssh connection, e.g. to talk to a remote docker
+    /// daemon at `/var/run/docker.sock`.
+    ///
+    /// With OpenSSH 9.4 or later locally, the connection is forwarded as
+    /// with `ssh -W`, which requires `path` to be absolute. Otherwise, it is
+    /// relayed by `python3` or `perl` run on the remote host.
+    pub async fn open_direct_streamlocal(
+        &self,
+        path: &str,
+    ) -> Result<DirectStreamlocal<'_>, Error> {
+        self.health.ensure_open()?;
+        DirectStreamlocal::open(self, delegate!(&self.imp, imp, { imp.ctl() }), path).await
+    }
+
     /// Request to open a local/remote port forwarding.
     /// The `Socket` can be either a unix socket or a tcp socket.
     ///
@@ -471,20 +1102,72 @@ impl Session {
     ///
     /// Otherwise, `listen_socket` on the remote machine will be forwarded to `connect_socket`
     /// on the local machine.
+    ///
+    /// The forwarding is closed once the returned [`ForwardGuard`] is dropped,
+    /// unless it is [leaked](ForwardGuard::leak).
+    ///
+    /// For local forwardings, a tcp `listen_socket` with port 0 listens on a
+    /// free port picked by the OS, as returned by
+    /// [`ForwardGuard::local_addr`]. The port is only reserved until ssh
+    /// listens on it, so another process may take it in between, in which
+    /// case the request fails.
     pub async fn request_port_forward(
         &self,
         forward_type: impl Into<ForwardType>,
         listen_socket: impl Into<Socket<'_>>,
         connect_socket: impl Into<Socket<'_>>,
-    ) -> Result<(), Error> {
-        delegate!(&self.0, imp, {
-            imp.request_port_forward(
-                forward_type.into(),
-                listen_socket.into(),
-                connect_socket.into(),
-            )
-            .await
-        })
+    ) -> Result<ForwardGuard, Error> {
+        let forward_type = forward_type.into();
+        let mut listen_socket = listen_socket.into().into_owned();
+        let connect_socket = connect_socket.into().into_owned();
+
+        let local_addr = match (forward_type, &mut listen_socket) {
+            (ForwardType::Local, Socket::TcpSocket { host, port }) if *port == 0 => {
+                let addr = std::net::TcpListener::bind((&**host, 0))
+                    .and_then(|listener| listener.local_addr())
+                    .map_err(Error::LocalIo)?;
+                *host = addr.ip().to_string().into();
+                *port = addr.port();
+                Some(addr)
+            }
+            (ForwardType::Local, Socket::TcpSocket { host, port }) => host
+                .parse()
+                .ok()
+                .map(|ip| std::net::SocketAddr::new(ip, *port)),
+            _ => None,
+        };
+
+        let op = Op::port_forward(
+            &self.target(),
+            "request",
+            forward_type,
+            &listen_socket,
+            &connect_socket,
+        );
+        let fut = async {
+            let _guard = self.health.control.read().await;
+            self.health.ensure_open()?;
+
+            let res = delegate!(&self.imp, imp, {
+                imp.request_port_forward(
+                    forward_type,
+                    listen_socket.clone(),
+                    connect_socket.clone(),
+                )
+                .await
+            });
+            self.health.record(&res);
+            res
+        };
+        op.run(fut, |_| None).await?;
+
+        Ok(ForwardGuard::new(
+            Session::from_imp(self.watcher_imp()),
+            forward_type,
+            listen_socket,
+            connect_socket,
+            local_addr,
+        ))
     }
 
     /// Close a previously established local/remote port forwarding.
@@ -496,22 +1179,113 @@ impl Session {
         listen_socket: impl Into<Socket<'_>>,
         connect_socket: impl Into<Socket<'_>>,
     ) -> Result<(), Error> {
-        delegate!(&self.0, imp, {
-            imp.close_port_forward(
-                forward_type.into(),
-                listen_socket.into(),
-                connect_socket.into(),
-            )
-            .await
-        })
+        let forward_type = forward_type.into();
+        let listen_socket = listen_socket.into();
+        let connect_socket = connect_socket.into();
+
+        let op = Op::port_forward(
+            &self.target(),
+            "close",
+            forward_type,
+            &listen_socket,
+            &connect_socket,
+        );
+        let fut = async {
+            let _guard = self.health.control.read().await;
+            self.health.ensure_open()?;
+
+            let res = delegate!(&self.imp, imp, {
+                imp.close_port_forward(forward_type, listen_socket, connect_socket)
+                    .await
+            });
+            self.health.record(&res);
+            res
+        };
+        op.run(fut, |_| None).await
     }
 
     /// Terminate the remote connection.
     ///
     /// This destructor terminates the ssh multiplex server
-    /// regardless of how it was created.
+    /// regardless of how it was created, unless the session is
+    /// [`Ownership::Detached`].
     pub async fn close(self) -> Result<(), Error> {
-        let res: Result<Option<TempDir>, Error> = delegate!(self.0, imp, { imp.close().await });
+        let op = Op::session(&self.target(), "close");
+        op.run(self.close_impl(), |_| None).await
+    }
+
+    async fn close_impl(self) -> Result<(), Error> {
+        if self.ownership() == Ownership::Detached {
+            self.detach();
+            return Ok(());
+        }
+
+        if self.health.closing.load(Ordering::Acquire) {
+            // The master is draining and no longer listens on the control
+            // socket, so only the local state is left to remove.
+            let state_dir = self.state_dir().map(Path::to_path_buf);
+            self.detach();
+            return state_dir
+                .map(fs::remove_dir_all)
+                .transpose()
+                .map_err(Error::Cleanup)
+                .map(|_| ());
+        }
+
+        let res: Result<Option<TempDir>, Error> = delegate!(self.imp, imp, { imp.close().await });
+
+        res?.map(TempDir::close)
+            .transpose()
+            .map_err(Error::Cleanup)
+            .map(|_| ())
+    }
+
+    /// Make the ssh multiplex master stop accepting new commands, port
+    /// forwards and sessions, while those already running continue.
+    ///
+    /// The control socket is removed, and the master exits once the last
+    /// of them completes, which allows draining the connection gracefully,
+    /// e.g. while the controlling application is being redeployed.
+    ///
+    /// Once this is called, new commands and port forwarding requests fail
+    /// with [`Error::SessionClosing`], while the port forwarding requests
+    /// already in flight are waited for before the master is told to stop.
+    /// [`close`](Session::close) then only removes the local state.
+    ///
+    /// This is what [`close`](Session::close) does with the `native-mux`
+    /// backend.
+    pub async fn stop_accepting(&self) -> Result<(), Error> {
+        let op = Op::session(&self.target(), "stop_accepting");
+        op.run(self.stop_accepting_impl(), |_| None).await
+    }
+
+    async fn stop_accepting_impl(&self) -> Result<(), Error> {
+        if self.health.closing.swap(true, Ordering::AcqRel) {
+            return Err(Error::SessionClosing);
+        }
+        let _guard = self.health.control.write().await;
+
+        let res: Result<(), Error> = delegate!(&self.imp, imp, { imp.stop_accepting().await });
+        if res.is_err() {
+            self.health.closing.store(false, Ordering::Release);
+        }
+        self.health.record(&res);
+        res
+    }
+
+    /// Terminate the ssh multiplex master immediately, along with all the
+    /// commands, port forwards and sessions still running through it.
+    ///
+    /// This is what [`close`](Session::close) does with the `process-mux`
+    /// backend.
+    pub async fn terminate(self) -> Result<(), Error> {
+        let op = Op::session(&self.target(), "terminate");
+        op.run(self.terminate_impl(), |_| None).await
+    }
+
+    async fn terminate_impl(self) -> Result<(), Error> {
+        let res: Result<Option<TempDir>, Error> =
+            delegate!(self.imp, imp, { imp.terminate().await });
 
         res?.map(TempDir::close)
             .transpose()
@@ -524,6 +1298,50 @@ impl Session {
     ///
     /// Return (path to control socket, path to ssh multiplex output log)
     pub fn detach(self) -> (Box<Path>, Option<Box<Path>>) {
-        delegate!(self.0, imp, { imp.detach() })
+        delegate!(self.imp, imp, { imp.detach() })
+    }
+
+    /// Same as [`Session::detach`], except that the
+    /// [state directory](Session::state_dir) is moved to `dir` first,
+    /// so that the control socket and the log end up at a location
+    /// chosen by the caller.
+    ///
+    /// `dir` must not exist yet (or be an empty directory) and must be
+    /// on the same filesystem as the state directory.
+    ///
+    /// Return (path to control socket, path to ssh multiplex output log),
+    /// both inside `dir`, which can be passed to [`Session::resume`] or
+    /// [`Session::resume_mux`].
+    ///
+    /// On failure, the `Session` is returned untouched along with the error.
+    #[allow(clippy::type_complexity)]
+    pub fn detach_to(
+        self,
+        dir: impl AsRef<Path>,
+    ) -> Result<(Box<Path>, Option<Box<Path>>), (io::Error, Self)> {
+        let dir = dir.as_ref();
+
+        let state_dir = match self.state_dir() {
+            Some(state_dir) => state_dir.to_path_buf(),
+            None => {
+                let err = io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "session does not own a state directory",
+                );
+                return Err((err, self));
+            }
+        };
+
+        if let Err(err) = fs::rename(&state_dir, dir) {
+            return Err((err, self));
+        }
+
+        let rebase = |path: Box<Path>| match path.strip_prefix(&state_dir) {
+            Ok(relative) => dir.join(relative).into_boxed_path(),
+            Err(_) => path,
+        };
+
+        let (ctl, master_log) = self.detach();
+        Ok((rebase(ctl), master_log.map(rebase)))
     }
 }
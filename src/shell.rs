@@ -0,0 +1,258 @@
+use super::{Error, PtyOptions, RemoteChild, Session, Stdio};
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::ptr;
+use std::task::{Context, Poll};
+
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+fn cvt(ret: i32) -> io::Result<i32> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Open a pty and return its leader, in non-blocking mode, and follower.
+fn open_pty() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut leader = -1;
+    let mut follower = -1;
+
+    // Safety: the out pointers are valid and the optional ones are null.
+    cvt(unsafe {
+        libc::openpty(
+            &mut leader,
+            &mut follower,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    })?;
+
+    // Safety: openpty succeeded, so both fds are open and owned by us.
+    let (leader, follower) =
+        unsafe { (OwnedFd::from_raw_fd(leader), OwnedFd::from_raw_fd(follower)) };
+
+    for fd in [leader.as_raw_fd(), follower.as_raw_fd()] {
+        // Safety: fd is open.
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    }
+
+    let fd = leader.as_raw_fd();
+    // Safety: fd is open.
+    unsafe {
+        let flags = cvt(libc::fcntl(fd, libc::F_GETFL))?;
+        cvt(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+    }
+
+    Ok((leader, follower))
+}
+
+fn make_raw(fd: RawFd) -> io::Result<()> {
+    let mut termios = MaybeUninit::uninit();
+
+    // Safety: fd is open and tcgetattr initializes termios on success.
+    unsafe {
+        cvt(libc::tcgetattr(fd, termios.as_mut_ptr()))?;
+        let mut termios = termios.assume_init();
+        libc::cfmakeraw(&mut termios);
+        cvt(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+    }
+    Ok(())
+}
+
+/// Signal sent to the foreground process of an [`InteractiveShell`] by
+/// [`InteractiveShell::send_signal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShellSignal {
+    /// `SIGINT`, as sent by `Ctrl-C`.
+    Interrupt,
+    /// `SIGQUIT`, as sent by `Ctrl-\`.
+    Quit,
+    /// `SIGTSTP`, as sent by `Ctrl-Z`.
+    Suspend,
+}
+
+impl ShellSignal {
+    fn control_char(self) -> u8 {
+        match self {
+            ShellSignal::Interrupt => 0x03,
+            ShellSignal::Quit => 0x1c,
+            ShellSignal::Suspend => 0x1a,
+        }
+    }
+}
+
+/// A remote login shell attached to a pseudo-terminal, as returned by
+/// [`Session::shell_interactive`].
+///
+/// It implements [`AsyncRead`] and [`AsyncWrite`] to interact with the
+/// terminal, so that TUI or expect-style automation can be built on top
+/// of it. As with any terminal, what is written is echoed back, and
+/// stdout and stderr are merged.
+#[derive(Debug)]
+pub struct InteractiveShell<'s> {
+    session: &'s Session,
+    child: RemoteChild<'s>,
+    pty: AsyncFd<OwnedFd>,
+}
+
+impl<'s> InteractiveShell<'s> {
+    pub(crate) async fn spawn(session: &'s Session) -> Result<InteractiveShell<'s>, Error> {
+        let (leader, follower) = open_pty().map_err(Error::ChildIo)?;
+        let stdio = || {
+            follower
+                .try_clone()
+                .map(Stdio::from)
+                .map_err(Error::ChildIo)
+        };
+
+        let mut cmd = session.raw_command("exec \"$SHELL\" -l");
+        cmd.request_pty(PtyOptions::new())
+            .stdin(stdio()?)
+            .stdout(stdio()?)
+            .stderr(stdio()?);
+        let child = cmd.spawn().await?;
+
+        // ssh puts its stdin in raw mode once the remote pty is allocated,
+        // with the original modes sent to the remote side, so that the local
+        // terminal passes everything through. Mimic it for the mux client.
+        if !cmd.enters_raw_mode() {
+            make_raw(leader.as_raw_fd()).map_err(Error::ChildIo)?;
+        }
+
+        Ok(Self {
+            session,
+            child,
+            pty: AsyncFd::new(leader).map_err(Error::ChildIo)?,
+        })
+    }
+
+    /// Resize the terminal to `cols` columns and `rows` rows, and
+    /// [notify](Session::notify_window_change) the remote side.
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), Error> {
+        let winsize = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        // Safety: the fd is open and winsize is valid for TIOCSWINSZ.
+        cvt(unsafe { libc::ioctl(self.pty.as_raw_fd(), libc::TIOCSWINSZ as _, &winsize) })
+            .map_err(Error::ChildIo)?;
+
+        self.session.notify_window_change().await
+    }
+
+    /// Send `signal` to the foreground process of the remote terminal.
+    ///
+    /// The ssh multiplex protocol does not support sending signals, so this
+    /// types the corresponding control character, which is turned into the
+    /// signal by the remote terminal unless its settings are changed, e.g.
+    /// by a program reading raw input.
+    pub async fn send_signal(&mut self, signal: ShellSignal) -> Result<(), Error> {
+        self.write_all(&[signal.control_char()])
+            .await
+            .map_err(Error::ChildIo)
+    }
+
+    /// Access the handle of the remote shell process.
+    pub fn child(&mut self) -> &mut RemoteChild<'s> {
+        &mut self.child
+    }
+
+    /// Close the terminal, which hangs up the remote shell, and wait for it
+    /// to exit.
+    pub async fn wait(self) -> Result<ExitStatus, Error> {
+        drop(self.pty);
+        self.child.wait().await
+    }
+}
+
+impl AsyncRead for InteractiveShell<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.pty.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let unfilled = buf.initialize_unfilled();
+            let res = guard.try_io(|fd| {
+                // Safety: unfilled is valid for writes of its length.
+                let n = unsafe {
+                    libc::read(fd.as_raw_fd(), unfilled.as_mut_ptr().cast(), unfilled.len())
+                };
+                if n == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+
+            match res {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                // Reading the leader fails with EIO once all the followers
+                // are closed, i.e. the remote shell exited.
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::EIO) => {
+                    return Poll::Ready(Ok(()))
+                }
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for InteractiveShell<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = match self.pty.poll_write_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let res = guard.try_io(|fd| {
+                // Safety: buf is valid for reads of its length.
+                let n = unsafe { libc::write(fd.as_raw_fd(), buf.as_ptr().cast(), buf.len()) };
+                if n == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+
+            match res {
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
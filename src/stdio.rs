@@ -1,3 +1,4 @@
+use super::metrics_sink::Meter;
 use super::Error;
 
 #[cfg(feature = "native-mux")]
@@ -11,7 +12,7 @@ use std::process;
 use std::task::{Context, Poll};
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
-    net::unix::pipe::{Receiver as PipeReader, Sender as PipeWriter},
+    net::unix::pipe::{pipe, Receiver as PipeReader, Sender as PipeWriter},
 };
 
 #[derive(Debug)]
@@ -28,6 +29,10 @@ pub(crate) enum StdioImpl {
 
 /// Describes what to do with a standard I/O stream for a remote child process
 /// when passed to the stdin, stdout, and stderr methods of Command.
+///
+/// Any [`OwnedFd`], e.g. a [`File`] or a
+/// [`UnixStream`](std::os::unix::net::UnixStream), can be converted into
+/// a `Stdio` safely, which then owns it and closes it exactly once on drop.
 #[derive(Debug)]
 pub struct Stdio(pub(crate) StdioImpl);
 impl Stdio {
@@ -114,6 +119,7 @@ impl_try_from_for_stdio!(ChildStdout);
 impl_try_from_for_stdio!(ChildStderr);
 
 impl_from_for_stdio!(File);
+impl_from_for_stdio!(std::os::unix::net::UnixStream);
 
 macro_rules! impl_try_from_tokio_process_child_for_stdio {
     ($type:ident) => {
@@ -132,16 +138,29 @@ impl_try_from_tokio_process_child_for_stdio!(ChildStdout);
 impl_try_from_tokio_process_child_for_stdio!(ChildStderr);
 
 /// Input for the remote child.
+///
+/// Shutting it down with [`AsyncWriteExt::shutdown`](tokio::io::AsyncWriteExt::shutdown)
+/// closes the underlying pipe, so that the remote child receives EOF,
+/// without having to drop the handle.
 #[derive(Debug)]
-pub struct ChildStdin(PipeWriter);
+pub struct ChildStdin(PipeWriter, Option<Meter>);
 
 /// Stdout for the remote child.
 #[derive(Debug)]
-pub struct ChildStdout(PipeReader);
+pub struct ChildStdout(PipeReader, Option<Meter>);
 
 /// Stderr for the remote child.
 #[derive(Debug)]
-pub struct ChildStderr(PipeReader);
+pub struct ChildStderr(PipeReader, Option<Meter>);
+
+impl ChildStdin {
+    /// Create a local pipe whose write end stands in for the stdin of a
+    /// remote child, for tasks forwarding what is written to it.
+    pub(crate) fn local_pipe() -> io::Result<(Self, PipeReader)> {
+        let (writer, reader) = pipe()?;
+        Ok((Self(writer, None), reader))
+    }
+}
 
 pub(crate) trait TryFromChildIo<T>: Sized {
     type Error;
@@ -158,7 +177,7 @@ macro_rules! impl_from_impl_child_io {
                 let fd = arg.into_owned_fd().map_err(Error::ChildIo)?;
 
                 <$inner>::from_owned_fd(fd)
-                    .map(Self)
+                    .map(|pipe| Self(pipe, None))
                     .map_err(Error::ChildIo)
             }
         }
@@ -170,7 +189,7 @@ macro_rules! impl_from_impl_child_io {
             type Error = Error;
 
             fn try_from(arg: native_mux_impl::$type) -> Result<Self, Self::Error> {
-                Ok(Self(arg))
+                Ok(Self(arg, None))
             }
         }
     };
@@ -210,6 +229,15 @@ macro_rules! impl_child_stdio {
         }
     };
 
+    (set_meter, $type:ty) => {
+        impl $type {
+            /// Report the bytes going through this stream to `meter`.
+            pub(crate) fn set_meter(&mut self, meter: Meter) {
+                self.1 = Some(meter);
+            }
+        }
+    };
+
     (AsyncRead, $type:ty) => {
         impl_child_stdio!(AsRawFd, $type);
         impl_child_stdio!(AsFd, $type);
@@ -221,7 +249,12 @@ macro_rules! impl_child_stdio {
                 cx: &mut Context<'_>,
                 buf: &mut ReadBuf<'_>,
             ) -> Poll<io::Result<()>> {
-                Pin::new(&mut self.0).poll_read(cx, buf)
+                let filled = buf.filled().len();
+                let poll = Pin::new(&mut self.0).poll_read(cx, buf);
+                if let Some(meter) = &self.1 {
+                    meter.record(buf.filled().len() - filled);
+                }
+                poll
             }
         }
     };
@@ -237,7 +270,11 @@ macro_rules! impl_child_stdio {
                 cx: &mut Context<'_>,
                 buf: &[u8],
             ) -> Poll<io::Result<usize>> {
-                Pin::new(&mut self.0).poll_write(cx, buf)
+                let poll = Pin::new(&mut self.0).poll_write(cx, buf);
+                if let (Some(meter), Poll::Ready(Ok(n))) = (&self.1, &poll) {
+                    meter.record(*n);
+                }
+                poll
             }
 
             fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
@@ -248,7 +285,19 @@ macro_rules! impl_child_stdio {
                 mut self: Pin<&mut Self>,
                 cx: &mut Context<'_>,
             ) -> Poll<io::Result<()>> {
-                Pin::new(&mut self.0).poll_shutdown(cx)
+                match Pin::new(&mut self.0).poll_shutdown(cx) {
+                    Poll::Ready(Ok(())) => (),
+                    res => return res,
+                }
+
+                // Close the pipe so that the remote process receives EOF,
+                // but keep a valid fd around: it is replaced with a pipe
+                // whose read end is already closed, so that further writes
+                // fail with `BrokenPipe`.
+                let (closed, _) = pipe()?;
+                self.0 = closed;
+
+                Poll::Ready(Ok(()))
             }
 
             fn poll_write_vectored(
@@ -256,7 +305,11 @@ macro_rules! impl_child_stdio {
                 cx: &mut Context<'_>,
                 bufs: &[io::IoSlice<'_>],
             ) -> Poll<io::Result<usize>> {
-                Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+                let poll = Pin::new(&mut self.0).poll_write_vectored(cx, bufs);
+                if let (Some(meter), Poll::Ready(Ok(n))) = (&self.1, &poll) {
+                    meter.record(*n);
+                }
+                poll
             }
 
             fn is_write_vectored(&self) -> bool {
@@ -269,3 +322,6 @@ macro_rules! impl_child_stdio {
 impl_child_stdio!(AsyncWrite, ChildStdin);
 impl_child_stdio!(AsyncRead, ChildStdout);
 impl_child_stdio!(AsyncRead, ChildStderr);
+
+impl_child_stdio!(set_meter, ChildStdin);
+impl_child_stdio!(set_meter, ChildStdout);
@@ -0,0 +1,155 @@
+//! Instrumentation of the operations of sessions and commands, with the
+//! `tracing` feature.
+//!
+//! Every operation gets a `debug` span with the same fields whichever the
+//! backend is, and an event is emitted in it once it completes, with the
+//! time it took and either the exit status or the error.
+
+use super::{Error, ForwardType, Socket};
+
+use std::future::Future;
+use std::process::ExitStatus;
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// The destination of a session, recorded in the spans of its operations.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Target {
+    #[cfg(feature = "tracing")]
+    destination: Option<Box<str>>,
+}
+
+impl Target {
+    pub(crate) fn new(destination: Option<&str>) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = destination;
+
+        Self {
+            #[cfg(feature = "tracing")]
+            destination: destination.map(Into::into),
+        }
+    }
+}
+
+/// An instrumented operation.
+#[derive(Debug)]
+pub(crate) struct Op {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl Op {
+    fn new(span: tracing::Span) -> Self {
+        Self {
+            span,
+            start: Instant::now(),
+        }
+    }
+
+    /// Operation `op` of a command, where `command` returns the remote
+    /// command line, only called if the span is enabled.
+    pub(crate) fn command(
+        target: &Target,
+        op: &'static str,
+        command: impl FnOnce() -> Vec<u8>,
+    ) -> Self {
+        let span = tracing::debug_span!(
+            "openssh::command",
+            op,
+            destination = target.destination.as_deref(),
+            command = tracing::field::Empty,
+        );
+        if !span.is_disabled() {
+            span.record("command", String::from_utf8_lossy(&command()).as_ref());
+        }
+        Self::new(span)
+    }
+
+    /// Operation `op` of a session.
+    pub(crate) fn session(target: &Target, op: &'static str) -> Self {
+        Self::new(tracing::debug_span!(
+            "openssh::session",
+            op,
+            destination = target.destination.as_deref(),
+        ))
+    }
+
+    /// Port forwarding operation `op` of a session.
+    pub(crate) fn port_forward(
+        target: &Target,
+        op: &'static str,
+        forward_type: ForwardType,
+        listen_socket: &Socket<'_>,
+        connect_socket: &Socket<'_>,
+    ) -> Self {
+        Self::new(tracing::debug_span!(
+            "openssh::port_forward",
+            op,
+            destination = target.destination.as_deref(),
+            ?forward_type,
+            ?listen_socket,
+            ?connect_socket,
+        ))
+    }
+
+    /// Run `fut` in the span of the operation, reporting its outcome with
+    /// the exit status returned by `status`, if any.
+    pub(crate) async fn run<T, F, S>(self, fut: F, status: S) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+        S: FnOnce(&T) -> Option<ExitStatus>,
+    {
+        let res = fut.instrument(self.span.clone()).await;
+        let elapsed_ms = self.start.elapsed().as_millis() as u64;
+
+        let _enter = self.span.enter();
+        match &res {
+            Ok(value) => match status(value) {
+                Some(status) => tracing::debug!(elapsed_ms, %status, "completed"),
+                None => tracing::debug!(elapsed_ms, "completed"),
+            },
+            Err(err) => tracing::debug!(elapsed_ms, error = %err, "failed"),
+        }
+        res
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Op {
+    pub(crate) fn command(
+        _target: &Target,
+        _op: &'static str,
+        _command: impl FnOnce() -> Vec<u8>,
+    ) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn session(_target: &Target, _op: &'static str) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn port_forward(
+        _target: &Target,
+        _op: &'static str,
+        _forward_type: ForwardType,
+        _listen_socket: &Socket<'_>,
+        _connect_socket: &Socket<'_>,
+    ) -> Self {
+        Self {}
+    }
+
+    pub(crate) async fn run<T, F, S>(self, fut: F, _status: S) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+        S: FnOnce(&T) -> Option<ExitStatus>,
+    {
+        fut.await
+    }
+}
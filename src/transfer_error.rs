@@ -0,0 +1,164 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Why the remote host failed to access a file, as parsed from the error
+/// messages of the remote commands and returned by
+/// [`Error::transfer_error`](crate::Error::transfer_error).
+///
+/// This covers the transfers of [`Scp`](crate::Scp), the operations of
+/// [`FileOps`](crate::FileOps) and the other helpers running commands on
+/// the remote host, so that retry and reporting logic can branch on the
+/// cause instead of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TransferError {
+    /// The remote user is not allowed to access the file.
+    PermissionDenied,
+    /// The disk quota of the remote user is exceeded, or the filesystem is
+    /// full.
+    QuotaExceeded,
+    /// The file, or one of its parent directories, does not exist.
+    NoSuchFile,
+    /// The file is on a read-only filesystem.
+    ReadOnlyFs,
+}
+
+impl TransferError {
+    /// Parse the error message of a remote command, e.g.
+    /// `scp: /etc/shadow: Permission denied`, or `None` if it is not
+    /// recognized.
+    pub(crate) fn parse(msg: &str) -> Option<Self> {
+        // Also covers `No such file or directory`.
+        if msg.contains("No such file") {
+            Some(TransferError::NoSuchFile)
+        } else if msg.contains("Permission denied") || msg.contains("Operation not permitted") {
+            Some(TransferError::PermissionDenied)
+        } else if msg.contains("Disk quota exceeded")
+            || msg.contains("No space left on device")
+            || msg.contains("Quota exceeded")
+        {
+            Some(TransferError::QuotaExceeded)
+        } else if msg.contains("Read-only file system") {
+            Some(TransferError::ReadOnlyFs)
+        } else {
+            None
+        }
+    }
+
+    /// Map the status code of an sftp `SSH_FXP_STATUS` response, e.g. as
+    /// reported by `openssh-sftp-client`, or `None` if it is not one of the
+    /// failures above.
+    pub fn from_sftp_status(code: u32) -> Option<Self> {
+        match code {
+            // SSH_FX_NO_SUCH_FILE, SSH_FX_NO_SUCH_PATH
+            2 | 10 => Some(TransferError::NoSuchFile),
+            // SSH_FX_PERMISSION_DENIED
+            3 => Some(TransferError::PermissionDenied),
+            // SSH_FX_WRITE_PROTECT
+            12 => Some(TransferError::ReadOnlyFs),
+            // SSH_FX_NO_SPACE_ON_FILESYSTEM, SSH_FX_QUOTA_EXCEEDED
+            14 | 15 => Some(TransferError::QuotaExceeded),
+            _ => None,
+        }
+    }
+
+    fn kind(self) -> io::ErrorKind {
+        match self {
+            TransferError::PermissionDenied => io::ErrorKind::PermissionDenied,
+            TransferError::NoSuchFile => io::ErrorKind::NotFound,
+            TransferError::QuotaExceeded | TransferError::ReadOnlyFs => io::ErrorKind::Other,
+        }
+    }
+}
+
+/// The payload of the io error of [`Error::Remote`](crate::Error::Remote)
+/// when the failure is recognized, which displays as the message of the
+/// remote command.
+#[derive(Debug)]
+pub(crate) struct TransferFailure {
+    pub(crate) reason: TransferError,
+    pub(crate) msg: String,
+}
+
+impl TransferFailure {
+    /// Turn the error message of a remote command into an io error, whose
+    /// kind reflects the failure if it is recognized.
+    pub(crate) fn io_error(msg: String) -> io::Error {
+        match TransferError::parse(&msg) {
+            Some(reason) => io::Error::new(reason.kind(), TransferFailure { reason, msg }),
+            None => io::Error::new(io::ErrorKind::Other, msg),
+        }
+    }
+}
+
+impl fmt::Display for TransferFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for TransferFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::{TransferError, TransferFailure};
+
+    use std::io;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            TransferError::parse("scp: /etc/shadow: Permission denied"),
+            Some(TransferError::PermissionDenied)
+        );
+        assert_eq!(
+            TransferError::parse("chmod: changing permissions of 'a': Operation not permitted"),
+            Some(TransferError::PermissionDenied)
+        );
+        assert_eq!(
+            TransferError::parse("cat: /missing: No such file or directory"),
+            Some(TransferError::NoSuchFile)
+        );
+        assert_eq!(
+            TransferError::parse("scp: /home/user/big: Disk quota exceeded"),
+            Some(TransferError::QuotaExceeded)
+        );
+        assert_eq!(
+            TransferError::parse("sh: 1: cannot create /tmp/a: No space left on device"),
+            Some(TransferError::QuotaExceeded)
+        );
+        assert_eq!(
+            TransferError::parse("scp: /mnt/ro/a: Read-only file system"),
+            Some(TransferError::ReadOnlyFs)
+        );
+        assert_eq!(TransferError::parse("scp: protocol error"), None);
+
+        assert_eq!(
+            TransferError::from_sftp_status(3),
+            Some(TransferError::PermissionDenied)
+        );
+        assert_eq!(TransferError::from_sftp_status(4), None);
+    }
+
+    #[test]
+    fn io_error() {
+        let err = TransferFailure::io_error("scp: /a: No such file or directory".to_owned());
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "scp: /a: No such file or directory");
+        let failure = err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<TransferFailure>()
+            .unwrap();
+        assert_eq!(failure.reason, TransferError::NoSuchFile);
+
+        let err = TransferFailure::io_error("scp: protocol error".to_owned());
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(err
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<TransferFailure>()
+            .is_none());
+    }
+}
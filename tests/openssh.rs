@@ -4,15 +4,16 @@ use std::{
     env,
     io::{self, Write},
     net::IpAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
+    sync::Arc,
     time::Duration,
 };
 use tempfile::tempdir;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{UnixListener, UnixStream},
-    time::sleep,
+    time::{sleep, timeout},
 };
 
 use openssh::*;
@@ -376,6 +377,49 @@ async fn over_session_err_because_cwd() {
     }
 }
 
+/// Test that `over_ssh_with_opts` translates env vars and `current_dir`.
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn over_session_with_opts() {
+    let opts = TranslateOpts {
+        env: EnvStrategy::PrefixEnvCommand,
+        cwd: CwdStrategy::CdAnd,
+    };
+    for session in connects().await {
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("pwd; echo \"$MY_ENV_VAR\"; echo \"${HOME-unset}\"")
+            .env("MY_ENV_VAR", "it's a $value")
+            .env_remove("HOME")
+            .current_dir("/");
+        let output = command
+            .over_ssh_with_opts(&session, opts)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"/\nit's a $value\nunset\n");
+
+        assert!(matches!(
+            command.over_ssh(&session),
+            Err(openssh::Error::CommandHasEnv)
+        ));
+
+        let output = std::process::Command::new("echo")
+            .current_dir("/does not exist")
+            .over_ssh_with_opts(&session, opts)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+
+        session.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn shell() {
@@ -822,7 +866,7 @@ async fn remote_socket_forward() {
         let output_listener = UnixListener::bind(&unix_socket).unwrap();
 
         eprintln!("Requesting port forward");
-        session
+        let forward = session
             .request_port_forward(ForwardType::Remote, (loopback(), *port), &*unix_socket)
             .await
             .unwrap();
@@ -852,10 +896,7 @@ async fn remote_socket_forward() {
         assert_eq!(DATA, &buffer);
 
         eprintln!("Canceling port forward");
-        session
-            .close_port_forward(ForwardType::Remote, (loopback(), *port), &*unix_socket)
-            .await
-            .unwrap();
+        forward.close().await.unwrap();
 
         eprintln!("Trying to connect again");
         let e = output.try_read(&mut buffer).unwrap_err();
@@ -897,7 +938,8 @@ async fn local_socket_forward() {
         session
             .request_port_forward(ForwardType::Local, &*unix_socket, (loopback(), port))
             .await
-            .unwrap();
+            .unwrap()
+            .leak();
 
         eprintln!("Connecting to forwarded socket");
         let mut output = UnixStream::connect(&unix_socket).await.unwrap();
@@ -1157,3 +1199,1852 @@ async fn test_session_to_command() {
         assert!(status.success());
     }
 }
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn chaff_keepalive() {
+    for session in connects().await {
+        let mut child = session
+            .command("cat")
+            .chaff_keepalive(Duration::from_millis(100), "\n")
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        let mut stdin = child.stdin().take().unwrap();
+        sleep(Duration::from_millis(350)).await;
+        stdin.write_all(b"hello\n").await.unwrap();
+        drop(stdin);
+
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.starts_with(b"\n\n"));
+        assert!(output.stdout.ends_with(b"\nhello\n"));
+
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn test_state_dir_and_detach_to() {
+    for (session, name) in connects_with_name().await {
+        let state_dir = session.state_dir().unwrap().to_path_buf();
+        assert!(state_dir.is_dir());
+        assert!(session.control_socket().starts_with(&state_dir));
+
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("state");
+
+        let (ctl, master_log) = session.detach_to(&target).unwrap();
+        assert!(!state_dir.exists());
+        assert!(ctl.starts_with(&target));
+        assert!(master_log.as_deref().unwrap().starts_with(&target));
+
+        let session: Session = match name {
+            #[cfg(feature = "process-mux")]
+            "process-mux" => Session::resume(ctl, master_log),
+            #[cfg(feature = "native-mux")]
+            "native-mux" => Session::resume_mux(ctl, master_log),
+            _ => unreachable!(),
+        };
+        assert!(session.state_dir().is_none());
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn trampoline_threshold() {
+    let long_arg = "x".repeat(4096);
+
+    for session in connects().await {
+        // stdin defaults to null for `output`
+        let out = session
+            .command("echo")
+            .arg(&long_arg)
+            .trampoline_threshold(64)
+            .output()
+            .await
+            .unwrap();
+        assert!(out.status.success());
+        assert_eq!(out.stdout, format!("{}\n", long_arg).into_bytes());
+
+        // stdin after the script is passed through to the command
+        let mut child = session
+            .command("cat")
+            .arg("-")
+            .arg(&long_arg)
+            .trampoline_threshold(64)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+        {
+            let mut stdin = child.stdin().take().unwrap();
+            stdin.write_all(b"hello\n").await.unwrap();
+        }
+        let out = child.wait_with_output().await.unwrap();
+        assert!(out.status.success());
+        assert_eq!(out.stdout, b"hello\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn preflight_banner() {
+    let listener = tokio::net::TcpListener::bind((loopback(), 0))
+        .await
+        .unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"SSH-2.0-Fake_1.0\r\n").await.unwrap();
+    });
+
+    let banner = preflight(
+        &format!("ssh://test-user@127.0.0.1:{}", port),
+        Duration::from_secs(5),
+    )
+    .await
+    .unwrap();
+    assert_eq!(banner, "SSH-2.0-Fake_1.0");
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn preflight_refused() {
+    match preflight("ssh://127.0.0.1:9", Duration::from_secs(5)).await {
+        Err(Error::Connect(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused),
+        res => unreachable!("{:?}", res),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn preflight_server() {
+    let banner = preflight(&addr(), Duration::from_secs(5)).await.unwrap();
+    assert!(banner.starts_with("SSH-2.0-"), "{}", banner);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn connection_recipe() {
+    for (session, name) in connects_with_name().await {
+        let recipe = session.export_recipe().unwrap().clone();
+        assert_eq!(recipe.destination(), "127.0.0.1");
+
+        let builder = SessionBuilder::from_recipe(&recipe);
+        assert_eq!(builder.get_user(), Some("test-user"));
+        assert_eq!(builder.get_port(), Some("2222"));
+
+        let session2: Session = match name {
+            #[cfg(feature = "process-mux")]
+            "process-mux" => builder.connect(recipe.destination()).await.unwrap(),
+            #[cfg(feature = "native-mux")]
+            "native-mux" => builder.connect_mux(recipe.destination()).await.unwrap(),
+            _ => unreachable!(),
+        };
+        assert!(session2.export_recipe().is_some());
+
+        session2.check().await.unwrap();
+        session2.close().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn resolve_to() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .resolve_to(loopback());
+
+    for session in
+        session_builder_connect(builder, "ssh://test-user@opensshtest.invalid:2222").await
+    {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn degraded_session() {
+    let dir = tempdir().unwrap();
+    let ctl: Box<Path> = dir.path().join("master").into();
+
+    let mut sessions: Vec<Session> = Vec::new();
+    #[cfg(feature = "process-mux")]
+    sessions.push(Session::resume(ctl.clone(), None));
+    #[cfg(feature = "native-mux")]
+    sessions.push(Session::resume_mux(ctl.clone(), None));
+
+    for session in sessions {
+        assert!(!session.is_degraded());
+        assert!(session.last_error().is_none());
+
+        session.check().await.unwrap_err();
+        assert!(session.is_degraded());
+        assert!(session.last_error().is_some());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn healthy_session() {
+    for session in connects().await {
+        session.command("true").status().await.unwrap();
+        assert!(!session.is_degraded());
+        assert!(session.last_error().is_none());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn setsid() {
+    for session in connects().await {
+        // The shell is the leader of the new session.
+        let status = session
+            .shell("[ \"$(ps -o sid= -p $$)\" -eq $$ ]")
+            .setsid(true)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let status = session
+            .command("false")
+            .setsid(true)
+            .status()
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn stdin_shutdown() {
+    for session in connects().await {
+        let mut child = session
+            .command("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        let mut stdin = child.stdin().take().unwrap();
+        stdin.write_all(b"hello\n").await.unwrap();
+        stdin.shutdown().await.unwrap();
+
+        // cat only exits once it gets EOF
+        let mut stdout = Vec::new();
+        let mut child_stdout = child.stdout().take().unwrap();
+        child_stdout.read_to_end(&mut stdout).await.unwrap();
+        assert_eq!(stdout, b"hello\n");
+
+        let err = stdin.write_all(b"world\n").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        assert!(child.wait().await.unwrap().success());
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn try_wait() {
+    for session in connects().await {
+        let mut child = session.command("sleep").arg("1").spawn().await.unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            sleep(Duration::from_millis(100)).await;
+        };
+        assert!(status.success());
+        assert!(child.try_wait().unwrap().unwrap().success());
+        assert!(child.wait().await.unwrap().success());
+
+        let mut child = session.command("false").spawn().await.unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        assert_eq!(child.wait().await.unwrap().code(), Some(1));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn command_env() {
+    for (session, name) in connects_with_name().await {
+        let res = session
+            .command("true")
+            .env("LANG", "C")
+            .envs([("OPENSSH_RS_TEST", "\"quoted\" \\value")])
+            .status()
+            .await;
+
+        // Whether the variables are set depends on the `AcceptEnv` of the
+        // server, so only check that they are sent without error.
+        if name == "native-mux" {
+            assert!(matches!(res, Err(Error::CommandHasEnv)), "{res:?}");
+        } else {
+            assert!(res.unwrap().success());
+        }
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn output_in() {
+    let pool = std::sync::Mutex::new(vec![b"stale".to_vec()]);
+
+    for session in connects().await {
+        let output = session
+            .command("echo")
+            .arg("hello")
+            .output_in(&pool)
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+        assert!(output.stderr.is_empty());
+
+        pool.put(output.stdout);
+        pool.put(output.stderr);
+
+        session.close().await.unwrap();
+    }
+
+    assert!(!pool.lock().unwrap().is_empty());
+}
+
+#[cfg(feature = "process-mux")]
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn generated_config() {
+    let mut config = ConfigWriter::new();
+    config.host(
+        HostConfig::new("openssh-rs-test-alias")
+            .host_name(loopback().to_string())
+            .port(2222)
+            .user("test-user"),
+    );
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .config(config);
+
+    let session: Session = builder.connect("openssh-rs-test-alias").await.unwrap();
+    let output = session.command("whoami").output().await.unwrap();
+    assert_eq!(output.stdout, b"test-user\n");
+    session.close().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn master_events() {
+    use futures_core::Stream;
+    use std::future::poll_fn;
+    use std::pin::Pin;
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .master_verbosity(1);
+
+    for session in session_builder_connect(builder, &addr()).await {
+        assert!(session.master_log().unwrap().is_file());
+
+        let status = session.command("true").status().await.unwrap();
+        assert!(status.success());
+
+        let mut events = session.master_events().unwrap();
+        let mut host_key = false;
+        let mut authenticated = false;
+        let mut channel_opened = false;
+
+        while !(host_key && authenticated && channel_opened) {
+            let event = poll_fn(|cx| Pin::new(&mut events).poll_next(cx))
+                .await
+                .unwrap()
+                .unwrap();
+
+            match event.kind() {
+                MasterEventKind::HostKey { fingerprint, .. } => {
+                    assert!(fingerprint.starts_with("SHA256:"));
+                    host_key = true;
+                }
+                MasterEventKind::Authenticated { method } => {
+                    assert_eq!(method, "publickey");
+                    authenticated = true;
+                }
+                MasterEventKind::ChannelOpen { .. } => channel_opened = true,
+                _ => (),
+            }
+        }
+
+        let mut reader = session.master_log_reader().unwrap();
+        let mut buf = [0; 6];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"debug1");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn concurrency_budget() {
+    let budget = ConcurrencyBudget::new(1);
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .concurrency_budget(budget.clone());
+
+    let sessions = session_builder_connect(builder, &addr()).await;
+    for session in &sessions {
+        let child = session.command("sleep").arg("1").spawn().await.unwrap();
+        assert_eq!(budget.available(), 0);
+
+        for other in &sessions {
+            let mut cmd = other.command("true");
+            let spawn = timeout(Duration::from_millis(300), cmd.status());
+            assert!(spawn.await.is_err());
+        }
+
+        assert!(child.wait().await.unwrap().success());
+        assert_eq!(budget.available(), 1);
+        assert!(session.command("true").status().await.unwrap().success());
+    }
+
+    for session in sessions {
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn subscribe_health() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .keepalive_interval(Duration::from_millis(100));
+
+    for session in session_builder_connect(builder, &addr()).await {
+        let mut health = session.subscribe_health();
+
+        // The first check runs right away.
+        timeout(
+            Duration::from_secs(5),
+            health.wait_for(|health| *health == ConnectionHealth::Healthy),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let ctl = session.control_socket().to_path_buf();
+        let status = std::process::Command::new("ssh")
+            .arg("-S")
+            .arg(&ctl)
+            .arg("-O")
+            .arg("exit")
+            .arg("none")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        timeout(
+            Duration::from_secs(5),
+            health.wait_for(|health| matches!(health, ConnectionHealth::Degraded { .. })),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(session.is_degraded());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn reconnecting_session() {
+    let policy = RetryPolicy::new().initial_backoff(Duration::from_millis(10));
+
+    for session in connects().await {
+        let session = ReconnectingSession::new(session, policy.clone()).unwrap();
+        let whoami =
+            |session: Arc<Session>| async move { session.arc_command("whoami").output().await };
+
+        let output = session.run(whoami).await.unwrap();
+        assert_eq!(output.stdout, b"test-user\n");
+
+        let ctl = session.session().await.control_socket().to_path_buf();
+        let status = std::process::Command::new("ssh")
+            .arg("-S")
+            .arg(&ctl)
+            .arg("-O")
+            .arg("exit")
+            .arg("none")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = session.run(whoami).await.unwrap();
+        assert_eq!(output.stdout, b"test-user\n");
+        assert_ne!(session.session().await.control_socket(), ctl);
+    }
+
+    #[cfg(feature = "process-mux")]
+    {
+        let session = Session::resume(Path::new("/nonexistent").into(), None);
+        assert!(ReconnectingSession::new(session, RetryPolicy::new()).is_err());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+
+    let mut pool = SessionPool::new(builder);
+    pool.max_sessions_per_host(2)
+        .max_commands_per_session(1)
+        .max_idle(Some(Duration::from_millis(100)));
+
+    let host = addr();
+    let output = pool
+        .command(&host, "whoami")
+        .await
+        .unwrap()
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, b"test-user\n");
+
+    // The first session is reused once idle, and another one is connected
+    // while it is busy.
+    let first = pool.session(&host).await.unwrap();
+    let second = pool.session(&host).await.unwrap();
+    assert_ne!(first.control_socket(), second.control_socket());
+    // Beyond the limit, the sessions are shared.
+    let third = pool.session(&host).await.unwrap();
+    assert!([first.control_socket(), second.control_socket()].contains(&third.control_socket()));
+
+    // A lost session is replaced.
+    let ctl = first.control_socket().to_path_buf();
+    drop((first, second, third));
+    let status = std::process::Command::new("ssh")
+        .arg("-S")
+        .arg(&ctl)
+        .arg("-O")
+        .arg("exit")
+        .arg("none")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let session = pool.session(&host).await.unwrap();
+    assert_ne!(session.control_socket(), ctl);
+    let ctl = session.control_socket().to_path_buf();
+    drop(session);
+
+    // Idle sessions are closed.
+    sleep(Duration::from_millis(200)).await;
+    pool.evict_idle().await;
+    assert!(!ctl.exists());
+    let session = pool.session(&host).await.unwrap();
+    assert_ne!(session.control_socket(), ctl);
+    drop(session);
+
+    pool.close().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn request_pty() {
+    for session in connects().await {
+        let output = session
+            .shell("tty; echo $TERM")
+            .request_pty(PtyOptions::new().term("vt100"))
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.split("\r\n").collect();
+        assert!(lines[0].starts_with("/dev/"), "{stdout:?}");
+        assert_eq!(lines[1], "vt100");
+
+        // Without a pty, `tty` fails.
+        let status = session.command("tty").status().await.unwrap();
+        assert!(!status.success());
+
+        session.notify_window_change().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn capture_head_tail() {
+    for session in connects().await {
+        let output = session
+            .shell("seq 1 10000; echo oops >&2")
+            .capture_head_tail(8, 6)
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout.head, b"1\n2\n3\n4\n");
+        assert_eq!(output.stdout.tail, b"10000\n");
+        assert_eq!(output.stderr.head, b"oops\n");
+        assert!(output.stderr.tail.is_empty());
+        assert_eq!(output.stderr.dropped, 0);
+
+        let total = (1..=10000)
+            .map(|i: u32| i.to_string().len() + 1)
+            .sum::<usize>();
+        assert_eq!(output.stdout.dropped, (total - 8 - 6) as u64);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn interactive_shell() {
+    for session in connects().await {
+        let mut shell = session.shell_interactive().await.unwrap();
+        shell.resize(100, 40).await.unwrap();
+
+        shell.write_all(b"stty size; exit\n").await.unwrap();
+
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(10), shell.read_to_end(&mut output))
+            .await
+            .unwrap()
+            .unwrap();
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("40 100"), "{}", output);
+
+        assert!(shell.wait().await.unwrap().success());
+
+        let mut shell = session.shell_interactive().await.unwrap();
+        shell.write_all(b"sleep 100\n").await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        shell.send_signal(ShellSignal::Interrupt).await.unwrap();
+        shell.write_all(b"exit 3\n").await.unwrap();
+
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(10), shell.read_to_end(&mut output))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(shell.wait().await.unwrap().code(), Some(3));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn scp() {
+    for session in connects().await {
+        let local = tempdir().unwrap();
+        let src = local.path().join("src");
+        std::fs::create_dir_all(src.join("nested")).unwrap();
+        std::fs::write(src.join("a.txt"), "hello\n").unwrap();
+        std::fs::write(src.join("nested").join("b.txt"), vec![b'b'; 100_000]).unwrap();
+
+        let remote = String::from_utf8(
+            session
+                .command("mktemp")
+                .arg("-d")
+                .output()
+                .await
+                .unwrap()
+                .stdout,
+        )
+        .unwrap();
+        let remote = remote.trim();
+
+        let mut last = 0;
+        let mut scp = session.scp();
+        scp.on_progress(|_, transferred, total| {
+            assert!(transferred <= total);
+            last = transferred;
+        });
+        scp.send(src.join("nested").join("b.txt"), remote)
+            .await
+            .unwrap();
+        drop(scp);
+        assert_eq!(last, 100_000);
+
+        let mut scp = session.scp();
+        scp.send_dir(&src, format!("{}/copy", remote))
+            .await
+            .unwrap();
+        let output = session
+            .command("cat")
+            .arg(format!("{}/copy/a.txt", remote))
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+
+        scp.recv(format!("{}/copy/nested/b.txt", remote), local.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(local.path().join("b.txt")).unwrap(),
+            vec![b'b'; 100_000]
+        );
+
+        let err = scp
+            .recv(format!("{}/missing", remote), local.path())
+            .await
+            .unwrap_err();
+        assert_eq!(err.transfer_error(), Some(TransferError::NoSuchFile));
+
+        drop(scp);
+
+        session
+            .command("rm")
+            .arg("-rf")
+            .arg(remote)
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn remote_command_mode() {
+    for mut session in connects().await {
+        session.set_remote_command_mode(RemoteCommandMode::ForcedCommandPayload {
+            template: "echo payload: {command}".to_string(),
+        });
+        let output = session.command("printf").arg("a b").output().await.unwrap();
+        assert_eq!(output.stdout, b"payload: printf a b\n");
+
+        session.set_remote_command_mode(RemoteCommandMode::SubsystemOnly);
+        let err = session.command("true").status().await.unwrap_err();
+        assert!(matches!(err, Error::CommandRejected), "{:?}", err);
+
+        session.set_remote_command_mode(RemoteCommandMode::Command);
+        assert!(session.command("true").status().await.unwrap().success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn remote_shell_syntax() {
+    for mut session in connects().await {
+        session.set_remote_shell(RemoteShell::PowerShell);
+        let mut cmd = session.command("echo");
+        cmd.pipe(session.command("cat"));
+        let err = cmd.status().await.unwrap_err();
+        assert!(
+            matches!(err, Error::UnsupportedShellSyntax("pipe")),
+            "{:?}",
+            err
+        );
+
+        // csh can redirect stdout, but not stderr alone.
+        session.set_remote_shell(RemoteShell::Csh);
+        let err = session
+            .command("true")
+            .stderr_to_remote_file("/dev/null")
+            .status()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::UnsupportedShellSyntax("stderr_to_remote_file")),
+            "{:?}",
+            err
+        );
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn control_socket_label() {
+    let mut builder = SessionBuilder::default();
+    builder.control_socket_label("report export");
+
+    for session in session_builder_connect(builder, &addr()).await {
+        let dir = session.control_socket().parent().unwrap();
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        assert!(
+            name.starts_with(".ssh-connection-report_export-"),
+            "{}",
+            name
+        );
+
+        assert!(session.command("true").status().await.unwrap().success());
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn stop_accepting_and_terminate() {
+    for session in connects().await {
+        let child = session
+            .shell("sleep 1; echo done")
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        session.stop_accepting().await.unwrap();
+        let err = session.command("true").status().await.unwrap_err();
+        assert!(matches!(err, Error::SessionClosing), "{:?}", err);
+        let err = session.stop_accepting().await.unwrap_err();
+        assert!(matches!(err, Error::SessionClosing), "{:?}", err);
+
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+
+        session.close().await.unwrap();
+    }
+
+    for session in connects().await {
+        session.terminate().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn file_ops() {
+    for session in connects().await {
+        let dir = String::from_utf8(
+            session
+                .command("mktemp")
+                .arg("-d")
+                .output()
+                .await
+                .unwrap()
+                .stdout,
+        )
+        .unwrap();
+        let path = Path::new(dir.trim()).join("a file's name");
+
+        let ops = session.file_ops();
+        assert!(!ops.exists(&path).await.unwrap());
+
+        ops.write(&path, "hello\n").await.unwrap();
+        ops.append(&path, b"world\n").await.unwrap();
+        assert!(ops.exists(&path).await.unwrap());
+        assert_eq!(ops.read(&path).await.unwrap(), b"hello\nworld\n");
+
+        ops.chmod(&path, 0o600).await.unwrap();
+        let mut stat = session.command("stat");
+        stat.arg("-c").arg("%a").arg(path.to_str().unwrap());
+        assert_eq!(stat.output().await.unwrap().stdout, b"600\n");
+
+        let uid = ops.user_id("test-user").await.unwrap();
+        let mut id = session.command("id");
+        id.arg("-g");
+        let gid = String::from_utf8(id.output().await.unwrap().stdout).unwrap();
+        ops.chown(&path, uid, gid.trim().parse().unwrap())
+            .await
+            .unwrap();
+        let err = ops.chown(&path, 0, 0).await.unwrap_err();
+        assert_eq!(err.transfer_error(), Some(TransferError::PermissionDenied));
+        assert!(ops.user_id("no-such-user").await.is_err());
+
+        let err = ops.read(path.with_extension("missing")).await.unwrap_err();
+        assert_eq!(err.transfer_error(), Some(TransferError::NoSuchFile));
+        match err {
+            Error::Remote(err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        session
+            .command("rm")
+            .arg("-rf")
+            .arg(dir.trim())
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn compat() {
+    for session in connects().await {
+        let compat = session.compat().await.unwrap();
+        assert_ne!(compat.userland(), Userland::Unknown);
+
+        let dir = compat.mktemp(true).await.unwrap();
+        let path = Path::new(&dir).join("file");
+        session.file_ops().write(&path, "hello\n").await.unwrap();
+
+        assert_eq!(compat.file_size(&path).await.unwrap(), 6);
+        assert!(compat.modified(&path).await.unwrap() > 0);
+        let dotted = format!("{}/./file", dir);
+        assert_eq!(
+            compat.canonicalize(dotted).await.unwrap(),
+            compat.canonicalize(&path).await.unwrap()
+        );
+
+        session
+            .command("rm")
+            .arg("-rf")
+            .arg(&dir)
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn schedule() {
+    for session in connects().await {
+        let job = match session.schedule("echo hello; exit 3", When::Now).await {
+            Ok(job) => job,
+            // The test server may have neither at nor systemd.
+            Err(Error::Remote(err)) if err.kind() == io::ErrorKind::Unsupported => {
+                session.close().await.unwrap();
+                continue;
+            }
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+
+        let status = timeout(Duration::from_secs(120), async {
+            loop {
+                match job.status(&session).await.unwrap() {
+                    JobStatus::Exited(code) => break code,
+                    _ => sleep(Duration::from_secs(1)).await,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(status, 3);
+        assert_eq!(job.fetch_output(&session).await.unwrap(), b"hello\n");
+
+        let spool = job.spool().to_owned();
+        job.clone().cancel(&session).await.unwrap();
+        match job.status(&session).await.unwrap_err() {
+            Error::Remote(err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert!(!session.file_ops().exists(spool).await.unwrap());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[cfg(feature = "encoding")]
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn output_encoding() {
+    use openssh::encoding_rs::SHIFT_JIS;
+
+    for session in connects().await {
+        let output = session
+            .shell(r"printf '\202\240'; printf '\202\242' >&2")
+            .output_encoding(SHIFT_JIS, DecodeErrors::Fail)
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "あ".as_bytes());
+        assert_eq!(output.stderr, "い".as_bytes());
+
+        let err = session
+            .shell(r"printf '\377'")
+            .output_encoding(SHIFT_JIS, DecodeErrors::Fail)
+            .output()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidOutputEncoding("Shift_JIS")));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn wait_idle() {
+    for session in connects().await {
+        assert!(session.wait_idle(Duration::from_secs(1)).await.unwrap());
+
+        let mut cmd = session.command("sleep");
+        cmd.arg("2");
+        let child = cmd.spawn().await.unwrap();
+        assert!(!session.wait_idle(Duration::from_millis(100)).await.unwrap());
+
+        let (idle, status) = tokio::join!(session.wait_idle(Duration::from_secs(10)), child.wait());
+        assert!(status.unwrap().success());
+        assert!(idle.unwrap());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn broadcast() {
+    use futures_core::Stream;
+    use std::future::poll_fn;
+    use std::pin::Pin;
+
+    let sessions = connects().await;
+
+    let mut results = openssh::broadcast(&sessions, "echo", ["a b", "$HOME"], 1);
+    let mut seen = vec![false; sessions.len()];
+    while let Some((index, res)) = poll_fn(|cx| Pin::new(&mut results).poll_next(cx)).await {
+        let output = res.unwrap();
+        assert_eq!(output.stdout, b"a b $HOME\n");
+        seen[index] = true;
+    }
+    assert!(seen.iter().all(|seen| *seen));
+    drop(results);
+
+    for session in sessions {
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(any(feature = "process-mux", feature = "native-mux"))]
+async fn control_socket_path() {
+    async fn check(session: Session, socket: &Path) {
+        assert!(socket.exists());
+
+        let output = session.command("echo").arg("foo").output().await.unwrap();
+        assert_eq!(output.stdout, b"foo\n");
+
+        session.close().await.unwrap();
+        assert!(!socket.exists());
+    }
+
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("cm-100%");
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .user_known_hosts_file(get_known_hosts_path())
+        .control_socket_path(&socket);
+
+    // The path can only be reused once the previous master exits, so the
+    // sessions are connected one at a time.
+    #[cfg(feature = "process-mux")]
+    check(builder.connect(&addr()).await.unwrap(), &socket).await;
+
+    #[cfg(feature = "native-mux")]
+    check(builder.connect_mux(&addr()).await.unwrap(), &socket).await;
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "process-mux")]
+async fn attach() {
+    for session in connects().await {
+        let (ctl, _master_log) = session.detach();
+
+        let attached = Session::attach(&ctl).await.unwrap();
+        assert_eq!(attached.ownership(), Ownership::Detached);
+        let output = attached.command("echo").arg("foo").output().await.unwrap();
+        assert_eq!(output.stdout, b"foo\n");
+        attached.close().await.unwrap();
+
+        // The master is still running after close.
+        let attached = Session::attach(&ctl).await.unwrap();
+        attached.terminate().await.unwrap();
+
+        Session::attach(&ctl).await.unwrap_err();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn locale_and_timezone() {
+    for session in connects().await {
+        let output = session
+            .command("date")
+            .arg("+%Z")
+            .locale("C")
+            .timezone("UTC")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"UTC\n");
+
+        let output = session
+            .raw_command("printenv")
+            .force_env("OPENSSH_TEST", "a b")
+            .raw_arg("OPENSSH_TEST")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"a b\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn builder_snapshot() {
+    for session in connects().await {
+        let builder = session.builder_snapshot().unwrap();
+        for sibling in session_builder_connect(builder, &addr()).await {
+            let output = sibling.command("echo").arg("foo").output().await.unwrap();
+            assert_eq!(output.stdout, b"foo\n");
+
+            sibling.close().await.unwrap();
+        }
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn redirect_to_remote_file() {
+    for session in connects().await {
+        let dir = session
+            .command("mktemp")
+            .arg("-d")
+            .output()
+            .await
+            .unwrap()
+            .stdout;
+        let dir = String::from_utf8(dir).unwrap();
+        let dir = Path::new(dir.trim_end());
+
+        let output = session
+            .command("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .stdout_to_remote_file(dir.join("out file"))
+            .stderr_to_remote_file(dir.join("err"))
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+
+        let output = session
+            .command("cat")
+            .stdin_from_remote_file(dir.join("out file"))
+            .arg("-")
+            .raw_arg(dir.join("err"))
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"out\nerr\n");
+
+        session
+            .command("rm")
+            .arg("-rf")
+            .raw_arg(dir)
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "harness")]
+async fn harness() {
+    let server = openssh::harness::TestServer::start().await.unwrap();
+
+    let mut sessions = Vec::with_capacity(2);
+    #[cfg(feature = "process-mux")]
+    sessions.push(
+        server
+            .builder()
+            .connect(server.destination())
+            .await
+            .unwrap(),
+    );
+    #[cfg(feature = "native-mux")]
+    sessions.push(
+        server
+            .builder()
+            .connect_mux(server.destination())
+            .await
+            .unwrap(),
+    );
+
+    for session in sessions {
+        let output = session.command("echo").arg("foo").output().await.unwrap();
+        assert_eq!(output.stdout, b"foo\n");
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn pipe() {
+    for session in connects().await {
+        let mut sort = session.command("sort");
+        sort.arg("-r");
+
+        let output = session
+            .command("printf")
+            .arg("a b\\nc|d\\n")
+            .pipe(sort)
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"c|d\na b\n");
+
+        let mut next = session.command("sh");
+        next.arg("-c")
+            .arg("cat; echo \"${A-unset} $B\"")
+            .force_env("B", "next");
+        let output = session
+            .command("echo")
+            .arg("first")
+            .pipe(next)
+            .force_env("A", "self")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"first\nunset next\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "native-mux")]
+async fn find_existing_master() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("cm");
+    let config = dir.path().join("config");
+    std::fs::write(&config, format!("ControlPath {}\n", socket.display())).unwrap();
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .config_file(&config);
+    assert_eq!(builder.find_existing_master(&addr()).await.unwrap(), None);
+
+    let session = builder
+        .clone()
+        .user_known_hosts_file(get_known_hosts_path())
+        .control_socket_path(&socket)
+        .connect_mux(&addr())
+        .await
+        .unwrap();
+
+    let found = builder.find_existing_master(&addr()).await.unwrap();
+    assert_eq!(found.as_deref(), Some(socket.as_path()));
+
+    let attached = Session::attach_mux(found.unwrap()).await.unwrap();
+    let output = attached.command("echo").arg("foo").output().await.unwrap();
+    assert_eq!(output.stdout, b"foo\n");
+    attached.close().await.unwrap();
+
+    session.close().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn info() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .master_verbosity(1);
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        let info = session.info().await.unwrap();
+        assert_eq!(info.user(), Some("test-user"));
+        assert_eq!(info.port(), Some(2222));
+        assert_eq!(info.control_socket(), session.control_socket());
+        assert_eq!(info.remote_addr().unwrap().port(), 2222);
+        assert!(info.server_version().unwrap().starts_with("OpenSSH_"));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn execute_plan() {
+    for session in connects().await {
+        let dir = session
+            .command("mktemp")
+            .arg("-d")
+            .output()
+            .await
+            .unwrap()
+            .stdout;
+        let dir = String::from_utf8(dir).unwrap().trim_end().to_owned();
+        let a = format!("{}/a", dir);
+        let b = format!("{}/b", dir);
+
+        let mut plan = Plan::new();
+        plan.run("touch", [&a])
+            .rollback_run("rm", [&a])
+            .rename(&a, &b)
+            .rollback_run("mv", [&b, &a])
+            .step("fail", |session| {
+                Box::pin(async move {
+                    session.command("false").status().await?;
+                    Err(Error::Disconnected)
+                })
+            });
+
+        let err = session.execute_plan(&plan).await.unwrap_err();
+        assert_eq!(err.index(), 2);
+        assert_eq!(err.step(), "fail");
+        assert!(err.rollback_errors().is_empty());
+
+        let output = session.command("ls").arg(&dir).output().await.unwrap();
+        assert_eq!(output.stdout, b"");
+
+        session
+            .command("rm")
+            .args(["-rf", &dir])
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn forward_agent() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .forward_agent(true);
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        let status = session
+            .shell(r#"test -S "$SSH_AUTH_SOCK""#)
+            .forward_agent(true)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let status = session
+            .shell(r#"test -S "$SSH_AUTH_SOCK""#)
+            .forward_agent(false)
+            .status()
+            .await
+            .unwrap();
+        assert!(!status.success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn identity_from_memory() {
+    let key = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/.test-key")).unwrap();
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .identity_from_memory(key)
+        // Make sure the key of the agent is not used instead.
+        .ssh_auth_sock("/nonexistent");
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        session.check().await.unwrap();
+
+        let dir = session.control_socket().parent().unwrap();
+        assert!(!dir.join("identity").exists());
+
+        // The key is not kept around to reconnect with.
+        let session = ReconnectingSession::new(session, RetryPolicy::new()).unwrap_err();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn forward_x11() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .forward_x11(true, false);
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        // Without a local `DISPLAY`, the command runs without X11.
+        let status = session
+            .command("true")
+            .forward_x11(true)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn max_output_size() {
+    for session in connects().await {
+        let output = session
+            .command("printf")
+            .arg("0123456789")
+            .max_output_size(10)
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"0123456789");
+
+        let err = session
+            .command("yes")
+            .max_output_size(1024)
+            .output()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::OutputTooLarge), "{:?}", err);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn measure_latency() {
+    for session in connects().await {
+        let latency = session.measure_latency(5).await.unwrap();
+        assert_eq!(latency.samples(), 5);
+        assert!(latency.min() <= latency.percentile(50.0));
+        assert!(latency.percentile(50.0) <= latency.max());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn stream() {
+    use futures_core::Stream;
+    use openssh::OutputChunk;
+    use std::future::poll_fn;
+    use std::pin::Pin;
+
+    for session in connects().await {
+        let mut stream = session
+            .shell("printf 'a\\nb'; sleep 1; printf 'c\\n'; echo err >&2; exit 3")
+            .stream()
+            .await
+            .unwrap()
+            .line_buffered(true);
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            match chunk.unwrap() {
+                OutputChunk::Stdout(line) => stdout.push(line),
+                OutputChunk::Stderr(line) => stderr.push(line),
+            }
+        }
+        assert_eq!(stdout, [&b"a\n"[..], b"bc\n"]);
+        assert_eq!(stderr, [b"err\n"]);
+        assert_eq!(stream.wait().await.unwrap().code(), Some(3));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn stdin_transform() {
+    for session in connects().await {
+        let script: &'static [u8] = b"select {{table}};\n\nselect 1;";
+        let output = session
+            .command("cat")
+            .stdin_transform(script, |line| line.replace("{{table}}", "users"))
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"select users;\n\nselect 1;");
+        assert!(output.status.success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn ssh_askpass() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .batch_mode(false)
+        // Never asked, since the key of the agent is accepted.
+        .ssh_askpass("/bin/false");
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn forward_guard() {
+    use tokio::net::TcpStream;
+
+    for session in connects().await {
+        let forward = session
+            .request_port_forward(ForwardType::Local, (loopback(), 0), (loopback(), 2222))
+            .await
+            .unwrap();
+        let addr = forward.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut banner = [0; 4];
+        stream.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-");
+        drop(stream);
+
+        drop(forward);
+        // Closed by a task spawned on drop.
+        sleep(Duration::from_secs(1)).await;
+        TcpStream::connect(addr).await.unwrap_err();
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn remote_lock() {
+    for session in connects().await {
+        let lock = session.remote_lock("openssh-rs-test").await.unwrap();
+        assert!(lock.path().ends_with("/openssh-rs-openssh-rs-test.lock"));
+
+        // Held until released.
+        let second = timeout(
+            Duration::from_secs(1),
+            session.remote_lock("openssh-rs-test"),
+        )
+        .await;
+        assert!(second.is_err());
+
+        lock.release().await.unwrap();
+        let lock = session.remote_lock("openssh-rs-test").await.unwrap();
+        lock.release().await.unwrap();
+
+        match session.remote_lock("../test").await.unwrap_err() {
+            Error::LocalIo(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            e => unreachable!("{:?}", e),
+        }
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn capture_to_file() {
+    use openssh::CaptureOptions;
+
+    for session in connects().await {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("build");
+
+        let captured = session
+            .shell("echo out; echo err >&2; exit 3")
+            .capture_to_file(&path, CaptureOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(captured.status.code(), Some(3));
+        assert_eq!(captured.stdout.len, 4);
+        assert_eq!(captured.stdout.files, [dir.path().join("build.stdout")]);
+        assert_eq!(std::fs::read(&captured.stdout.files[0]).unwrap(), b"out\n");
+        assert_eq!(std::fs::read(&captured.stderr.files[0]).unwrap(), b"err\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn direct_tcpip() {
+    use tokio::io::AsyncWriteExt;
+
+    for session in connects().await {
+        let mut tunnel = session.open_direct_tcpip("127.0.0.1", 2222).unwrap();
+        let mut banner = [0; 4];
+        tunnel.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-");
+        tunnel.shutdown().await.unwrap();
+        tunnel.write_all(b"closed").await.unwrap_err();
+        drop(tunnel);
+
+        // Nothing listens on the discard port.
+        let mut tunnel = session.open_direct_tcpip("127.0.0.1", 9).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(tunnel.read_to_end(&mut buf).await.unwrap(), 0);
+        let err = tunnel.wait().await.unwrap_err();
+        assert!(matches!(err, Error::Remote(_)), "{:?}", err);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn direct_streamlocal() {
+    use tokio::io::AsyncWriteExt;
+
+    for session in connects().await {
+        let python = session.shell("command -v python3").status().await.unwrap();
+        if !python.success() {
+            eprintln!("skipping: the echo server requires python3 on the remote host");
+            continue;
+        }
+
+        let dir = session.command("mktemp").arg("-d").output().await.unwrap();
+        let dir = String::from_utf8(dir.stdout).unwrap();
+        let socket = format!("{}/echo.sock", dir.trim());
+
+        // Echo a single line.
+        let mut server = session
+            .command("python3")
+            .arg("-c")
+            .arg(
+                "import socket, sys\n\
+                 s = socket.socket(socket.AF_UNIX)\n\
+                 s.bind(sys.argv[1]); s.listen(1)\n\
+                 print('ready', flush=True)\n\
+                 c, _ = s.accept(); c.sendall(c.makefile('rb').readline())\n",
+            )
+            .arg(&socket)
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+        let mut ready = [0; 6];
+        server
+            .stdout()
+            .as_mut()
+            .unwrap()
+            .read_exact(&mut ready)
+            .await
+            .unwrap();
+
+        let mut tunnel = session.open_direct_streamlocal(&socket).await.unwrap();
+        tunnel.write_all(b"hello\n").await.unwrap();
+        tunnel.shutdown().await.unwrap();
+        let mut echo = Vec::new();
+        tunnel.read_to_end(&mut echo).await.unwrap();
+        assert_eq!(echo, b"hello\n");
+        tunnel.wait().await.unwrap();
+        assert!(server.wait().await.unwrap().success());
+
+        let missing = format!("{}/missing.sock", dir.trim());
+        let mut tunnel = session.open_direct_streamlocal(&missing).await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(tunnel.read_to_end(&mut buf).await.unwrap(), 0);
+        let err = tunnel.wait().await.unwrap_err();
+        assert!(matches!(err, Error::Remote(_)), "{:?}", err);
+
+        session
+            .command("rm")
+            .args(["-rf", dir.trim()])
+            .status()
+            .await
+            .unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn pinned_host_key() {
+    // The key of the test server, added by `start_sshd.sh`.
+    let known_hosts = std::fs::read_to_string(get_known_hosts_path()).unwrap();
+    let host_keys: Vec<String> = known_hosts
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|key| !key.is_empty())
+        .collect();
+
+    let mut builder = SessionBuilder::default();
+    for key in &host_keys {
+        builder.pinned_host_key(key);
+    }
+    // The known hosts file of the tests is replaced by the pinned keys.
+    for session in session_builder_connect(builder, &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    // The user key is a valid key, but not the one of the host.
+    let user_key = std::fs::read_to_string(".test-key.pub").unwrap();
+    let mut builder = SessionBuilder::default();
+    builder.pinned_host_key(user_key.trim());
+
+    #[cfg(feature = "process-mux")]
+    builder.connect(&addr()).await.unwrap_err();
+
+    #[cfg(feature = "native-mux")]
+    builder.connect_mux(&addr()).await.unwrap_err();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn remote_executor() {
+    use openssh::{FileTransfer, RemoteExecutor};
+
+    for session in connects().await {
+        let executor: &dyn RemoteExecutor = &session;
+        executor.check().await.unwrap();
+        let output = executor.output("echo", &["a b"]).await.unwrap();
+        assert_eq!(output.stdout, b"a b\n");
+        let output = executor.shell_output("echo $((1 + 1))").await.unwrap();
+        assert_eq!(output.stdout, b"2\n");
+
+        let file_ops = session.file_ops();
+        let transports: [&dyn FileTransfer; 2] = [&session, &file_ops];
+        for transport in transports {
+            let dir = tempdir().unwrap();
+            let local = dir.path().join("local");
+            std::fs::write(&local, b"upload").unwrap();
+
+            transport
+                .upload(&local, "/tmp/openssh-rs-transfer")
+                .await
+                .unwrap();
+            assert_eq!(
+                transport.read("/tmp/openssh-rs-transfer").await.unwrap(),
+                b"upload"
+            );
+            transport
+                .write("/tmp/openssh-rs-transfer", b"download")
+                .await
+                .unwrap();
+            transport
+                .download("/tmp/openssh-rs-transfer", &local)
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read(&local).unwrap(), b"download");
+        }
+        executor
+            .output("rm", &["/tmp/openssh-rs-transfer"])
+            .await
+            .unwrap();
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn metrics_sink() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Counters {
+        spawned: AtomicU64,
+        exited: AtomicU64,
+        stdin: AtomicU64,
+        stdout: AtomicU64,
+    }
+
+    impl MetricsSink for Counters {
+        fn command_spawned(&self) {
+            self.spawned.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn command_exited(&self, _elapsed: Duration) {
+            self.exited.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn stdin_bytes(&self, n: u64) {
+            self.stdin.fetch_add(n, Ordering::Relaxed);
+        }
+
+        fn stdout_bytes(&self, n: u64) {
+            self.stdout.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    for mut session in connects().await {
+        let counters = Arc::new(Counters::default());
+        session.set_metrics_sink(Some(counters.clone()));
+
+        let mut child = session
+            .command("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+        child
+            .stdin()
+            .take()
+            .unwrap()
+            .write_all(b"hello")
+            .await
+            .unwrap();
+        let output = child.wait_with_output().await.unwrap();
+        assert_eq!(output.stdout, b"hello");
+
+        assert_eq!(counters.spawned.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.exited.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.stdin.load(Ordering::Relaxed), 5);
+        assert_eq!(counters.stdout.load(Ordering::Relaxed), 5);
+
+        session.set_metrics_sink(None);
+        session.command("true").status().await.unwrap();
+        assert_eq!(counters.spawned.load(Ordering::Relaxed), 1);
+
+        session.close().await.unwrap();
+    }
+}